@@ -9,9 +9,11 @@
 //!
 //! A client requesting time from a server on localhost:2345.
 //! It will inovoke the "now" method, which will return the current
-//! unix timestamp (number of seconds since 1.1. 1970).
+//! unix timestamp (number of seconds since 1.1. 1970). Then it subscribes
+//! to the time updates, waits for three of them and unsubscribes.
 
 extern crate futures;
+#[macro_use]
 extern crate serde_json;
 #[macro_use]
 extern crate slog;
@@ -21,9 +23,10 @@ extern crate tokio_core;
 extern crate tokio_io;
 extern crate tokio_jsonrpc;
 
+use std::io::{Error as IoError, ErrorKind};
 use std::time::Duration;
 
-use futures::Future;
+use futures::{Future, Stream};
 use tokio_core::reactor::Core;
 use tokio_core::net::TcpStream;
 use tokio_io::AsyncRead;
@@ -31,6 +34,7 @@ use slog::{Drain, Logger};
 
 use tokio_jsonrpc::{Endpoint, LineCodec};
 use tokio_jsonrpc::message::Response;
+use tokio_jsonrpc::pubsub::Subscriber;
 
 fn main() {
     // An application logger
@@ -48,32 +52,63 @@ fn main() {
     let socket = TcpStream::connect(&"127.0.0.1:2345".parse().unwrap(), &handle);
 
     let client = socket.and_then(|socket| {
+        // The server sends the updates as the "time" notification. The subscriber receives them.
+        let subscriber = Subscriber::new().notification_method("time".to_owned());
         // Create a client endpoint
-        let (client, _) = Endpoint::client_only(socket.framed(LineCodec::new()))
+        let (client, _) = Endpoint::new(socket.framed(LineCodec::new()), subscriber.clone())
             .logger(logger.new(o!("client" => 1)))
             .start(&handle);
 
         info!(logger, "Calling rpc");
+        let logger_cloned = logger.clone();
         client
             .call("now".to_owned(), None, Some(Duration::from_secs(5)))
-            .and_then(|(_client, response)| response)
-            .map(|x| match x {
-                // Received an error from the server,
-                Some(Response {
-                    result: Ok(result), ..
-                }) => {
-                    let r: u64 = serde_json::from_value(result).unwrap();
-                    info!(logger, "received response"; "result" => format!("{:?}", r));
-                },
-                // Received an error from the server,
-                Some(Response {
-                    result: Err(err), ..
-                }) => {
-                    info!(logger, "remote error"; "error" => format!("{:?}", err));
-                },
-                // Timeout
-                None => info!(logger, "timeout"),
+            .and_then(|(client, response)| response.map(|response| (client, response)))
+            .map(move |(client, x)| {
+                match x {
+                    // Received an error from the server,
+                    Some(Response {
+                        result: Ok(result), ..
+                    }) => {
+                        let r: u64 = serde_json::from_value(result).unwrap();
+                        info!(logger_cloned, "received response"; "result" => format!("{:?}", r));
+                    },
+                    // Received an error from the server,
+                    Some(Response {
+                        result: Err(err), ..
+                    }) => {
+                        info!(logger_cloned, "remote error"; "error" => format!("{:?}", err));
+                    },
+                    // Timeout
+                    None => info!(logger_cloned, "timeout"),
+                }
+                client
+            })
+            .and_then(move |client| {
+                info!(logger, "Subscribing");
+                subscriber
+                    .subscribe(client, "subscribe".to_owned(), Some(json!({"secs": 1})))
+                    .and_then(move |(client, subscription)| {
+                        let subscription =
+                            subscription.map_err(|e| IoError::new(ErrorKind::Other, e.message))?;
+                        Ok((client, subscription, logger))
+                    })
             })
+            .and_then(|(client, subscription, logger)| {
+                // Ask for three updates. Then stop them.
+                let id = subscription.id().to_owned();
+                subscription
+                    .take(3)
+                    .for_each(move |update| {
+                        info!(logger, "time update"; "time" => format!("{}", update));
+                        Ok(())
+                    })
+                    .map_err(|()| IoError::new(ErrorKind::Other, "Lost subscription"))
+                    .and_then(move |()| {
+                        client.call("unsubscribe".to_owned(), Some(json!([id])), None)
+                    })
+            })
+            .and_then(|(_client, response)| response)
     });
 
     // Run the whole thing
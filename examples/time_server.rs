@@ -9,12 +9,11 @@
 //!
 //! A server listening on localhost:2345. It reponds to the „now“ method, returning the current
 //! unix timestamp (number of seconds since 1.1. 1970). You can also subscribe to periodic time
-//! updates.
+//! updates and unsubscribe from them again.
 
 extern crate futures;
 #[macro_use]
 extern crate serde_derive;
-#[macro_use]
 extern crate serde_json;
 #[macro_use]
 extern crate slog;
@@ -36,6 +35,8 @@ use slog::{Drain, Logger};
 use slog_term::{FullFormat, PlainSyncDecorator};
 
 use tokio_jsonrpc::{Endpoint, LineCodec, RpcError, Server, ServerCtl};
+use tokio_jsonrpc::pubsub::Publisher;
+use tokio_jsonrpc::server::{AbstractServer, ServerChain};
 
 /// A helper struct to deserialize the parameters
 #[derive(Deserialize)]
@@ -55,58 +56,24 @@ fn now() -> u64 {
 
 /// The server implementation
 ///
-/// Future versions of the library will have some helpers to create them in an easier way.
-struct TimeServer(Handle, Logger);
+/// It provides only the current time, the subscriptions are handled by a `Publisher`.
+struct TimeServer(Logger);
 
 impl Server for TimeServer {
     /// The return type of RPC
-    ///
-    /// As we have two different RPCs with different results, we use the generic Value.
-    type Success = Value;
-    type RpcCallResult = Result<Value, RpcError>;
+    type Success = u64;
+    type RpcCallResult = Result<u64, RpcError>;
     /// Just a formality, we don't need this one
     type NotificationResult = Result<(), ()>;
     /// The actual implementation of the RPC methods
     fn rpc(
-        &self, ctl: &ServerCtl, method: &str, params: &Option<Value>
+        &self, _ctl: &ServerCtl, method: &str, _params: &Option<Value>
     ) -> Option<Self::RpcCallResult> {
         match method {
             // Return the number of seconds since epoch (eg. unix timestamp)
             "now" => {
-                debug!(self.1, "Providing time");
-                Some(Ok(Value::Number(now().into())))
-            },
-            // Subscribe to receiving updates of time (we don't do unsubscription)
-            "subscribe" => {
-                debug!(self.1, "Subscribing");
-                // Some parsing and bailing out on errors
-                let (s_params,) = jsonrpc_params!(params, "s_params" => SubscribeParams);
-                // We need to have a client to be able to send notifications
-                let client = ctl.client().unwrap();
-                let handle = self.0.clone();
-                let logger = self.1.clone();
-                // Get a stream that „ticks“
-                let result = Interval::new(Duration::new(s_params.secs, s_params.nsecs), &self.0)
-                    .or_else(|e| Err(RpcError::server_error(Some(format!("Interval: {}", e)))))
-                    .map(move |interval| {
-                        let logger_cloned = logger.clone();
-                        // And send the notification on each tick (and pass the client through)
-                        let notified = interval.fold(client, move |client, _| {
-                                debug!(logger_cloned, "Tick");
-                                client.notify("time".to_owned(), Some(json!([now()])))
-                            })
-                            // So it can be spawned, spawn needs ().
-                            .map(|_| ())
-                            // TODO: This reports a „Shouldn't happen“ error ‒ do something
-                            // about that
-                            .map_err(move |e| {
-                                         error!(logger, "Error notifying about a time: {}", e);
-                                     });
-                        handle.spawn(notified);
-                        // We need some result, but we don't send any meaningful value
-                        Value::Null
-                    });
-                Some(result)
+                debug!(self.0, "Providing time");
+                Some(Ok(now()))
             },
             // Method not known
             _ => None,
@@ -114,6 +81,30 @@ impl Server for TimeServer {
     }
 }
 
+/// Creates the publisher of the periodic time updates
+///
+/// Calling the `subscribe` method (with the period as parameter) starts sending the `time`
+/// notifications. The `unsubscribe` method stops them.
+fn publisher(handle: Handle, logger: Logger) -> Publisher {
+    Publisher::new(handle.clone())
+        .notification_method("time".to_owned())
+        .topic("subscribe", move |_ctl, params| {
+            debug!(logger, "Subscribing");
+            // Some parsing and bailing out on errors
+            let (s_params,) = jsonrpc_params!(params, wrap "s_params" => SubscribeParams)?;
+            let logger = logger.clone();
+            // Get a stream that „ticks“ and turn each tick into the current time
+            Interval::new(Duration::new(s_params.secs, s_params.nsecs), &handle)
+                .map(move |interval| {
+                    interval.map(move |_| {
+                        debug!(logger, "Tick");
+                        now()
+                    })
+                })
+                .map_err(|e| RpcError::server_error(Some(format!("Interval: {}", e))))
+        })
+}
+
 fn main() {
     // An application logger
     let plain = PlainSyncDecorator::new(io::stdout());
@@ -128,16 +119,14 @@ fn main() {
     let service = listener.incoming().for_each(move |(connection, addr)| {
         let addr = format!("{}", addr);
         // Once a connection is made, create an endpoint on it, using the above server
-        let (_client, finished) =
-            Endpoint::new(
-                connection.framed(LineCodec::new()),
-                TimeServer(
-                    handle.clone(),
-                    logger.new(o!("cli" => addr.clone(),
-                                                                             "context" => "time")),
-                ),
-            ).logger(logger.new(o!("cli" => addr.clone(), "context" => "json RPC")))
-                .start(&handle);
+        let server_logger = logger.new(o!("cli" => addr.clone(), "context" => "time"));
+        let server = ServerChain::new(vec![
+            Box::new(AbstractServer::new(TimeServer(server_logger.clone()))),
+            Box::new(AbstractServer::new(publisher(handle.clone(), server_logger))),
+        ]);
+        let (_client, finished) = Endpoint::new(connection.framed(LineCodec::new()), server)
+            .logger(logger.new(o!("cli" => addr.clone(), "context" => "json RPC")))
+            .start(&handle);
         // If it finishes with an error, report it
         let logger = logger.clone();
         let err_report =
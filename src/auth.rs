@@ -0,0 +1,144 @@
+// Copyright 2017 tokio-jsonrpc Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Authentication of connections.
+//!
+//! An [`AuthGate`](struct.AuthGate.html), set by
+//! [`Endpoint::auth_gate`](../endpoint/struct.Endpoint.html#method.auth_gate), makes the
+//! connection authenticate before anything else may be called. Until the server marks the
+//! connection as authenticated by
+//! [`ServerCtl::authenticate`](../endpoint/struct.ServerCtl.html#method.authenticate), only the
+//! login method and the explicitly allowed methods are handled. Other RPCs are answered with the
+//! unauthorized error and other notifications are dropped. This applies to everything the
+//! endpoint handles, including the extensions, the introspection and the answers to the pings.
+//!
+//! The handler of the login method is expected to check the credentials and authenticate the
+//! connection. The later calls may then read the identity through
+//! [`ServerCtl::identity`](../endpoint/struct.ServerCtl.html#method.identity).
+//!
+//! As the authentication belongs to the connection, the calls are checked at the time they
+//! arrive. Calls in the same batch as the login may be refused.
+//!
+//! # Examples
+//!
+//! ```rust
+//! # extern crate serde_json;
+//! # extern crate tokio_core;
+//! # extern crate tokio_jsonrpc;
+//! # use serde_json::Value;
+//! # use tokio_core::reactor::Core;
+//! # use tokio_jsonrpc::{Endpoint, RpcError, Server, ServerCtl};
+//! # use tokio_jsonrpc::auth::AuthGate;
+//! # use tokio_jsonrpc::memory::pair;
+//! # fn main() {
+//! struct Real;
+//! impl Server for Real {
+//!     type Success = bool;
+//!     type RpcCallResult = Result<bool, RpcError>;
+//!     type NotificationResult = Result<(), ()>;
+//!     fn rpc(
+//!         &self, ctl: &ServerCtl, method: &str, params: &Option<Value>
+//!     ) -> Option<Self::RpcCallResult> {
+//!         match method {
+//!             // Trust anyone who claims a name
+//!             "login" => Some(match *params {
+//!                 Some(Value::Array(ref names)) if names.len() == 1 => {
+//!                     ctl.authenticate(names[0].clone());
+//!                     Ok(true)
+//!                 },
+//!                 _ => Err(RpcError::invalid_params(None)),
+//!             }),
+//!             "is_admin" => Some(Ok(ctl.identity() == Some(Value::from("admin")))),
+//!             _ => None,
+//!         }
+//!     }
+//! }
+//!
+//! let core = Core::new().unwrap();
+//! let (connection, _other) = pair();
+//! let (_client, _finished) = Endpoint::new(connection, Real)
+//!     .auth_gate(AuthGate::new("login").allow("version"))
+//!     .start(&core.handle());
+//! # }
+//! ```
+
+use std::collections::HashSet;
+
+use endpoint::ServerCtl;
+use message::RpcError;
+
+/// The error code the [`AuthGate`](struct.AuthGate.html) refuses the calls with by default.
+pub const UNAUTHORIZED: i64 = -32_001;
+
+/// Configuration of the calls allowed before authentication.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AuthGate {
+    login: String,
+    allowed: HashSet<String>,
+    unauthorized: RpcError,
+}
+
+impl AuthGate {
+    /// Creates the gate with the given login method.
+    ///
+    /// The refused RPCs are answered with the [`UNAUTHORIZED`](constant.UNAUTHORIZED.html) error
+    /// code by default.
+    pub fn new(login: &str) -> Self {
+        AuthGate {
+            login: login.to_owned(),
+            allowed: HashSet::new(),
+            unauthorized: RpcError::new(UNAUTHORIZED, "Unauthorized".to_owned(), None),
+        }
+    }
+    /// Allows a method to be called before authentication.
+    pub fn allow(mut self, method: &str) -> Self {
+        self.allowed.insert(method.to_owned());
+        self
+    }
+    /// Sets the error the refused RPCs are answered with.
+    pub fn unauthorized(self, error: RpcError) -> Self {
+        AuthGate {
+            unauthorized: error,
+            ..self
+        }
+    }
+    /// Checks if the method may be called now.
+    ///
+    /// Returns the error to refuse the call with.
+    pub(crate) fn check(&self, ctl: &ServerCtl, method: &str) -> Result<(), RpcError> {
+        if method == self.login || self.allowed.contains(method) || ctl.identity().is_some() {
+            Ok(())
+        } else {
+            Err(self.unauthorized.clone())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Only the login and the allowed methods pass before authentication.
+    #[test]
+    fn check() {
+        let gate = AuthGate::new("login").allow("version");
+        let (ctl, _, _) = ServerCtl::new_test();
+        assert_eq!(UNAUTHORIZED, gate.check(&ctl, "hello").unwrap_err().code);
+        gate.check(&ctl, "version").unwrap();
+        gate.check(&ctl, "login").unwrap();
+        assert!(ctl.identity().is_none());
+        // The login handler would do this
+        ctl.authenticate(json!("alice"));
+        gate.check(&ctl, "hello").unwrap();
+        assert_eq!(Some(json!("alice")), ctl.identity());
+        // A custom error
+        let forbidden = RpcError::new(-32_003, "Log in first".to_owned(), None);
+        let gate = AuthGate::new("login").unauthorized(forbidden.clone());
+        let (ctl, _, _) = ServerCtl::new_test();
+        assert_eq!(Err(forbidden), gate.check(&ctl, "hello"));
+    }
+}
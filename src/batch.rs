@@ -0,0 +1,82 @@
+// Copyright 2017 tokio-jsonrpc Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Handling of batches.
+//!
+//! The specification allows sending multiple requests and notifications in a single batch and
+//! leaves it to the server how it processes them ‒ the entries may run in any order and in
+//! parallel and the responses may be in any order too. By default, the endpoint runs as many
+//! entries of a batch at once as the
+//! [`Endpoint::parallel`](../endpoint/struct.Endpoint.html#method.parallel) limit allows and
+//! answers in the order they complete.
+//!
+//! A [`BatchPolicy`](struct.BatchPolicy.html), set by
+//! [`Endpoint::batch_policy`](../endpoint/struct.Endpoint.html#method.batch_policy), changes
+//! that. Note that a batch counts as a single message towards the `Endpoint::parallel` limit and
+//! its entries are limited separately, by the policy.
+
+/// Configuration of how batches are processed.
+#[derive(Copy, Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct BatchPolicy {
+    pub(crate) max_len: Option<usize>,
+    pub(crate) concurrency: Option<usize>,
+    pub(crate) ordered: bool,
+}
+
+impl BatchPolicy {
+    /// Creates the default policy.
+    ///
+    /// Batches of any length are accepted, their entries run with the same parallelism as the
+    /// whole endpoint (see
+    /// [`Endpoint::parallel`](../endpoint/struct.Endpoint.html#method.parallel)) and the
+    /// responses are in the order of completion.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Limits the number of entries in a batch.
+    ///
+    /// Longer batches are rejected as a whole with a single invalid request error, none of their
+    /// entries is processed.
+    pub fn max_len(self, max_len: usize) -> Self {
+        BatchPolicy {
+            max_len: Some(max_len),
+            ..self
+        }
+    }
+    /// Limits how many entries of a single batch may run at once.
+    ///
+    /// An entry is passed to the server only after a previous one completes, if there are already
+    /// this many running. This replaces the default of the endpoint's parallelism, it may be both
+    /// lower and higher.
+    ///
+    /// # Panics
+    ///
+    /// If `concurrency` is 0.
+    pub fn concurrency(self, concurrency: usize) -> Self {
+        assert!(concurrency > 0, "A batch needs to run at least one entry at a time");
+        BatchPolicy {
+            concurrency: Some(concurrency),
+            ..self
+        }
+    }
+    /// Runs the entries one after another.
+    ///
+    /// Each entry is passed to the server only after the previous one completes, so the entries
+    /// may depend on each other. This is the same as concurrency of 1 and the responses are in
+    /// the order of the requests.
+    pub fn sequential(self) -> Self {
+        self.concurrency(1)
+    }
+    /// Answers in the order of the requests.
+    ///
+    /// The responses in the answer are sorted by the position of their requests in the batch.
+    /// The answer is sent only once all the entries complete anyway, so this doesn't delay
+    /// anything and doesn't change how the entries run.
+    pub fn ordered(self, ordered: bool) -> Self {
+        BatchPolicy { ordered, ..self }
+    }
+}
@@ -12,16 +12,26 @@
 //! messages to be separated by newlines and not to contain newlines in their representation. On
 //! the other hand, it can recover from syntax error in a message and you can respond with an error
 //! instead of terminating the connection.
+//!
+//! The [RawLine](struct.RawLine.html) codec is a variant of the line separated one that doesn't
+//! parse the parameters of the incoming calls.
+//!
+//! By default, the codecs accept some messages the specification forbids, but that are still
+//! unambiguous. The line separated ones can be switched into the strict mode by their `strict`
+//! method, the boundary separated one has the [StrictBoundary](struct.StrictBoundary.html)
+//! variant. See [`message::strict`](../message/fn.strict.html) for what is checked.
 
-use std::io::{Error, ErrorKind, Result as IoResult};
+use std::io::{Error, ErrorKind, Result as IoResult, Write};
 
 use tokio_io::codec::{Decoder, Encoder};
 use bytes::{BufMut, BytesMut};
 use serde_json::de::Deserializer;
-use serde_json::ser::to_vec;
+use serde_json::ser::to_writer;
 use serde_json::error::Error as SerdeError;
+use serde_json::Value;
 
-use message::{decoded_to_parsed, from_slice, from_str, Message, Parsed};
+use message::{classify_strict, decoded_to_parsed, from_slice, from_slice_raw,
+              from_slice_raw_strict, from_slice_strict, from_str, Broken, Message, Parsed};
 
 /// A helper to wrap the error
 fn err_map(e: SerdeError) -> Error {
@@ -33,12 +43,31 @@ trait PositionCache {
     fn position(&mut self) -> &mut usize;
 }
 
+/// Appends whatever is written to the buffer, growing it as needed
+struct Append<'a>(&'a mut BytesMut);
+
+impl<'a> Write for Append<'a> {
+    fn write(&mut self, data: &[u8]) -> IoResult<usize> {
+        self.0.extend_from_slice(data);
+        Ok(data.len())
+    }
+    fn flush(&mut self) -> IoResult<()> {
+        Ok(())
+    }
+}
+
 /// An encoding function reused by [`Line`], [`DirtyLine`] and [`Boundary`]
+///
+/// The message is serialized straight into the buffer.
 fn encode_codec(msg: &Message, buf: &mut BytesMut) -> IoResult<()> {
-    let encoded = to_vec(&msg).map_err(err_map)?;
     // As discovered the hard way, we must not overwrite buf, but append to it.
-    buf.reserve(encoded.len() + 1);
-    buf.put_slice(&encoded);
+    let start = buf.len();
+    if let Err(e) = to_writer(Append(buf), msg) {
+        // Don't leave half a message behind
+        buf.truncate(start);
+        return Err(err_map(e));
+    }
+    buf.reserve(1);
     buf.put(b'\n');
     Ok(())
 }
@@ -74,12 +103,18 @@ where
 /// Note that the produced items is a `Result`, to allow not terminating the stream on
 /// protocol-level errors.
 #[derive(Debug, Default)]
-pub struct Line(usize);
+pub struct Line(usize, bool);
 
 impl Line {
     /// A constructor
     pub fn new() -> Self {
-        Line(0)
+        Line(0, false)
+    }
+    /// Sets the strict mode.
+    ///
+    /// In the strict mode, the messages violating the specification are decoded as broken.
+    pub fn strict(self, strict: bool) -> Self {
+        Line(self.0, strict)
     }
 }
 
@@ -101,7 +136,8 @@ impl Decoder for Line {
     type Item = Parsed;
     type Error = Error;
     fn decode(&mut self, src: &mut BytesMut) -> IoResult<Option<Parsed>> {
-        decode_codec(self, src, from_slice)
+        let parse = if self.1 { from_slice_strict } else { from_slice };
+        decode_codec(self, src, parse)
     }
 }
 
@@ -115,12 +151,18 @@ impl Decoder for Line {
 ///
 /// In contrast, Line errors on such invalid inputs. Encoding is the same for both codecs, however.
 #[derive(Debug, Default)]
-pub struct DirtyLine(usize);
+pub struct DirtyLine(usize, bool);
 
 impl DirtyLine {
     /// A constructor
     pub fn new() -> Self {
-        DirtyLine(0)
+        DirtyLine(0, false)
+    }
+    /// Sets the strict mode.
+    ///
+    /// In the strict mode, the messages violating the specification are decoded as broken.
+    pub fn strict(self, strict: bool) -> Self {
+        DirtyLine(self.0, strict)
     }
 }
 
@@ -134,8 +176,14 @@ impl Decoder for DirtyLine {
     type Item = Parsed;
     type Error = Error;
     fn decode(&mut self, src: &mut BytesMut) -> IoResult<Option<Parsed>> {
+        let strict = self.1;
         decode_codec(self, src, |bytes| {
-            from_str(String::from_utf8_lossy(bytes).as_ref())
+            let lossy = String::from_utf8_lossy(bytes);
+            if strict {
+                from_slice_strict(lossy.as_bytes())
+            } else {
+                from_str(lossy.as_ref())
+            }
         })
     }
 }
@@ -148,6 +196,59 @@ impl Encoder for DirtyLine {
     }
 }
 
+/// A codec working with JSONRPC 2.0 messages, keeping the parameters raw.
+///
+/// This works like the [Line](struct.Line.html) codec. However, the requests and notifications
+/// are decoded with [`from_slice_raw`](../message/fn.from_slice_raw.html), so their parameters
+/// aren't parsed into a `Value`. The endpoint then passes them to
+/// [`Server::rpc_raw`](../server/trait.Server.html#method.rpc_raw) and
+/// [`Server::notification_raw`](../server/trait.Server.html#method.notification_raw). This saves
+/// the work with large parameters, if the server deserializes them directly.
+///
+/// Encoding is the same as with `Line`.
+#[derive(Debug, Default)]
+pub struct RawLine(usize, bool);
+
+impl RawLine {
+    /// A constructor
+    pub fn new() -> Self {
+        RawLine(0, false)
+    }
+    /// Sets the strict mode.
+    ///
+    /// In the strict mode, the messages violating the specification are decoded as broken.
+    pub fn strict(self, strict: bool) -> Self {
+        RawLine(self.0, strict)
+    }
+}
+
+impl PositionCache for RawLine {
+    fn position(&mut self) -> &mut usize {
+        &mut self.0
+    }
+}
+
+impl Decoder for RawLine {
+    type Item = Parsed;
+    type Error = Error;
+    fn decode(&mut self, src: &mut BytesMut) -> IoResult<Option<Parsed>> {
+        let parse = if self.1 {
+            from_slice_raw_strict
+        } else {
+            from_slice_raw
+        };
+        decode_codec(self, src, parse)
+    }
+}
+
+impl Encoder for RawLine {
+    type Item = Message;
+    type Error = Error;
+    fn encode(&mut self, msg: Message, buf: &mut BytesMut) -> IoResult<()> {
+        encode_codec(&msg, buf)
+    }
+}
+
 /// A codec working with JSONRPC 2.0 messages.
 ///
 /// This produces or encodes [Message](../message/enum.Message.html). It takes the JSON object
@@ -182,6 +283,38 @@ impl Decoder for Boundary {
     }
 }
 
+/// The [Boundary](struct.Boundary.html) codec in the strict mode.
+///
+/// The messages violating the specification are decoded as broken.
+pub struct StrictBoundary;
+
+impl Encoder for StrictBoundary {
+    type Item = Message;
+    type Error = Error;
+    fn encode(&mut self, msg: Message, buf: &mut BytesMut) -> IoResult<()> {
+        encode_codec(&msg, buf)
+    }
+}
+
+impl Decoder for StrictBoundary {
+    type Item = Parsed;
+    type Error = Error;
+    fn decode(&mut self, src: &mut BytesMut) -> IoResult<Option<Parsed>> {
+        let (decoded, pos) = {
+            let mut deserializer = Deserializer::from_slice(src).into_iter::<Value>();
+            let decoded = deserializer.next().and_then(|result| match result {
+                Err(ref e) if e.is_eof() => None,
+                Err(e) => Some(Err(Broken::SyntaxError(format!("{}", e)))),
+                Ok(value) => Some(classify_strict(value)),
+            });
+            (decoded, deserializer.byte_offset())
+        };
+
+        src.split_to(pos);
+        Ok(decoded)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -268,6 +401,38 @@ mod tests {
         );
     }
 
+    /// All the codecs have the strict mode, the lenient one stays the default
+    #[test]
+    fn decode_strict() {
+        fn decode<C: Decoder<Item = Parsed, Error = Error>>(mut codec: C, input: &[u8]) -> Parsed {
+            codec.decode(&mut get_buf(input)).unwrap().unwrap()
+        }
+
+        let input = b"{\"jsonrpc\":\"2.0\",\"method\":\"m\",\"params\":null}\n";
+        assert!(decode(Line::new(), input).is_ok());
+        assert!(decode(DirtyLine::new(), input).is_ok());
+        assert!(decode(RawLine::new(), input).is_ok());
+        assert!(decode(Boundary, input).is_ok());
+        let strict = vec![
+            decode(Line::new().strict(true), input),
+            decode(DirtyLine::new().strict(true), input),
+            decode(RawLine::new().strict(true), input),
+            decode(StrictBoundary, input),
+        ];
+        for parsed in strict {
+            match parsed {
+                Err(Broken::Unmatched(_, ref reason)) => {
+                    assert_eq!("params is not an array or object", reason)
+                },
+                other => panic!("Something unexpected: {:?}", other),
+            }
+        }
+        match decode(StrictBoundary, b"{]") {
+            Err(Broken::SyntaxError(_)) => (),
+            other => panic!("Something unexpected: {:?}", other),
+        }
+    }
+
     /// Not enough data for a whole message
     #[test]
     fn decode_boundary_short() {
@@ -14,27 +14,39 @@
 //! simply don't call any RPCs or notifications and forget about the returned
 //! [`Client`](struct.Client.html) structure.
 
+use std::borrow::Cow;
 use std::error::Error;
 use std::fmt::{Display, Formatter, Result as FmtResult};
 use std::io::{self, Error as IoError, ErrorKind};
 use std::collections::HashMap;
 use std::rc::Rc;
-use std::time::Duration;
-use std::cell::RefCell;
+use std::time::{Duration, Instant};
+use std::cell::{Cell, RefCell};
 
-use futures::{Future, IntoFuture, Sink, Stream};
+use futures::{Future, IntoFuture, Poll, Sink, Stream};
 use futures::future::Either;
 use futures::stream::{self, empty, unfold, Once};
-use futures::unsync::mpsc::{channel, Sender};
+use futures::unsync::mpsc::{channel, unbounded, Sender, UnboundedReceiver, UnboundedSender};
 use futures::unsync::oneshot::{channel as one_channel, Sender as OneSender};
 #[cfg(test)]
 use futures::unsync::oneshot::Receiver as OneReceiver;
-use serde_json::{to_value, Value};
+use serde::Serialize;
+use serde_json::Value;
 use slog::{Discard, Logger};
 use tokio_core::reactor::{Handle, Timeout};
 
-use message::{Broken, Message, Notification, Parsed, Request, Response, RpcError};
-use server::{Empty as EmptyServer, Server};
+use auth::AuthGate;
+use batch::BatchPolicy;
+use message::{accept_v1, is_reserved, strict, Broken, Message, Notification, Parsed, RawJson,
+              RawParams, Request, Response, RpcError, Version, PARSE_ERROR};
+use introspection::{Introspection, DISCOVER_METHOD};
+use keepalive::{Keepalive, Pinger, PING_METHOD};
+use limit::{Admission, Limiter, Limits};
+use metrics::{Metrics, NoMetrics};
+use rate::{RateLimiter, RateLimits};
+use server::{BoxNotificationResult, BoxRpcCallResult, BoxServer, Empty as EmptyServer,
+             NotificationForwarder, NotificationStream, Server};
+use trace::{in_span, instrument, Connection as TraceConnection};
 
 /// Thing that terminates the connection once dropped.
 ///
@@ -58,13 +70,21 @@ struct ServerCtlInternal {
     stop: bool,
     // Terminate the nice way (if all others also drop)
     terminator: Option<RcDrop>,
-    // Terminate right now
-    killer: Option<OneSender<()>>,
+    // Terminate right now (with an error to report, possibly)
+    killer: Option<OneSender<Option<IoError>>>,
     // Info to be able to create a new clients
     idmap: IDMap,
     handle: Handle,
     sender: Option<Sender<Message>>,
     logger: Logger,
+    metrics: Rc<Metrics>,
+    tracing: TraceConnection,
+    // Notified once the server terminates
+    watchers: Vec<OneSender<()>>,
+    // Get the responses nobody waits for (None once the endpoint is finished)
+    unsolicited: Option<Vec<UnboundedSender<Response>>>,
+    // Who the other side authenticated as
+    identity: Option<Value>,
 }
 
 /// An error indicator when a connection has been already terminated.
@@ -100,6 +120,10 @@ impl ServerCtl {
         debug!(internal.logger, "Server cleanup");
         internal.stop = true;
         internal.sender.take();
+        for watcher in internal.watchers.drain(..) {
+            // If nobody listens, nobody cares
+            let _ = watcher.send(());
+        }
         f(&mut internal)
     }
     /// Stop answering RPCs and calling notifications.
@@ -116,11 +140,32 @@ impl ServerCtl {
     ///
     /// Like, right now. Without a goodbye.
     pub fn kill(&self) {
+        self.kill_with(None);
+    }
+    /// Kill the connection and report the error from the endpoint's finished future.
+    fn kill_with(&self, error: Option<IoError>) {
         self.cleanup(|internal| {
             // The option might be None, but only after we called it already.
-            internal.killer.take().map(|s| s.send(()));
+            internal.killer.take().map(|s| s.send(error));
         });
     }
+    /// A future that resolves once the server terminates.
+    ///
+    /// This happens when it is terminated or killed through the `ServerCtl` or when the connection
+    /// ends. If it is already terminated, the future resolves right away.
+    ///
+    /// It allows stopping activities (like periodic notifications) that would otherwise keep the
+    /// connection alive.
+    pub fn terminated(&self) -> BoxFuture<(), ()> {
+        let mut internal = self.0.borrow_mut();
+        if internal.stop {
+            return Box::new(Ok(()).into_future());
+        }
+        let (sender, receiver) = one_channel();
+        internal.watchers.push(sender);
+        // If the internal is dropped without terminating, there's no server anymore either
+        Box::new(receiver.then(|_| Ok(())))
+    }
     /// Create a new client for the current endpoint.
     ///
     /// This is a way in which the server may access the other endpoint (eg. call RPCs or send
@@ -136,14 +181,23 @@ impl ServerCtl {
         let internal = self.0.borrow();
         let terminator = internal.terminator.as_ref().ok_or(AlreadyTerminated)?;
         let sender = internal.sender.as_ref().ok_or(AlreadyTerminated)?;
-        Ok(Client::new(
-            &internal.idmap,
-            self,
-            &internal.handle,
-            terminator,
-            sender,
-            internal.logger.clone(),
-        ))
+        Ok(Client::new(self, &internal, terminator, sender))
+    }
+    /// Marks the connection as authenticated.
+    ///
+    /// The identity is whatever the server wants to remember about who is on the other side (eg.
+    /// the user name). It stays with the connection and the later calls may read it through
+    /// [`identity`](#method.identity). It also opens the
+    /// [`AuthGate`](../auth/struct.AuthGate.html). Authenticating again replaces the
+    /// identity.
+    pub fn authenticate(&self, identity: Value) {
+        let mut internal = self.0.borrow_mut();
+        debug!(internal.logger, "Authenticated"; "identity" => format!("{}", identity));
+        internal.identity = Some(identity);
+    }
+    /// The identity the connection authenticated as, if any.
+    pub fn identity(&self) -> Option<Value> {
+        self.0.borrow().identity.clone()
     }
     // This one is for unit tests, not part of the general-purpose API. It creates a dummy
     // ServerCtl that does nothing, but still can be passed to the Server for checking.
@@ -154,7 +208,7 @@ impl ServerCtl {
     // * Kill future (fires when kill is signalled)
     #[doc(hidden)]
     #[cfg(test)]
-    pub fn new_test() -> (Self, OneReceiver<()>, OneReceiver<()>) {
+    pub fn new_test() -> (Self, OneReceiver<()>, OneReceiver<Option<IoError>>) {
         let (drop_sender, drop_receiver) = one_channel();
         let (kill_sender, kill_receiver) = one_channel();
         let (msg_sender, _msg_receiver) = channel(1);
@@ -170,6 +224,11 @@ impl ServerCtl {
             handle: handle,
             sender: Some(msg_sender),
             logger: Logger::root(Discard, o!()),
+            metrics: Rc::new(NoMetrics),
+            tracing: TraceConnection::new(),
+            watchers: Vec::new(),
+            unsolicited: Some(Vec::new()),
+            identity: None,
         })));
         (ctl, drop_receiver, kill_receiver)
     }
@@ -193,35 +252,247 @@ fn shouldnt_happen<E>(_: E) -> IoError {
     IoError::new(ErrorKind::Other, "Shouldn't happen")
 }
 
-fn do_request<RpcServer: Server + 'static>(
-    server: &RpcServer, ctl: &ServerCtl, request: Request, logger: &Logger
+/// A registered extension.
+///
+/// It is shared, so the endpoint builder can be cloned.
+type Extension = Rc<
+    Server<
+        Success = RawJson,
+        RpcCallResult = BoxRpcCallResult,
+        NotificationResult = BoxNotificationResult,
+    >,
+>;
+
+/// Everything the server half needs to process the incoming messages.
+struct Context<RpcServer> {
+    server: RpcServer,
+    ctl: ServerCtl,
+    idmap: IDMap,
+    logger: Logger,
+    metrics: Rc<Metrics>,
+    tracing: TraceConnection,
+    introspection: Option<Introspection>,
+    extensions: Vec<Extension>,
+    reserved_names: bool,
+    fail_on_parse_error: bool,
+    version: Version,
+    // Answer the pings of the other side
+    answer_pings: bool,
+    parallel: usize,
+    batch_policy: BatchPolicy,
+    limiter: Option<Limiter>,
+    rate_limiter: Option<RateLimiter>,
+    auth_gate: Option<AuthGate>,
+}
+
+impl<RpcServer: Server> Context<RpcServer> {
+    /// Passes an RPC to the built-in and registered extensions.
+    ///
+    /// The built-in ping answer comes last, after the server (see
+    /// [`ping_rpc`](#method.ping_rpc)).
+    fn extension_rpc(&self, method: &str, params: &Option<Value>) -> Option<BoxRpcCallResult> {
+        match self.introspection {
+            Some(ref introspection) if method == DISCOVER_METHOD => {
+                let document = introspection.document(&self.server.methods());
+                let document = RawJson::serialize(&document).expect("Documents are always JSON");
+                return Some(Box::new(Ok(document).into_future()));
+            },
+            _ => (),
+        }
+        self.extensions
+            .iter()
+            .filter_map(|ext| ext.rpc(&self.ctl, method, params))
+            .next()
+    }
+    /// Passes a notification to the registered extensions.
+    fn extension_notification(
+        &self, method: &str, params: &Option<Value>
+    ) -> Option<BoxNotificationResult> {
+        self.extensions
+            .iter()
+            .filter_map(|ext| ext.notification(&self.ctl, method, params))
+            .next()
+    }
+    /// Checks the call passes the authentication gate, if there's one.
+    fn authorized(&self, method: &str) -> Result<(), RpcError> {
+        match self.auth_gate {
+            Some(ref gate) => gate.check(&self.ctl, method),
+            None => Ok(()),
+        }
+    }
+    /// Answers a ping RPC nobody else answered, if the keepalive is on.
+    fn ping_rpc(&self, method: &str) -> Option<BoxRpcCallResult> {
+        if self.answer_pings && method == PING_METHOD {
+            let pong = RawJson::serialize(&true).expect("A bool is always JSON");
+            Some(Box::new(Ok(pong).into_future()))
+        } else {
+            None
+        }
+    }
+    /// Accepts a ping notification nobody else accepted, if the keepalive is on.
+    fn ping_notification(&self, method: &str) -> Option<BoxNotificationResult> {
+        if self.answer_pings && method == PING_METHOD {
+            Some(Box::new(Ok(()).into_future()))
+        } else {
+            None
+        }
+    }
+}
+
+/// Sets the version of a call made by this endpoint.
+///
+/// The answers of the other side's calls are left alone, they keep the version of their request.
+fn own_version(msg: Message, version: Version) -> Message {
+    match msg {
+        Message::Request(_) | Message::Notification(_) | Message::Batch(_) => {
+            msg.with_version(version)
+        },
+        other => other,
+    }
+}
+
+/// The parameters of the incoming calls, either parsed or raw.
+///
+/// This picks the right callbacks of the server.
+trait CallParams: Sized {
+    fn rpc<RpcServer: Server>(
+        server: &RpcServer, ctl: &ServerCtl, method: &str, params: &Option<Self>
+    ) -> Option<RpcServer::RpcCallResult>;
+    fn notification<RpcServer: Server>(
+        server: &RpcServer, ctl: &ServerCtl, method: &str, params: &Option<Self>
+    ) -> Option<RpcServer::NotificationResult>;
+    /// The params as a `Value`, for the extensions.
+    fn parsed(params: &Option<Self>) -> Cow<'_, Option<Value>>;
+}
+
+impl CallParams for Value {
+    fn rpc<RpcServer: Server>(
+        server: &RpcServer, ctl: &ServerCtl, method: &str, params: &Option<Value>
+    ) -> Option<RpcServer::RpcCallResult> {
+        server.rpc(ctl, method, params)
+    }
+    fn notification<RpcServer: Server>(
+        server: &RpcServer, ctl: &ServerCtl, method: &str, params: &Option<Value>
+    ) -> Option<RpcServer::NotificationResult> {
+        server.notification(ctl, method, params)
+    }
+    fn parsed(params: &Option<Value>) -> Cow<'_, Option<Value>> {
+        Cow::Borrowed(params)
+    }
+}
+
+impl CallParams for RawParams {
+    fn rpc<RpcServer: Server>(
+        server: &RpcServer, ctl: &ServerCtl, method: &str, params: &Option<RawParams>
+    ) -> Option<RpcServer::RpcCallResult> {
+        server.rpc_raw(ctl, method, params)
+    }
+    fn notification<RpcServer: Server>(
+        server: &RpcServer, ctl: &ServerCtl, method: &str, params: &Option<RawParams>
+    ) -> Option<RpcServer::NotificationResult> {
+        server.notification_raw(ctl, method, params)
+    }
+    fn parsed(params: &Option<RawParams>) -> Cow<'_, Option<Value>> {
+        Cow::Owned(params.as_ref().map(RawParams::to_value))
+    }
+}
+
+fn serialize_result<Success: Serialize>(result: Success) -> RawJson {
+    RawJson::serialize(&result).expect("Bad result type")
+}
+
+fn do_request<RpcServer: Server + 'static, Params: CallParams + 'static>(
+    ctx: &Context<RpcServer>, request: Request<Params>
 ) -> FutureMessage {
-    match server.rpc(ctl, &request.method, &request.params) {
+    ctx.metrics.request_received(&request.method);
+    let start = Instant::now();
+    let span = ctx.tracing.request(&request.method, &request.id);
+    let rpc = in_span(&span, || {
+        // Nothing at all before authentication, not even the extensions
+        if let Err(error) = ctx.authorized(&request.method) {
+            debug!(ctx.logger, "Unauthorized RPC {}", request.method);
+            let refused: BoxRpcCallResult = Box::new(Err(error).into_future());
+            return Some(Either::A(refused));
+        }
+        if is_reserved(&request.method) {
+            let params = Params::parsed(&request.params);
+            let extension = ctx.extension_rpc(&request.method, &params);
+            if extension.is_some() || ctx.reserved_names {
+                return extension
+                    .or_else(|| ctx.ping_rpc(&request.method))
+                    .map(Either::A);
+            }
+        }
+        // The result is serialized right away, without building a Value of it. The boxed results
+        // of the extensions (and the pings) already are.
+        Params::rpc(&ctx.server, &ctx.ctl, &request.method, &request.params)
+            .map(|result| Either::B(result.into_future().map(serialize_result)))
+            .or_else(|| ctx.ping_rpc(&request.method).map(Either::A))
+    });
+    match rpc {
         None => {
-            trace!(logger, "Server refused RPC {}", request.method);
-            let reply = request.error(RpcError::method_not_found(request.method.clone()));
+            trace!(ctx.logger, "Server refused RPC {}", request.method);
+            let error = RpcError::method_not_found(request.method.clone());
+            ctx.metrics
+                .request_answered(&request.method, Err(&error), start.elapsed());
+            let reply = request.error(error);
             Box::new(Ok(Some(reply)).into_future())
         },
         Some(future) => {
-            trace!(logger, "Server accepted RPC {}", request.method);
-            let result = future.into_future().then(move |result| match result {
-                Err(err) => Ok(Some(request.error(err))),
-                Ok(result) => Ok(Some(
-                    request.reply(to_value(result).expect("Bad result type")),
-                )),
+            trace!(ctx.logger, "Server accepted RPC {}", request.method);
+            let metrics = ctx.metrics.clone();
+            let result = instrument(span, future.into_future()).then(move |result| {
+                let latency = start.elapsed();
+                match result {
+                    Err(err) => {
+                        metrics.request_answered(&request.method, Err(&err), latency);
+                        Ok(Some(request.error(err)))
+                    },
+                    Ok(result) => {
+                        metrics.request_answered(&request.method, Ok(()), latency);
+                        Ok(Some(request.reply_raw(result)))
+                    },
+                }
             });
             Box::new(result)
         },
     }
 }
 
-fn do_notification<RpcServer: Server>(
-    server: &RpcServer, ctl: &ServerCtl, notification: &Notification, logger: &Logger
+fn do_notification<RpcServer: Server, Params: CallParams>(
+    ctx: &Context<RpcServer>, notification: &Notification<Params>
 ) -> FutureMessage {
-    match server.notification(ctl, &notification.method, &notification.params) {
+    ctx.metrics.notification_received(&notification.method);
+    let start = Instant::now();
+    let span = ctx.tracing.notification(&notification.method);
+    let handled = in_span(&span, || {
+        if ctx.authorized(&notification.method).is_err() {
+            debug!(ctx.logger, "Unauthorized notification {}", notification.method);
+            let refused: BoxNotificationResult = Box::new(Err(()).into_future());
+            return Some(Either::A(refused));
+        }
+        if is_reserved(&notification.method) {
+            let params = Params::parsed(&notification.params);
+            let extension = ctx.extension_notification(&notification.method, &params);
+            if extension.is_some() || ctx.reserved_names {
+                return extension
+                    .or_else(|| ctx.ping_notification(&notification.method))
+                    .map(Either::A);
+            }
+        }
+        Params::notification(
+            &ctx.server,
+            &ctx.ctl,
+            &notification.method,
+            &notification.params,
+        )
+            .map(|result| Either::B(result.into_future()))
+            .or_else(|| ctx.ping_notification(&notification.method).map(Either::A))
+    });
+    match handled {
         None => {
             trace!(
-                logger,
+                ctx.logger,
                 "Server refused notification {}",
                 notification.method
             );
@@ -230,118 +501,225 @@ fn do_notification<RpcServer: Server>(
         // We ignore both success and error, so we convert it into something for now
         Some(future) => {
             trace!(
-                logger,
+                ctx.logger,
                 "Server accepted notification {}",
                 notification.method
             );
-            Box::new(future.into_future().then(|_| Ok(None)))
+            let metrics = ctx.metrics.clone();
+            let method = notification.method.clone();
+            Box::new(instrument(span, future.into_future()).then(move |_| {
+                metrics.notification_handled(&method, start.elapsed());
+                Ok(None)
+            }))
         },
     }
 }
 
-// To process a batch using the same set of parallel executors as the whole server, we produce a
-// stream of the computations which return nothing, but gather the results. Then we add yet another
-// future at the end of that stream that takes the gathered results and wraps them into the real
-// message ‒ the result of the whole batch.
+/// Runs the call once the limits allow it.
+///
+/// The call gets the error to reject it with, if it is over the limit.
+fn limited<RpcServer, Call>(
+    ctx: &Rc<Context<RpcServer>>, method: &str, call: Call
+) -> FutureMessage
+where
+    RpcServer: 'static,
+    Call: FnOnce(&Context<RpcServer>, Option<RpcError>) -> FutureMessage + 'static,
+{
+    let admission = match ctx.limiter {
+        Some(ref limiter) => limiter.admit(method),
+        None => return call(ctx, None),
+    };
+    match admission {
+        Admission::Now(permit) => Box::new(call(ctx, None).then(move |result| {
+            drop(permit);
+            result
+        })),
+        Admission::Later(permit) => {
+            trace!(ctx.logger, "Call of {} waits for the limit", method);
+            let ctx = ctx.clone();
+            let started = permit.map_err(shouldnt_happen).and_then(move |permit| {
+                call(&ctx, None).then(move |result| {
+                    drop(permit);
+                    result
+                })
+            });
+            Box::new(started)
+        },
+        Admission::Rejected(error) => {
+            debug!(ctx.logger, "Call of {} rejected by the limit", method);
+            call(ctx, Some(error))
+        },
+    }
+}
+
+/// Checks the rate limits, returning the error to reject the call with if it comes too often.
+fn throttled<RpcServer>(
+    ctx: &Context<RpcServer>, method: &str, notification: bool
+) -> Option<RpcError> {
+    ctx.rate_limiter
+        .as_ref()
+        .and_then(|limiter| limiter.throttle(method, notification))
+}
+
+fn limited_request<RpcServer: Server + 'static, Params: CallParams + 'static>(
+    ctx: &Rc<Context<RpcServer>>, request: Request<Params>
+) -> FutureMessage {
+    if let Some(error) = throttled(ctx, &request.method, false) {
+        return Box::new(Ok(Some(request.error(error))).into_future());
+    }
+    let method = request.method.clone();
+    limited(ctx, &method, move |ctx, rejected| match rejected {
+        Some(error) => Box::new(Ok(Some(request.error(error))).into_future()),
+        None => do_request(ctx, request),
+    })
+}
+
+fn limited_notification<RpcServer: Server + 'static, Params: CallParams + 'static>(
+    ctx: &Rc<Context<RpcServer>>, notification: Notification<Params>
+) -> FutureMessage {
+    if throttled(ctx, &notification.method, true).is_some() {
+        return Box::new(Ok(None).into_future());
+    }
+    let method = notification.method.clone();
+    limited(ctx, &method, move |ctx, rejected| match rejected {
+        Some(_) => Box::new(Ok(None).into_future()),
+        None => do_notification(ctx, &notification),
+    })
+}
+
+// A batch is processed as a single message, producing a single future. Inside, its entries run
+// as the batch policy allows, by default with the same parallelism as the whole endpoint. Their
+// results are gathered and wrapped into the real message ‒ the result of the whole batch.
 fn do_batch<RpcServer: Server + 'static>(
-    server: &RpcServer, ctl: &ServerCtl, idmap: &IDMap, logger: &Logger, msg: Vec<Message>
+    ctx: &Rc<Context<RpcServer>>, msg: Vec<Message>
 ) -> FutureMessageStream {
-    // Create a large enough channel. We may be unable to pick up the results until the final
-    // future gets its turn, so shorter one could lead to a deadlock.
-    let (sender, receiver) = channel(msg.len());
-    // Each message produces a single stream of futures. Create that streams (right now, so we
-    // don't have to keep server long into the future).
-    let small_streams: Vec<_> = msg.into_iter()
-        .map(|sub| -> Result<_, IoError> {
-            let sender = sender.clone();
-            // This part is a bit convoluted. The do_msg returns a stream of futures. We want to
-            // take each of these futures (the outer and_then), run it to completion (the inner
-            // and_then), send its result through the sender if it provided one and then
-            // convert the result to None.
-            //
-            // Note that do_msg may return arbitrary number of work futures, but only at most one
-            // of them is supposed to provide a resulting value which would be sent through the
-            // channel. Unfortunately, there's no way to know which one it'll be, so we have to
-            // clone the sender all over the place.
-            //
-            // Also, it is a bit unfortunate how we need to allocate so many times here. We may try
-            // doing something about that in the future, but without implementing custom future and
-            // stream types, this seems the best we can do.
-            let all_sent = do_msg(server, ctl, idmap, logger, Ok(sub)).and_then(
-                move |future_message| -> Result<FutureMessage, _> {
-                    let sender = sender.clone();
-                    let msg_sent =
-                        future_message.and_then(move |response: Option<Message>| match response {
-                            None => Either::A(Ok(None).into_future()),
-                            Some(msg) => {
-                                Either::B(sender.send(msg).map_err(shouldnt_happen).map(|_| None))
-                            },
-                        });
-                    Ok(Box::new(msg_sent))
-                },
-            );
-            Ok(all_sent)
+    let policy = ctx.batch_policy;
+    let concurrency = policy.concurrency.unwrap_or(ctx.parallel);
+    match policy.max_len {
+        Some(max_len) if msg.len() > max_len => {
+            debug!(ctx.logger, "Batch too long"; "len" => msg.len(), "max" => max_len);
+            let mut error = RpcError::invalid_request();
+            let reason = format!("batch of {} messages, at most {} allowed", msg.len(), max_len);
+            error.data = Some(Value::String(reason));
+            let err: FutureMessage = Box::new(Ok(Some(Message::error(error))).into_future());
+            return Box::new(once(err));
+        },
+        _ => (),
+    }
+    let ctx = ctx.clone();
+    // Each entry is passed to the server only once its turn comes, so the sequential batches
+    // see the effects of the previous entries. The do_msg returns a stream of futures, but only
+    // at most one of them provides a response, so run them all and keep that one, together with
+    // the position of the entry.
+    let results = stream::iter_ok(msg.into_iter().enumerate())
+        .map(move |(idx, sub)| {
+            do_msg(&ctx, Ok(sub))
+                .and_then(|future_message| future_message)
+                .filter_map(|response| response)
+                .collect()
+                .map(move |responses| responses.into_iter().map(move |response| (idx, response)))
         })
-        .collect();
-    // We make it into a stream of streams and flatten it to get one big stream
-    let subs_stream = stream::iter_result(small_streams).flatten();
-    // Once all the results are produced, wrap them into a batch and return that one
-    let collected = receiver.collect().map_err(shouldnt_happen).map(|results| {
-        if results.is_empty() {
-            // The spec says to send nothing at all if there are no results
-            None
-        } else {
-            Some(Message::Batch(results))
-        }
-    });
-    let streamed: Once<FutureMessage, _> = once(Box::new(collected));
-    // Connect the wrapping single-item stream after the work stream
-    Box::new(subs_stream.chain(streamed))
+        .buffer_unordered(concurrency)
+        .collect()
+        .map(move |results| {
+            let mut results: Vec<_> = results.into_iter().flatten().collect();
+            if policy.ordered {
+                results.sort_by_key(|&(idx, _)| idx);
+            }
+            if results.is_empty() {
+                // The spec says to send nothing at all if there are no results
+                None
+            } else {
+                Some(Message::Batch(results.into_iter().map(|(_, msg)| msg).collect()))
+            }
+        });
+    let collected: FutureMessage = Box::new(results);
+    Box::new(once(collected))
 }
 
-fn do_response(idmap: &IDMap, logger: &Logger, response: Response) -> FutureMessageStream {
+fn do_response<RpcServer>(ctx: &Context<RpcServer>, response: Response) -> FutureMessageStream {
     let maybe_sender = response
         .id
         .as_str()
-        .and_then(|id| idmap.borrow_mut().remove(id));
+        .and_then(|id| ctx.idmap.borrow_mut().remove(id));
+    ctx.metrics.response_received(maybe_sender.is_some());
     if let Some(sender) = maybe_sender {
-        trace!(logger, "Received an RPC response"; "id" => format!("{:?}", response.id));
+        trace!(ctx.logger, "Received an RPC response"; "id" => format!("{:?}", response.id));
+        ctx.metrics.outstanding_calls(ctx.idmap.borrow().len());
         // Don't care about the result, if the other side went away, it doesn't need the response
         // and that's OK with us.
         drop(sender.send(response));
     } else {
-        error!(logger, "Unexpected RPC response"; "id" => format!("{:?}", response.id));
+        error!(ctx.logger, "Unexpected RPC response"; "id" => format!("{:?}", response.id));
+        let parse_error = match response.result {
+            Err(ref error) => response.id.is_null() && error.code == PARSE_ERROR,
+            Ok(_) => false,
+        };
+        if parse_error && ctx.fail_on_parse_error {
+            fail_calls(ctx, &response);
+        }
+        if let Some(ref mut senders) = ctx.ctl.0.borrow_mut().unsolicited {
+            senders.retain(|sender| sender.unbounded_send(response.clone()).is_ok());
+        }
     }
     Box::new(empty())
 }
 
+/// Resolves all the outstanding calls with the error the other side sent.
+///
+/// The other side couldn't parse one of our messages, but it can't tell which one, so any of the
+/// calls may be waiting for an answer that never comes.
+fn fail_calls<RpcServer>(ctx: &Context<RpcServer>, response: &Response) {
+    let senders: Vec<_> = ctx.idmap.borrow_mut().drain().collect();
+    debug!(ctx.logger, "Failing calls on a parse error"; "outstanding" => senders.len());
+    if senders.is_empty() {
+        return;
+    }
+    ctx.metrics.outstanding_calls(0);
+    for (id, sender) in senders {
+        let mut failed = response.clone();
+        failed.id = Value::String(id);
+        drop(sender.send(failed));
+    }
+}
+
 // Handle single message and turn it into an arbitrary number of futures that may be worked on in
 // parallel, but only at most one of which returns a response message
 fn do_msg<RpcServer: Server + 'static>(
-    server: &RpcServer, ctl: &ServerCtl, idmap: &IDMap, logger: &Logger, msg: Parsed
+    ctx: &Rc<Context<RpcServer>>, msg: Parsed
 ) -> FutureMessageStream {
-    let terminated = ctl.0.borrow().stop;
-    trace!(logger, "Do a message"; "terminated" => terminated, "message" => format!("{:?}", msg));
+    let terminated = ctx.ctl.0.borrow().stop;
+    trace!(
+        ctx.logger,
+        "Do a message";
+        "terminated" => terminated,
+        "message" => format!("{:?}", msg)
+    );
     if terminated {
         if let Ok(Message::Response(response)) = msg {
-            do_response(idmap, logger, response);
+            do_response(ctx, response);
         }
         Box::new(empty())
     } else {
         match msg {
             Err(broken) => {
-                let err: FutureMessage = Box::new(Ok(Some(broken.reply())).into_future());
+                ctx.metrics.broken(&broken);
+                let reply = broken.reply_in(ctx.version);
+                let err: FutureMessage = Box::new(Ok(Some(reply)).into_future());
                 Box::new(once(err))
             },
-            Ok(Message::Request(req)) => Box::new(once(do_request(server, ctl, req, logger))),
-            Ok(Message::Notification(notif)) => {
-                Box::new(once(do_notification(server, ctl, &notif, logger)))
+            Ok(Message::Request(req)) => Box::new(once(limited_request(ctx, req))),
+            Ok(Message::Notification(notif)) => Box::new(once(limited_notification(ctx, notif))),
+            Ok(Message::RawRequest(req)) => Box::new(once(limited_request(ctx, req))),
+            Ok(Message::RawNotification(notif)) => {
+                Box::new(once(limited_notification(ctx, notif)))
             },
-            Ok(Message::Batch(batch)) => do_batch(server, ctl, idmap, logger, batch),
-            Ok(Message::UnmatchedSub(value)) => {
-                do_msg(server, ctl, idmap, logger, Err(Broken::Unmatched(value)))
+            Ok(Message::Batch(batch)) => do_batch(ctx, batch),
+            Ok(Message::UnmatchedSub(value, reason)) => {
+                do_msg(ctx, Err(Broken::Unmatched(value, reason)))
             },
-            Ok(Message::Response(response)) => do_response(idmap, logger, response),
+            Ok(Message::Response(response)) => do_response(ctx, response),
+            Ok(Message::RawResponse(response)) => do_response(ctx, response.parsed()),
         }
     }
 }
@@ -360,6 +738,10 @@ struct ClientData {
     /// Keep the connection alive as long as the client is alive.
     terminator: RcDrop,
     logger: Logger,
+    metrics: Rc<Metrics>,
+    tracing: TraceConnection,
+    /// If methods with the reserved names may be called.
+    allow_reserved: bool,
 }
 
 /// The client part of the endpoint.
@@ -378,19 +760,23 @@ pub type RpcSent = BoxFuture<(Client, RpcFinished), IoError>;
 
 impl Client {
     /// A constructor (a private one).
+    ///
+    /// It takes the shared parts from the internals of the server control.
     fn new(
-        idmap: &IDMap, ctl: &ServerCtl, handle: &Handle, terminator: &RcDrop,
-        sender: &Sender<Message>, logger: Logger,
+        ctl: &ServerCtl, internal: &ServerCtlInternal, terminator: &RcDrop, sender: &Sender<Message>
     ) -> Self {
-        debug!(logger, "Creating a new client");
+        debug!(internal.logger, "Creating a new client");
         Client {
             sender: sender.clone(),
             data: ClientData {
-                idmap: idmap.clone(),
+                idmap: internal.idmap.clone(),
                 ctl: ctl.clone(),
-                handle: handle.clone(),
+                handle: internal.handle.clone(),
                 terminator: terminator.clone(),
-                logger,
+                logger: internal.logger.clone(),
+                metrics: internal.metrics.clone(),
+                tracing: internal.tracing.clone(),
+                allow_reserved: false,
             },
         }
     }
@@ -400,12 +786,20 @@ impl Client {
     /// once the message is sent. It yields the Client back (it is blocked for the time of sending)
     /// and another future that resolves once the answer is received (or once a timeout happens, in
     /// which case the result is None).
+    ///
+    /// Methods with names reserved for extensions (starting with `rpc.`) are refused with an
+    /// `InvalidInput` error, unless allowed by [`allow_reserved`](#method.allow_reserved).
     pub fn call(self, method: String, params: Option<Value>, timeout: Option<Duration>) -> RpcSent {
+        if let Err(e) = self.check_reserved(&method) {
+            return Box::new(Err(e).into_future());
+        }
         // We have to deconstruct self now, because the sender's send takes ownership for it for a
         // while. We construct it back once the message is passed on.
         let data = self.data;
         trace!(data.logger, "Calling RPC {}", method);
-        let msg = Message::request(method, params);
+        data.metrics.call_sent(&method);
+        let start = Instant::now();
+        let msg = Message::request(method.clone(), params);
         let id = match msg {
             Message::Request(Request {
                 id: Value::String(ref id),
@@ -413,12 +807,18 @@ impl Client {
             }) => id.clone(),
             _ => unreachable!("We produce only string IDs"),
         };
+        let span = data.tracing.call(&method, &Value::String(id.clone()));
         let (sender, receiver) = one_channel();
         let rc_terminator = data.terminator.clone();
         let logger_cloned = data.logger.clone();
+        let metrics = data.metrics.clone();
+        let method_cloned = method.clone();
         let received = receiver
             .map_err(|_| IoError::new(io::ErrorKind::Other, "Lost connection"))
-            .map(Some)
+            .map(move |response| {
+                metrics.call_answered(&method_cloned, start.elapsed());
+                Some(response)
+            })
             .then(move |r| {
                 trace!(logger_cloned, "Received RPC answer");
                 drop(rc_terminator);
@@ -434,9 +834,12 @@ impl Client {
                 let idmap = data.idmap.clone();
                 let id = id.clone();
                 let logger_cloned = data.logger.clone();
+                let metrics = data.metrics.clone();
+                let metrics_cloned = data.metrics.clone();
                 let completed = timeout
                     .then(move |r| {
                         trace!(logger_cloned, "RPC timed out");
+                        metrics.call_timed_out(&method);
                         r
                     })
                     .map(|_| None)
@@ -447,7 +850,10 @@ impl Client {
                     // This is a NOOP in case the real result arrives, since it is already deleted
                     // by then, but that doesn't matter and this is simpler.
                     .then(move |r| {
-                        idmap.borrow_mut().remove(&id);
+                        let mut idmap = idmap.borrow_mut();
+                        if idmap.remove(&id).is_some() {
+                            metrics_cloned.outstanding_calls(idmap.len());
+                        }
                         r
                     });
                 Box::new(completed)
@@ -455,7 +861,12 @@ impl Client {
             // If we don't have the timeout, simply pass the future to get the response through.
             None => Box::new(received),
         };
-        data.idmap.borrow_mut().insert(id, sender);
+        let completed: RpcFinished = Box::new(instrument(span, completed));
+        {
+            let mut idmap = data.idmap.borrow_mut();
+            idmap.insert(id, sender);
+            data.metrics.outstanding_calls(idmap.len());
+        }
         // Ensure the connection is kept alive until the answer comes
         let sent = self.sender
             .send(msg)
@@ -485,6 +896,54 @@ impl Client {
     pub fn server_ctl(&self) -> &ServerCtl {
         &self.data.ctl
     }
+    /// Provides the responses no call waits for.
+    ///
+    /// These are responses with an unknown ID (for example, to a call that has timed out already)
+    /// and errors with a null ID. The other side sends these when it can't tell which message
+    /// they belong to, usually because it couldn't parse it (see
+    /// [`Endpoint::fail_on_parse_error`](struct.Endpoint.html#method.fail_on_parse_error)).
+    ///
+    /// Each stream gets all the responses that come after it was created. The stream ends once
+    /// the endpoint finishes and it doesn't keep the connection alive.
+    pub fn unsolicited(&self) -> UnsolicitedStream {
+        let (sender, receiver) = unbounded();
+        // If the endpoint is finished already, the sender is dropped right away and the stream ends
+        if let Some(ref mut senders) = self.data.ctl.0.borrow_mut().unsolicited {
+            senders.push(sender);
+        }
+        UnsolicitedStream(receiver)
+    }
+    /// Allows or disallows calling methods with names reserved for extensions.
+    ///
+    /// The JSON RPC specification reserves methods starting with `rpc.` for system extensions. By
+    /// default, the client refuses to call them, to prevent calling them by accident. Use this to
+    /// call an extension the other side is known to provide.
+    pub fn allow_reserved(mut self, allow: bool) -> Self {
+        self.data.allow_reserved = allow;
+        self
+    }
+    /// Fails if the method is reserved and it is not allowed.
+    fn check_reserved(&self, method: &str) -> Result<(), IoError> {
+        if is_reserved(method) && !self.data.allow_reserved {
+            let msg = format!("Method {} is reserved for extensions", method);
+            Err(IoError::new(ErrorKind::InvalidInput, msg))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// The responses no call waits for.
+///
+/// It is created by [`Client::unsolicited`](struct.Client.html#method.unsolicited).
+pub struct UnsolicitedStream(UnboundedReceiver<Response>);
+
+impl Stream for UnsolicitedStream {
+    type Item = Response;
+    type Error = ();
+    fn poll(&mut self) -> Poll<Option<Response>, ()> {
+        self.0.poll()
+    }
 }
 
 /// The builder structure for the end point.
@@ -544,13 +1003,25 @@ pub struct Endpoint<Connection, RpcServer> {
     server: RpcServer,
     parallel: usize,
     logger: Logger,
+    metrics: Rc<Metrics>,
+    introspection: Option<Introspection>,
+    extensions: Vec<Extension>,
+    reserved_names: bool,
+    keepalive: Option<Keepalive>,
+    version: Version,
+    strict: bool,
+    fail_on_parse_error: bool,
+    batch_policy: BatchPolicy,
+    limits: Option<Limits>,
+    rate_limits: Option<RateLimits>,
+    auth_gate: Option<AuthGate>,
 }
 
 impl<Connection, RpcServer> Endpoint<Connection, RpcServer>
 where
     Connection: Stream<Item = Parsed, Error = IoError>,
     Connection: Sink<SinkItem = Message, SinkError = IoError>,
-    Connection: Send + 'static,
+    Connection: 'static,
     RpcServer: Server + 'static,
 {
     /// Create the endpoint builder.
@@ -562,6 +1033,18 @@ where
             server,
             parallel: 1,
             logger: Logger::root(Discard, o!()),
+            metrics: Rc::new(NoMetrics),
+            introspection: None,
+            extensions: Vec::new(),
+            reserved_names: false,
+            keepalive: None,
+            version: Version::V2,
+            strict: false,
+            fail_on_parse_error: false,
+            batch_policy: BatchPolicy::new(),
+            limits: None,
+            rate_limits: None,
+            auth_gate: None,
         }
     }
     /// Set how many RPCs may be process in parallel.
@@ -584,6 +1067,134 @@ where
     pub fn logger(self, logger: Logger) -> Self {
         Endpoint { logger, ..self }
     }
+    /// Sets the metrics hook of the endpoint.
+    ///
+    /// The hook is notified about the requests and notifications received, answers produced,
+    /// calls made by the client, etc. Keep a clone of the `Rc` if you want to read the collected
+    /// values later on. By default, the events are thrown away.
+    pub fn metrics(self, metrics: Rc<Metrics>) -> Self {
+        Endpoint { metrics, ..self }
+    }
+    /// Turns on the introspection.
+    ///
+    /// The endpoint then answers the `rpc.discover` method with an [OpenRPC](https://open-rpc.org)
+    /// document, generated from the [`methods`](../server/trait.Server.html#method.methods) of the
+    /// server. The method doesn't reach the server in such case. See the
+    /// [`introspection`](../introspection/index.html) module.
+    pub fn introspection(self, introspection: Introspection) -> Self {
+        Endpoint {
+            introspection: Some(introspection),
+            ..self
+        }
+    }
+    /// Registers an extension.
+    ///
+    /// Extensions provide the methods with names reserved by the specification (starting with
+    /// `rpc.`). When such method is called, the extensions are asked first, in the order they were
+    /// registered, and the server only after none of them knows the method (see
+    /// [`reserved_names`](#method.reserved_names)). Extensions never see other method names.
+    pub fn extension(mut self, extension: BoxServer) -> Self {
+        self.extensions.push(Rc::from(extension));
+        self
+    }
+    /// Enforces the reserved method names.
+    ///
+    /// The specification reserves method names starting with `rpc.` for extensions. If this is
+    /// set, such methods are never passed to the server ‒ if no extension knows them, the call is
+    /// rejected with the method not found error. By default, they are passed to the server for
+    /// compatibility.
+    pub fn reserved_names(self, reserved_names: bool) -> Self {
+        Endpoint {
+            reserved_names,
+            ..self
+        }
+    }
+    /// Turns on the keepalive pings.
+    ///
+    /// The endpoint pings the other side periodically and kills the connection if the other side
+    /// doesn't respond in time. It also answers the pings of the other side, if neither the server
+    /// nor an extension does. See the [`keepalive`](../keepalive/index.html) module.
+    pub fn keepalive(self, keepalive: Keepalive) -> Self {
+        Endpoint {
+            keepalive: Some(keepalive),
+            ..self
+        }
+    }
+    /// Sets the version of the protocol.
+    ///
+    /// With [`Version::V1`](../message/enum.Version.html#variant.V1), the endpoint accepts the
+    /// JSON-RPC 1.0 messages in addition to the 2.0 ones (see
+    /// [`accept_v1`](../message/fn.accept_v1.html)) and sends its own calls (from the clients
+    /// and the keepalive pings) as 1.0. The requests are always answered in the version they
+    /// came in.
+    ///
+    /// The default is `V2`, which doesn't accept 1.0 messages.
+    pub fn version(self, version: Version) -> Self {
+        Endpoint { version, ..self }
+    }
+    /// Enforces the specification on the incoming messages.
+    ///
+    /// The messages violating it are treated as broken ‒ the requests are answered with the
+    /// invalid request error and an empty batch gets a single one, as the specification demands.
+    /// See [`message::strict`](../message/fn.strict.html) for what is checked. Some of the
+    /// violations are visible only in the original JSON, so use a codec in the strict mode too if
+    /// possible.
+    ///
+    /// The default is to be lenient.
+    pub fn strict(self, strict: bool) -> Self {
+        Endpoint { strict, ..self }
+    }
+    /// Fails the outstanding calls when the other side reports a parse error.
+    ///
+    /// The other side answers a message it can't parse with an error with a null ID, so it is not
+    /// known which call it belongs to and the calls would wait until their timeout (or forever).
+    /// If this is set, all the calls waiting for an answer get this error as their response
+    /// instead.
+    ///
+    /// The error is provided by [`Client::unsolicited`](struct.Client.html#method.unsolicited)
+    /// either way. This is off by default.
+    pub fn fail_on_parse_error(self, fail_on_parse_error: bool) -> Self {
+        Endpoint {
+            fail_on_parse_error,
+            ..self
+        }
+    }
+    /// Sets how the incoming batches are processed.
+    ///
+    /// See the [`batch`](../batch/index.html) module.
+    pub fn batch_policy(self, batch_policy: BatchPolicy) -> Self {
+        Endpoint {
+            batch_policy,
+            ..self
+        }
+    }
+    /// Limits how many calls the server handles at once.
+    ///
+    /// See the [`limit`](../limit/index.html) module.
+    pub fn limits(self, limits: Limits) -> Self {
+        Endpoint {
+            limits: Some(limits),
+            ..self
+        }
+    }
+    /// Limits how often the calls may come.
+    ///
+    /// See the [`rate`](../rate/index.html) module.
+    pub fn rate_limits(self, rate_limits: RateLimits) -> Self {
+        Endpoint {
+            rate_limits: Some(rate_limits),
+            ..self
+        }
+    }
+    /// Requires the connection to authenticate before anything else may be called.
+    ///
+    /// See the [`auth`](../auth/index.html) module.
+    pub fn auth_gate(self, auth_gate: AuthGate) -> Self {
+        Endpoint {
+            auth_gate: Some(auth_gate),
+            ..self
+        }
+    }
     /// Start the endpoint.
     ///
     /// Once all configuration is set, this creates the actual endpoint pair ‒ both the server and
@@ -598,6 +1209,8 @@ where
     pub fn start(self, handle: &Handle) -> (Client, Box<Future<Item = (), Error = IoError>>) {
         debug!(self.logger, "Starting endpoint"; "parallel" => self.parallel);
         let logger = self.logger;
+        let metrics = self.metrics;
+        let tracing = TraceConnection::new();
         let (terminator_sender, terminator_receiver) = one_channel();
         let (killer_sender, killer_receiver) = one_channel();
         let (sender, receiver) = channel(32);
@@ -611,15 +1224,14 @@ where
             handle: handle.clone(),
             sender: Some(sender.clone()),
             logger: logger.clone(),
+            metrics: metrics.clone(),
+            tracing: tracing.clone(),
+            watchers: Vec::new(),
+            unsolicited: Some(Vec::new()),
+            identity: None,
         })));
-        let client = Client::new(
-            &idmap,
-            &ctl,
-            handle,
-            &rc_terminator,
-            &sender,
-            logger.clone(),
-        );
+        let client = ctl.client()
+            .expect("A freshly started endpoint can't be terminated");
         let (sink, stream) = self.connection.split();
         // Create a future for each received item that'll return something. Run some of them in
         // parallel.
@@ -635,46 +1247,141 @@ where
         // Move out of self, otherwise the closure captures self, not only server :-|
         let server = self.server;
         server.initialized(&ctl);
+        for extension in &self.extensions {
+            extension.initialized(&ctl);
+        }
         let idmap_cloned = idmap.clone();
         let logger_cloned = logger.clone();
+        let metrics_cloned = metrics.clone();
         // A stream that contains no elements, but cleans the idmap once called (to kill the RPC
         // futures)
         let ctl_clone = ctl.clone();
         let cleaner = unfold((), move |_| -> Option<Result<_, _>> {
             let mut idmap = idmap_cloned.borrow_mut();
             debug!(logger_cloned, "Dropping unanswered RPCs (EOS)"; "outstanding" => idmap.len());
-            idmap.clear();
+            if !idmap.is_empty() {
+                idmap.clear();
+                metrics_cloned.outstanding_calls(0);
+            }
             // Terminate the server manually when we reach the end of input, because it holds the
             // client alive ‒ this will end the messages from the client endpoint.
             ctl_clone.terminate();
             Some(Ok((None, ())))
         });
         let idmap_cloned = idmap.clone();
-        let logger_cloned = logger.clone();
+        let ctl_transmitted = ctl.clone();
+        let ctl_keepalive = ctl.clone();
+        let received = Rc::new(Cell::new(Instant::now()));
+        let received_cloned = received.clone();
+        let ctx = Rc::new(Context {
+            server,
+            ctl,
+            idmap,
+            logger: logger.clone(),
+            metrics: metrics.clone(),
+            tracing,
+            introspection: self.introspection,
+            extensions: self.extensions,
+            reserved_names: self.reserved_names,
+            fail_on_parse_error: self.fail_on_parse_error,
+            version: self.version,
+            answer_pings: self.keepalive.is_some(),
+            parallel: self.parallel,
+            batch_policy: self.batch_policy,
+            limiter: self.limits.map(Limiter::new),
+            rate_limiter: self.rate_limits
+                .map(|limits| RateLimiter::new(limits, logger.clone())),
+            auth_gate: self.auth_gate,
+        });
+        let version = self.version;
+        let strict_mode = self.strict;
         let answers = stream
+            .inspect(move |_| received_cloned.set(Instant::now()))
+            .map(move |parsed| if strict_mode { strict(parsed) } else { parsed })
+            .map(move |parsed| match version {
+                Version::V1 => accept_v1(parsed),
+                Version::V2 => parsed,
+            })
             .map(Some)
             .chain(cleaner)
             .select(terminator)
             .take_while(|m| Ok(m.is_some()))
-            .map(move |parsed| do_msg(&server, &ctl, &idmap, &logger_cloned, parsed.unwrap()))
+            .map(move |parsed| do_msg(&ctx, parsed.unwrap()))
             .flatten()
             .buffer_unordered(self.parallel)
             .filter_map(|message| message);
         let logger_cloned = logger.clone();
         // Take both the client RPCs and the answers
-        let outbound = answers.select(receiver.map_err(shouldnt_happen));
+        let calls = receiver
+            .map(move |call| own_version(call, version))
+            .map_err(shouldnt_happen);
+        let outbound = answers.select(calls);
+        let outbound: BoxStream<Message, IoError> = match self.keepalive {
+            None => Box::new(outbound),
+            Some(config) => {
+                let (pings, ping_receiver) = unbounded();
+                let idmap = idmap_cloned.clone();
+                let idmap_forget = idmap_cloned.clone();
+                let metrics_register = metrics.clone();
+                let metrics_forget = metrics.clone();
+                Pinger {
+                    config,
+                    handle: handle.clone(),
+                    logger: logger.clone(),
+                    pings,
+                    // The pings are outstanding calls like any other, for the metrics too
+                    register: Box::new(move |id, sender| {
+                        let mut idmap = idmap.borrow_mut();
+                        idmap.insert(id, sender);
+                        metrics_register.outstanding_calls(idmap.len());
+                    }),
+                    forget: Rc::new(move |id| {
+                        let mut idmap = idmap_forget.borrow_mut();
+                        if idmap.remove(id).is_some() {
+                            metrics_forget.outstanding_calls(idmap.len());
+                        }
+                    }),
+                    received,
+                    kill: Rc::new(move |error| ctl_keepalive.kill_with(Some(error))),
+                }.spawn();
+                // The pings must not keep the connection alive, so we end with the rest of the
+                // outbound messages (the None is the marker of the end).
+                let pings = ping_receiver
+                    .map(move |ping| Some(own_version(ping, version)))
+                    .map_err(shouldnt_happen);
+                let with_pings = outbound
+                    .map(Some)
+                    .chain(once(None))
+                    .select(pings)
+                    .take_while(|m| Ok(m.is_some()))
+                    .map(Option::unwrap);
+                Box::new(with_pings)
+            },
+        };
         let (error_sender, error_receiver) = one_channel::<Option<IoError>>();
         // And send them all (or kill it, if it happens first)
         let transmitted = sink.send_all(outbound)
-            .map(|_| ())
+            .map(|_| None)
             .select(killer_receiver.map_err(shouldnt_happen))
             .then(move |result| {
                 // This will hopefully kill the RPC futures
                 // We kill on both ends, because we may kill the connection or the other side may.
                 let mut idmap = idmap_cloned.borrow_mut();
                 debug!(logger_cloned, "Dropping unanswered RPCs"; "outstanding" => idmap.len());
-                idmap.clear();
+                if !idmap.is_empty() {
+                    idmap.clear();
+                    metrics.outstanding_calls(0);
+                }
+                // Nothing more can be sent, so make sure the server knows it is terminated. Nothing
+                // more is received either, so end the streams of unsolicited responses.
+                ctl_transmitted.cleanup(|internal| internal.unsolicited = None);
                 match result {
+                    Ok((Some(e), _select_next)) => {
+                        debug!(logger_cloned, "Connection killed with an error";
+                               "error" => format!("{}", e));
+                        drop(error_sender.send(Some(e)));
+                        Err(())
+                    },
                     Ok(_) => {
                         debug!(logger_cloned, "Outbound stream ended successfully");
                         // Don't care about result (the other side simply doesn't care about the
@@ -709,7 +1416,7 @@ impl<Connection> Endpoint<Connection, EmptyServer>
 where
     Connection: Stream<Item = Parsed, Error = IoError>,
     Connection: Sink<SinkItem = Message, SinkError = IoError>,
-    Connection: Send + 'static,
+    Connection: 'static,
 {
     /// Create an endpoint with [`Empty`](../server/struct.Empty.html).
     ///
@@ -718,3 +1425,20 @@ where
         Self::new(connection, EmptyServer)
     }
 }
+
+impl<Connection> Endpoint<Connection, NotificationForwarder>
+where
+    Connection: Stream<Item = Parsed, Error = IoError>,
+    Connection: Sink<SinkItem = Message, SinkError = IoError>,
+    Connection: 'static,
+{
+    /// Create an endpoint that passes all incoming notifications into a stream.
+    ///
+    /// This is a client that doesn't answer any RPCs, but is interested in the notifications from
+    /// the other side. Use [`NotificationForwarder`](../server/struct.NotificationForwarder.html)
+    /// directly to receive only some of the notifications.
+    pub fn notifications(connection: Connection) -> (Self, NotificationStream) {
+        let (forwarder, stream) = NotificationForwarder::new();
+        (Self::new(connection, forwarder), stream)
+    }
+}
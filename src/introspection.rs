@@ -0,0 +1,321 @@
+// Copyright 2017 tokio-jsonrpc Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Describing the methods a server provides.
+//!
+//! A [`Server`](../server/trait.Server.html) may describe its methods by returning
+//! [`MethodInfo`](struct.MethodInfo.html) from its
+//! [`methods`](../server/trait.Server.html#method.methods) callback. The
+//! [`jsonrpc_method_info`](../macro.jsonrpc_method_info.html) macro helps building them, using
+//! the same parameter definitions as [`jsonrpc_params`](../macro.jsonrpc_params.html).
+//!
+//! If [`Endpoint::introspection`](../endpoint/struct.Endpoint.html#method.introspection) is
+//! set, the endpoint answers the `rpc.discover` method with an [OpenRPC](https://open-rpc.org)
+//! document generated from the descriptions.
+
+use serde_json::{Map, Value};
+
+/// The version of the OpenRPC specification the generated documents follow.
+pub const OPENRPC_VERSION: &str = "1.3.2";
+
+/// The name of the method answered with the OpenRPC document.
+pub const DISCOVER_METHOD: &str = "rpc.discover";
+
+/// A description of a single parameter of a method.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct ParamInfo {
+    /// The name of the parameter.
+    pub name: String,
+    /// The name of the rust type of the parameter (eg. `Option<Vec<String>>`).
+    pub type_name: String,
+    /// If the parameter must be present.
+    pub required: bool,
+}
+
+impl ParamInfo {
+    /// Creates the description of a parameter.
+    ///
+    /// The parameter is considered optional if its type is an `Option`. Whitespace is removed from
+    /// the type name.
+    pub fn new(name: &str, type_name: &str) -> Self {
+        let type_name = type_name
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .collect::<String>();
+        ParamInfo {
+            name: name.to_owned(),
+            required: strip_wrapper(&type_name, "Option").is_none(),
+            type_name,
+        }
+    }
+    /// Provides a JSON schema for the parameter type.
+    ///
+    /// Only the basic types are recognized (numbers, strings, bools, vectors and options of
+    /// these), other types are described just by their name.
+    pub fn schema(&self) -> Value {
+        schema(&self.type_name)
+    }
+}
+
+/// A description of a method (an RPC or a notification).
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct MethodInfo {
+    /// The name of the method.
+    pub name: String,
+    /// A human-readable description of what the method does.
+    pub description: Option<String>,
+    /// The parameters the method takes.
+    pub params: Vec<ParamInfo>,
+    /// If this is a notification instead of an RPC.
+    pub notification: bool,
+}
+
+impl MethodInfo {
+    /// Creates a description of an RPC without any parameters and description.
+    pub fn rpc(name: &str) -> Self {
+        MethodInfo {
+            name: name.to_owned(),
+            description: None,
+            params: Vec::new(),
+            notification: false,
+        }
+    }
+    /// Creates a description of a notification without any parameters and description.
+    pub fn notification(name: &str) -> Self {
+        MethodInfo {
+            notification: true,
+            ..Self::rpc(name)
+        }
+    }
+    /// Returns the same description, with the name prefixed.
+    ///
+    /// This is used by servers that route the methods by a prefix, like the
+    /// [`Namespaced`](../server/struct.Namespaced.html).
+    pub fn prefixed(self, prefix: &str) -> Self {
+        MethodInfo {
+            name: format!("{}{}", prefix, self.name),
+            ..self
+        }
+    }
+    /// Generates the OpenRPC method object.
+    pub fn to_openrpc(&self) -> Value {
+        let params = self.params
+            .iter()
+            .map(|param| {
+                json!({
+                    "name": param.name,
+                    "required": param.required,
+                    "schema": param.schema(),
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut method = Map::new();
+        method.insert("name".to_owned(), Value::String(self.name.clone()));
+        if let Some(ref description) = self.description {
+            method.insert("description".to_owned(), Value::String(description.clone()));
+        }
+        method.insert("params".to_owned(), Value::Array(params));
+        // OpenRPC has no notion of notifications, so we simply don't promise any result
+        if !self.notification {
+            method.insert("result".to_owned(), json!({"name": "result", "schema": {}}));
+        }
+        Value::Object(method)
+    }
+}
+
+/// The configuration of the introspection.
+///
+/// Pass it to [`Endpoint::introspection`](../endpoint/struct.Endpoint.html#method.introspection)
+/// to turn it on.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Introspection {
+    /// The title of the API.
+    pub title: String,
+    /// The version of the API (not of the OpenRPC nor JSON RPC).
+    pub version: String,
+    /// Optional longer description of the API.
+    pub description: Option<String>,
+}
+
+impl Introspection {
+    /// Creates the configuration with the title and version of the API.
+    pub fn new(title: String, version: String) -> Self {
+        Introspection {
+            title,
+            version,
+            description: None,
+        }
+    }
+    /// Sets the description of the API.
+    pub fn description(self, description: String) -> Self {
+        Introspection {
+            description: Some(description),
+            ..self
+        }
+    }
+    /// Generates the OpenRPC document describing the given methods.
+    pub fn document(&self, methods: &[MethodInfo]) -> Value {
+        let mut info = Map::new();
+        info.insert("title".to_owned(), Value::String(self.title.clone()));
+        info.insert("version".to_owned(), Value::String(self.version.clone()));
+        if let Some(ref description) = self.description {
+            info.insert("description".to_owned(), Value::String(description.clone()));
+        }
+        json!({
+            "openrpc": OPENRPC_VERSION,
+            "info": info,
+            "methods": methods.iter().map(MethodInfo::to_openrpc).collect::<Vec<_>>(),
+        })
+    }
+}
+
+/// If the type is `wrapper<inner>`, returns the inner.
+fn strip_wrapper<'a>(type_name: &'a str, wrapper: &str) -> Option<&'a str> {
+    type_name
+        .strip_prefix(wrapper)
+        .and_then(|rest| rest.strip_prefix('<'))
+        .and_then(|rest| rest.strip_suffix('>'))
+}
+
+/// Generates a JSON schema for a type name (with the whitespace already removed).
+fn schema(type_name: &str) -> Value {
+    if let Some(inner) = strip_wrapper(type_name, "Option") {
+        return schema(inner);
+    }
+    if let Some(inner) = strip_wrapper(type_name, "Vec") {
+        return json!({"type": "array", "items": schema(inner)});
+    }
+    let basic = match type_name {
+        "bool" => "boolean",
+        "String" | "&str" | "&'staticstr" | "char" => "string",
+        "i8" | "i16" | "i32" | "i64" | "isize" | "u8" | "u16" | "u32" | "u64" | "usize" => {
+            "integer"
+        },
+        "f32" | "f64" => "number",
+        "Value" | "serde_json::Value" => return json!({}),
+        _ => return json!({ "title": type_name }),
+    };
+    json!({ "type": basic })
+}
+
+/// Describes a method.
+///
+/// This creates a [`MethodInfo`](introspection/struct.MethodInfo.html). The first token is either
+/// `rpc` or `notification`, followed by the method name and its description. The parameters are
+/// listed the same way as in the [`jsonrpc_params`](macro.jsonrpc_params.html) macro, so the
+/// definitions can be shared between parsing and describing.
+///
+/// # Examples
+///
+/// ```rust
+/// # #[macro_use] extern crate tokio_jsonrpc;
+/// # fn main() {
+/// let info = jsonrpc_method_info!(rpc "hello", "Greets someone",
+///                                 "name" => String, "times" => Option<usize>);
+/// assert_eq!("hello", info.name);
+/// assert!(!info.notification);
+/// assert!(info.params[0].required);
+/// assert!(!info.params[1].required);
+///
+/// let info = jsonrpc_method_info!(notification "bye", "Says good bye");
+/// assert!(info.notification);
+/// assert!(info.params.is_empty());
+/// # }
+/// ```
+#[macro_export]
+macro_rules! jsonrpc_method_info {
+    ( rpc $name:expr, $description:expr $( , $pname:expr => $ptype:ty )* ) => {
+        jsonrpc_method_info!(false, $name, $description $( , $pname => $ptype )*)
+    };
+    ( notification $name:expr, $description:expr $( , $pname:expr => $ptype:ty )* ) => {
+        jsonrpc_method_info!(true, $name, $description $( , $pname => $ptype )*)
+    };
+    ( $notification:expr, $name:expr, $description:expr $( , $pname:expr => $ptype:ty )* ) => {
+        $crate::introspection::MethodInfo {
+            name: $name.to_owned(),
+            description: Some($description.to_owned()),
+            params: vec![
+                $( $crate::introspection::ParamInfo::new($pname, stringify!($ptype)), )*
+            ],
+            notification: $notification,
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Check the schemas of some types.
+    #[test]
+    fn schemas() {
+        assert_eq!(json!({"type": "string"}), ParamInfo::new("x", "String").schema());
+        assert_eq!(json!({"type": "integer"}), ParamInfo::new("x", "Option<u32>").schema());
+        assert_eq!(
+            json!({"type": "array", "items": {"type": "boolean"}}),
+            ParamInfo::new("x", "Option < Vec < bool > >").schema()
+        );
+        assert_eq!(json!({}), ParamInfo::new("x", "Value").schema());
+        assert_eq!(
+            json!({"title": "HashMap<String,u8>"}),
+            ParamInfo::new("x", "HashMap<String, u8>").schema()
+        );
+        assert!(ParamInfo::new("x", "Vec<Option<u8>>").required);
+        assert!(!ParamInfo::new("x", "Option<Vec<u8>>").required);
+        // Not confused by similar names
+        assert!(ParamInfo::new("x", "Optional<u8>").required);
+    }
+
+    /// Generate a whole document.
+    #[test]
+    fn document() {
+        let methods = vec![
+            jsonrpc_method_info!(rpc "hello", "Greets", "name" => String, "n" => Option<u8>),
+            MethodInfo::notification("bye").prefixed("x."),
+        ];
+        let introspection = Introspection::new("Test".to_owned(), "1.0".to_owned());
+        let expected = json!({
+            "openrpc": OPENRPC_VERSION,
+            "info": {
+                "title": "Test",
+                "version": "1.0",
+            },
+            "methods": [
+                {
+                    "name": "hello",
+                    "description": "Greets",
+                    "params": [
+                        {
+                            "name": "name",
+                            "required": true,
+                            "schema": {"type": "string"},
+                        },
+                        {
+                            "name": "n",
+                            "required": false,
+                            "schema": {"type": "integer"},
+                        },
+                    ],
+                    "result": {
+                        "name": "result",
+                        "schema": {},
+                    },
+                },
+                {
+                    "name": "x.bye",
+                    "params": [],
+                },
+            ],
+        });
+        assert_eq!(expected, introspection.document(&methods));
+        let described = introspection.description("Testing API".to_owned());
+        assert_eq!(
+            json!("Testing API"),
+            described.document(&[])["info"]["description"]
+        );
+    }
+}
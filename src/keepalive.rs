@@ -0,0 +1,189 @@
+// Copyright 2017 tokio-jsonrpc Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Active detection of dead connections.
+//!
+//! A connection may die without either side noticing (eg. when a NAT forgets about it). If
+//! [`Endpoint::keepalive`](../endpoint/struct.Endpoint.html#method.keepalive) is set, the endpoint
+//! sends a ping to the other side periodically. If nothing arrives from the other side within a
+//! timeout after the ping, the connection is considered dead. It is killed and the future
+//! returned from [`Endpoint::start`](../endpoint/struct.Endpoint.html#method.start) resolves with
+//! a [`PingTimeout`](struct.PingTimeout.html) error.
+//!
+//! An endpoint with the keepalive answers the [`PING_METHOD`](constant.PING_METHOD.html) by
+//! itself, unless its server or an extension handles the method. An endpoint without the keepalive
+//! answers it with an error, but any answer is a sign of life, so the pings work against any other
+//! endpoint of this library.
+
+use std::cell::Cell;
+use std::error::Error;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::io::{Error as IoError, ErrorKind};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use futures::{Future, IntoFuture, Stream};
+use futures::unsync::mpsc::UnboundedSender;
+use futures::unsync::oneshot::{channel as one_channel, Sender as OneSender};
+use serde_json::Value;
+use slog::Logger;
+use tokio_core::reactor::{Handle, Interval, Timeout};
+
+use message::{Message, Request, Response};
+
+/// The method used for the pings by default.
+///
+/// It is answered automatically by every endpoint with the keepalive, both as an RPC (with
+/// `true`) and as a notification.
+pub const PING_METHOD: &str = "rpc.ping";
+
+/// Configuration of the keepalive pings.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Keepalive {
+    interval: Duration,
+    timeout: Duration,
+    method: String,
+    notification: bool,
+}
+
+impl Keepalive {
+    /// Pings each `interval` and expects something to arrive within `timeout` after the ping.
+    ///
+    /// By default, the pings are RPCs of the [`PING_METHOD`](constant.PING_METHOD.html).
+    pub fn new(interval: Duration, timeout: Duration) -> Self {
+        Keepalive {
+            interval,
+            timeout,
+            method: PING_METHOD.to_owned(),
+            notification: false,
+        }
+    }
+    /// Sets the method to ping with.
+    ///
+    /// The other side must answer it, unless it is the default one.
+    pub fn method(self, method: String) -> Self {
+        Keepalive { method, ..self }
+    }
+    /// Pings with notifications instead of RPCs.
+    ///
+    /// Notifications don't get answered, so the connection is considered alive only if the other
+    /// side sends something on its own within the timeout (eg. its own pings). This is useful when
+    /// both sides ping.
+    pub fn notification(self, notification: bool) -> Self {
+        Keepalive {
+            notification,
+            ..self
+        }
+    }
+}
+
+/// The error reported when a ping goes unanswered.
+///
+/// It is wrapped inside an `IoError` of the `TimedOut` kind, it can be recognized by
+/// downcasting the inner error.
+#[derive(Copy, Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct PingTimeout;
+
+impl Display for PingTimeout {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "Keepalive ping unanswered")
+    }
+}
+
+impl Error for PingTimeout {
+    fn description(&self) -> &str {
+        "Keepalive ping unanswered"
+    }
+}
+
+impl PingTimeout {
+    /// Checks if the error is caused by an unanswered ping.
+    pub fn is(error: &IoError) -> bool {
+        match error.get_ref() {
+            Some(inner) => inner.is::<PingTimeout>(),
+            None => false,
+        }
+    }
+}
+
+/// The parts of the endpoint the pinging needs.
+pub(crate) struct Pinger {
+    pub config: Keepalive,
+    pub handle: Handle,
+    pub logger: Logger,
+    /// Where to put the ping messages. The receiving end stops when the endpoint does.
+    pub pings: UnboundedSender<Message>,
+    /// Registers the ping RPCs, so their answers are expected.
+    pub register: Box<Fn(String, OneSender<Response>)>,
+    /// Stops expecting the answer of a ping RPC.
+    pub forget: Rc<Fn(&str)>,
+    /// When the last message arrived from the other side.
+    pub received: Rc<Cell<Instant>>,
+    /// Kills the connection with the error.
+    pub kill: Rc<Fn(IoError)>,
+}
+
+impl Pinger {
+    /// Starts pinging.
+    ///
+    /// It ends once the endpoint stops.
+    pub fn spawn(self) {
+        let interval = match Interval::new(self.config.interval, &self.handle) {
+            Ok(interval) => interval,
+            Err(e) => {
+                error!(self.logger, "Can't set up keepalive"; "error" => format!("{}", e));
+                return;
+            },
+        };
+        let handle = self.handle.clone();
+        let spawner = self.handle.clone();
+        let pinging = interval.map_err(|_| ()).for_each(move |()| {
+            let sent = Instant::now();
+            let mut ping_id = None;
+            let msg = if self.config.notification {
+                Message::notification(self.config.method.clone(), None)
+            } else {
+                let msg = Message::request(self.config.method.clone(), None);
+                if let Message::Request(Request {
+                    id: Value::String(ref id),
+                    ..
+                }) = msg
+                {
+                    // We don't care about the answer itself, only that something arrived.
+                    let (sender, _receiver) = one_channel();
+                    (self.register)(id.clone(), sender);
+                    ping_id = Some(id.clone());
+                }
+                msg
+            };
+            trace!(self.logger, "Sending keepalive ping");
+            // If the endpoint is gone, stop pinging
+            self.pings.unbounded_send(msg).map_err(|_| ())?;
+            let received = self.received.clone();
+            let kill = self.kill.clone();
+            let logger = self.logger.clone();
+            let forget = self.forget.clone();
+            let check = Timeout::new(self.config.timeout, &self.handle)
+                .into_future()
+                .flatten()
+                .then(move |_| {
+                    // The answer may never come if the other side ignores the pings
+                    if let Some(id) = ping_id {
+                        forget(&id);
+                    }
+                    if received.get() < sent {
+                        info!(logger, "Keepalive ping unanswered, killing the connection");
+                        kill(IoError::new(ErrorKind::TimedOut, PingTimeout));
+                    }
+                    Ok(())
+                });
+            handle.spawn(check);
+            Ok(())
+        });
+        spawner.spawn(pinging);
+    }
+}
@@ -15,6 +15,14 @@
 //! draft of the higher-lever API is in the [`endpoint`](endpoint/index.html) module. Some helpers
 //! to compose the server part is in the [`server`](server/index.html) module.
 //!
+//! If the `tracing` feature is enabled, the endpoint creates a
+//! [tracing](https://docs.rs/tracing) span for each incoming request and notification and for
+//! each outgoing call. The span carries the method, the id and the connection number and it is the
+//! current span while the corresponding server callback or future runs.
+//!
+//! The `tower` feature brings the [`tower`](tower/index.html) module, with adapters between servers
+//! and [tower](https://docs.rs/tower) services.
+//!
 //! # Examples
 //!
 //! A skeleton of reading messages from the other side, mapping them to answers and sending them
@@ -114,23 +122,44 @@
 //! ```
 
 extern crate serde;
-// We use the json! macro only in the tests
 extern crate bytes;
 extern crate futures;
+#[cfg(feature = "tower")]
+extern crate futures_util;
 #[macro_use]
 extern crate serde_derive;
-#[cfg_attr(test, macro_use)]
+#[macro_use]
 extern crate serde_json;
 #[macro_use]
 extern crate slog;
 extern crate tokio_core;
 extern crate tokio_io;
+#[cfg(feature = "tower")]
+extern crate tower_service;
+#[cfg(feature = "tracing")]
+extern crate tracing;
 extern crate uuid;
 
+pub mod auth;
+pub mod batch;
 pub mod codec;
 pub mod endpoint;
+pub mod introspection;
+pub mod keepalive;
+pub mod limit;
+pub mod memory;
 pub mod message;
+pub mod metrics;
+pub mod middleware;
+pub mod mock;
+pub mod pubsub;
+pub mod rate;
+pub mod reconnect;
+pub mod record;
 pub mod server;
+#[cfg(feature = "tower")]
+pub mod tower;
+mod trace;
 
 /// This contains some reexports so macros can find them.
 ///
@@ -0,0 +1,328 @@
+// Copyright 2017 tokio-jsonrpc Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Limits of how many calls run at once.
+//!
+//! The [`Endpoint::parallel`](../endpoint/struct.Endpoint.html#method.parallel) limits how many
+//! incoming messages are processed at once, but a slow method may take all of these slots and a
+//! batch counts as a single message, no matter how many calls it contains. The
+//! [`Limits`](struct.Limits.html), set by
+//! [`Endpoint::limits`](../endpoint/struct.Endpoint.html#method.limits), count the running
+//! handlers of the server instead ‒ of both the RPCs and the notifications, whether they come
+//! alone or in a batch.
+//!
+//! A call over a limit waits until a slot frees up (the calls are started in the order they came)
+//! or it is rejected right away, if configured so.
+//!
+//! # Examples
+//!
+//! ```rust
+//! # extern crate tokio_jsonrpc;
+//! #
+//! # use tokio_jsonrpc::RpcError;
+//! # use tokio_jsonrpc::limit::Limits;
+//! #
+//! # fn main() {
+//! // At most one report at a time, two exports and 16 handlers in total
+//! let limits = Limits::new()
+//!     .method("report", 1)
+//!     .group(vec!["export.csv", "export.pdf"], 2)
+//!     .global(16);
+//! // The same, but answering with an error instead of waiting
+//! let busy = RpcError::new(-32_000, "Server busy".to_owned(), None);
+//! let rejecting = limits.clone().reject(busy);
+//! # }
+//! ```
+
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::rc::Rc;
+
+use futures::{Future, IntoFuture, Stream};
+use futures::stream::iter_ok;
+use futures::unsync::oneshot::{channel as one_channel, Sender as OneSender};
+
+use message::RpcError;
+
+/// Configuration of the limits of concurrently running calls.
+///
+/// By default, there are no limits.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Limits {
+    global: Option<usize>,
+    groups: Vec<(Vec<String>, usize)>,
+    reject: Option<RpcError>,
+}
+
+impl Limits {
+    /// Creates the configuration without any limits.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Limits the number of all calls running at once.
+    ///
+    /// A call limited by its method takes a slot of this limit only once it is allowed to run by
+    /// the limit of the method, so the waiting calls of a slow method don't block the others.
+    ///
+    /// # Panics
+    ///
+    /// If `limit` is 0.
+    pub fn global(self, limit: usize) -> Self {
+        assert!(limit > 0, "The limit needs to allow at least one call");
+        Limits {
+            global: Some(limit),
+            ..self
+        }
+    }
+    /// Limits the number of calls of a single method running at once.
+    ///
+    /// # Panics
+    ///
+    /// If `limit` is 0.
+    pub fn method(self, method: &str, limit: usize) -> Self {
+        self.group(Some(method), limit)
+    }
+    /// Limits the number of calls of a group of methods running at once.
+    ///
+    /// The methods of the group share the limit. A method belongs only to the first group it is
+    /// listed in.
+    ///
+    /// # Panics
+    ///
+    /// If `limit` is 0.
+    pub fn group<I, S>(mut self, methods: I, limit: usize) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        assert!(limit > 0, "The limit needs to allow at least one call");
+        let methods = methods.into_iter().map(Into::into).collect();
+        self.groups.push((methods, limit));
+        self
+    }
+    /// Rejects the calls over the limit instead of queueing them.
+    ///
+    /// The RPCs are answered with the given error, the notifications are dropped.
+    pub fn reject(self, error: RpcError) -> Self {
+        Limits {
+            reject: Some(error),
+            ..self
+        }
+    }
+}
+
+/// The state of one limit.
+struct State {
+    available: usize,
+    waiting: VecDeque<OneSender<Slot>>,
+}
+
+/// One limit, shared by everything it applies to.
+#[derive(Clone)]
+struct Semaphore(Rc<RefCell<State>>);
+
+impl Semaphore {
+    fn new(limit: usize) -> Self {
+        Semaphore(Rc::new(RefCell::new(State {
+            available: limit,
+            waiting: VecDeque::new(),
+        })))
+    }
+    /// Takes a slot, if there's one free right now.
+    fn try_acquire(&self) -> Option<Slot> {
+        let mut state = self.0.borrow_mut();
+        if state.available > 0 {
+            state.available -= 1;
+            Some(Slot(Some(self.clone())))
+        } else {
+            None
+        }
+    }
+    /// Waits in the queue for a slot.
+    fn acquire(&self) -> Box<Future<Item = Slot, Error = ()>> {
+        if let Some(slot) = self.try_acquire() {
+            return Box::new(Ok(slot).into_future());
+        }
+        let (sender, receiver) = one_channel();
+        self.0.borrow_mut().waiting.push_back(sender);
+        Box::new(receiver.map_err(|_| ()))
+    }
+}
+
+/// A taken slot of a limit, returned once dropped.
+struct Slot(Option<Semaphore>);
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        let semaphore = match self.0.take() {
+            Some(semaphore) => semaphore,
+            None => return,
+        };
+        loop {
+            // Don't keep it borrowed while sending, the failed slot gets dropped
+            let waiting = semaphore.0.borrow_mut().waiting.pop_front();
+            match waiting {
+                // Pass the slot directly to the next one, so nobody can overtake it
+                Some(waiting) => match waiting.send(Slot(Some(semaphore.clone()))) {
+                    Ok(()) => return,
+                    // That one is no longer interested, so the slot is not taken
+                    Err(mut slot) => {
+                        slot.0.take();
+                    },
+                },
+                None => {
+                    semaphore.0.borrow_mut().available += 1;
+                    return;
+                },
+            }
+        }
+    }
+}
+
+/// The permission for a call to run.
+///
+/// The call runs as long as it holds this.
+// The slots are only held, to be returned once dropped
+#[allow(dead_code)]
+pub(crate) struct Permit(Vec<Slot>);
+
+/// The decision about a call.
+pub(crate) enum Admission {
+    Now(Permit),
+    Later(Box<Future<Item = Permit, Error = ()>>),
+    Rejected(RpcError),
+}
+
+/// The limits of an endpoint, in action.
+pub(crate) struct Limiter {
+    global: Option<Semaphore>,
+    methods: HashMap<String, Semaphore>,
+    reject: Option<RpcError>,
+}
+
+impl Limiter {
+    pub fn new(limits: Limits) -> Self {
+        let mut methods = HashMap::new();
+        for (group, limit) in limits.groups {
+            let semaphore = Semaphore::new(limit);
+            for method in group {
+                methods.entry(method).or_insert_with(|| semaphore.clone());
+            }
+        }
+        Limiter {
+            global: limits.global.map(Semaphore::new),
+            methods,
+            reject: limits.reject,
+        }
+    }
+    /// Decides when a call of the method may run.
+    pub fn admit(&self, method: &str) -> Admission {
+        // The method limit goes first, so the calls waiting for it don't hold a global slot
+        let semaphores: Vec<_> = self.methods
+            .get(method)
+            .into_iter()
+            .chain(self.global.as_ref())
+            .cloned()
+            .collect();
+        let mut slots = Vec::with_capacity(semaphores.len());
+        for semaphore in &semaphores {
+            match semaphore.try_acquire() {
+                Some(slot) => slots.push(slot),
+                None => break,
+            }
+        }
+        if slots.len() == semaphores.len() {
+            return Admission::Now(Permit(slots));
+        }
+        // Return the partially taken ones, so they are not blocked by the waiting
+        drop(slots);
+        match self.reject {
+            Some(ref error) => Admission::Rejected(error.clone()),
+            None => {
+                let slots = iter_ok(semaphores)
+                    .and_then(|semaphore| semaphore.acquire())
+                    .collect()
+                    .map(Permit);
+                Admission::Later(Box::new(slots))
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio_core::reactor::Core;
+
+    use super::*;
+
+    /// The slots are handed to the waiting ones in order and the ones that gave up are skipped.
+    #[test]
+    fn queue() {
+        let mut core = Core::new().unwrap();
+        let semaphore = Semaphore::new(1);
+        let first = semaphore.try_acquire().unwrap();
+        assert!(semaphore.try_acquire().is_none());
+        let second = semaphore.acquire();
+        let gave_up = semaphore.acquire();
+        let third = semaphore.acquire();
+        drop(gave_up);
+        drop(first);
+        let second = core.run(second).unwrap();
+        assert!(semaphore.try_acquire().is_none());
+        drop(second);
+        let third = core.run(third).unwrap();
+        drop(third);
+        // Once nobody waits, the slot is free again
+        let free = semaphore.try_acquire();
+        assert!(free.is_some());
+        assert!(semaphore.try_acquire().is_none());
+    }
+
+    /// A limit that would never let anything run is refused.
+    #[test]
+    #[should_panic]
+    fn zero_limit() {
+        Limits::new().method("never", 0);
+    }
+
+    /// The methods take slots of their group and of the global limit.
+    #[test]
+    fn admit() {
+        let busy = RpcError::new(-32_000, "Busy".to_owned(), None);
+        let limiter = Limiter::new(
+            Limits::new()
+                .group(vec!["a", "b"], 1)
+                .global(2)
+                .reject(busy.clone()),
+        );
+        let a = match limiter.admit("a") {
+            Admission::Now(permit) => permit,
+            _ => panic!("Not admitted"),
+        };
+        // The group is full
+        match limiter.admit("b") {
+            Admission::Rejected(error) => assert_eq!(busy, error),
+            _ => panic!("Not rejected"),
+        }
+        // But there's still a global slot
+        let other = match limiter.admit("c") {
+            Admission::Now(permit) => permit,
+            _ => panic!("Not admitted"),
+        };
+        match limiter.admit("d") {
+            Admission::Rejected(_) => (),
+            _ => panic!("Not rejected"),
+        }
+        drop(a);
+        // The rejected call of the group didn't take the global slot
+        match limiter.admit("b") {
+            Admission::Now(_) => (),
+            _ => panic!("Not admitted"),
+        }
+        drop(other);
+    }
+}
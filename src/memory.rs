@@ -0,0 +1,333 @@
+// Copyright 2017 tokio-jsonrpc Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! An in-memory connection.
+//!
+//! This provides two connected halves, each usable as the connection of an
+//! [`Endpoint`](../endpoint/struct.Endpoint.html). It is meant mostly for tests, so two endpoints
+//! can be wired together without sockets.
+//!
+//! The messages are serialized and parsed again on the way, so the other side sees them exactly
+//! as if they went over a real connection. Optionally, the connection may misbehave ‒ delay,
+//! lose or reorder the messages ‒ see the [`Pair`](struct.Pair.html) builder.
+//!
+//! # Examples
+//!
+//! ```rust
+//! # extern crate futures;
+//! # extern crate tokio_core;
+//! # extern crate tokio_jsonrpc;
+//! # #[macro_use]
+//! # extern crate serde_json;
+//! #
+//! # use futures::Future;
+//! # use tokio_core::reactor::Core;
+//! # use tokio_jsonrpc::{Endpoint, RpcError, Server, ServerCtl};
+//! # use tokio_jsonrpc::memory;
+//! # use serde_json::Value;
+//! #
+//! struct Hello;
+//!
+//! impl Server for Hello {
+//!     type Success = String;
+//!     type RpcCallResult = Result<String, RpcError>;
+//!     type NotificationResult = Result<(), ()>;
+//!     fn rpc(&self, ctl: &ServerCtl, method: &str, _params: &Option<Value>)
+//!         -> Option<Self::RpcCallResult> {
+//!         ctl.terminate();
+//!         Some(Ok(format!("Hello from {}", method)))
+//!     }
+//! }
+//!
+//! # fn main() {
+//! let mut core = Core::new().unwrap();
+//! let handle = core.handle();
+//! let (left, right) = memory::pair();
+//! // Drop the server's own client right away, so the server can finish
+//! let (_, server_finished) = Endpoint::new(left, Hello).start(&handle);
+//! let (client, _finished) = Endpoint::client_only(right).start(&handle);
+//! let answered = client.call("test".to_owned(), None, None)
+//!     .and_then(|(_client, answered)| answered);
+//! let (response, ()) = core.run(answered.join(server_finished)).unwrap();
+//! assert_eq!(json!("Hello from test"), response.unwrap().result.unwrap());
+//! # }
+//! ```
+
+use std::collections::VecDeque;
+use std::io::{Error as IoError, ErrorKind};
+use std::time::{Duration, Instant};
+
+use futures::{Async, AsyncSink, Future, Poll, Sink, StartSend, Stream};
+use futures::unsync::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use serde_json::to_string;
+use tokio_core::reactor::{Handle, Timeout};
+
+use message::{from_str, Message, Parsed};
+
+/// How the connection misbehaves.
+#[derive(Clone)]
+struct Faults {
+    latency: Option<(Duration, Handle)>,
+    drop_every: Option<usize>,
+    reorder: bool,
+}
+
+/// A builder of a connected pair of [`Memory`](struct.Memory.html) halves.
+///
+/// By default, the connection is perfect ‒ the messages arrive right away, all of them and in
+/// order. The misbehaviour is deterministic, so the tests using it are repeatable. It applies to
+/// both directions.
+#[derive(Clone)]
+pub struct Pair(Faults);
+
+impl Pair {
+    /// Creates the builder of a perfect connection.
+    pub fn new() -> Self {
+        Pair(Faults {
+            latency: None,
+            drop_every: None,
+            reorder: false,
+        })
+    }
+    /// Delays each message by the given time.
+    ///
+    /// The handle is used to schedule the delivery.
+    pub fn latency(self, latency: Duration, handle: &Handle) -> Self {
+        Pair(Faults {
+            latency: Some((latency, handle.clone())),
+            ..self.0
+        })
+    }
+    /// Loses every n-th message (counted separately in each direction).
+    ///
+    /// # Panics
+    ///
+    /// If `n` is 0.
+    pub fn drop_every(self, n: usize) -> Self {
+        assert!(n > 0, "Can't drop every 0th message");
+        Pair(Faults {
+            drop_every: Some(n),
+            ..self.0
+        })
+    }
+    /// Reorders the messages.
+    ///
+    /// If more messages are ready to be received at once, the last one goes first. A lone message
+    /// is never held back to wait for another one, so this doesn't stall the connection.
+    pub fn reorder(self, reorder: bool) -> Self {
+        Pair(Faults { reorder, ..self.0 })
+    }
+    /// Creates the connected halves.
+    pub fn build(self) -> (Memory, Memory) {
+        let (left_sender, right_receiver) = unbounded();
+        let (right_sender, left_receiver) = unbounded();
+        (
+            Memory::new(left_sender, left_receiver, self.0.clone()),
+            Memory::new(right_sender, right_receiver, self.0),
+        )
+    }
+}
+
+impl Default for Pair {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Creates a pair of perfectly connected halves.
+///
+/// This is a shortcut for `Pair::new().build()`.
+pub fn pair() -> (Memory, Memory) {
+    Pair::new().build()
+}
+
+/// A message on the way, with the time it is to be delivered.
+type InFlight = (Instant, Parsed);
+
+/// One half of the in-memory connection.
+///
+/// It can be used as the connection of an [`Endpoint`](../endpoint/struct.Endpoint.html).
+/// Dropping (or closing) it ends the stream of the other half.
+pub struct Memory {
+    sender: Option<UnboundedSender<InFlight>>,
+    receiver: UnboundedReceiver<InFlight>,
+    /// Messages already taken from the receiver, but not delivered yet.
+    buffer: VecDeque<InFlight>,
+    /// If the receiver ended.
+    eos: bool,
+    /// Waits for the first message in the buffer to become due.
+    timer: Option<Timeout>,
+    faults: Faults,
+    /// Messages sent since the last dropped one.
+    sent: usize,
+}
+
+impl Memory {
+    fn new(
+        sender: UnboundedSender<InFlight>, receiver: UnboundedReceiver<InFlight>, faults: Faults
+    ) -> Self {
+        Memory {
+            sender: Some(sender),
+            receiver,
+            buffer: VecDeque::new(),
+            eos: false,
+            timer: None,
+            faults,
+            sent: 0,
+        }
+    }
+}
+
+impl Stream for Memory {
+    type Item = Parsed;
+    type Error = IoError;
+    fn poll(&mut self) -> Poll<Option<Parsed>, IoError> {
+        loop {
+            // Take everything that has arrived so far
+            while !self.eos {
+                match self.receiver.poll() {
+                    Ok(Async::Ready(Some(item))) => self.buffer.push_back(item),
+                    Ok(Async::Ready(None)) | Err(()) => self.eos = true,
+                    Ok(Async::NotReady) => break,
+                }
+            }
+            let now = Instant::now();
+            // All the messages have the same latency, so the due ones are at the front
+            let due = self.buffer
+                .iter()
+                .take_while(|&&(at, _)| at <= now)
+                .count();
+            if due > 0 {
+                self.timer.take();
+                let idx = if self.faults.reorder { due - 1 } else { 0 };
+                let (_, parsed) = self.buffer.remove(idx).unwrap();
+                return Ok(Async::Ready(Some(parsed)));
+            }
+            let at = match self.buffer.front() {
+                Some(&(at, _)) => at,
+                None if self.eos => return Ok(Async::Ready(None)),
+                None => return Ok(Async::NotReady),
+            };
+            if self.timer.is_none() {
+                // There's latency, otherwise the message would be due already
+                let handle = &self.faults.latency.as_ref().unwrap().1;
+                self.timer = Some(Timeout::new_at(at, handle)?);
+            }
+            match self.timer.as_mut().unwrap().poll()? {
+                Async::Ready(()) => self.timer = None,
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+        }
+    }
+}
+
+impl Sink for Memory {
+    type SinkItem = Message;
+    type SinkError = IoError;
+    fn start_send(&mut self, msg: Message) -> StartSend<Message, IoError> {
+        if let Some(n) = self.faults.drop_every {
+            self.sent += 1;
+            if self.sent == n {
+                self.sent = 0;
+                return Ok(AsyncSink::Ready);
+            }
+        }
+        let sender = match self.sender {
+            Some(ref sender) => sender,
+            None => return Err(IoError::new(ErrorKind::BrokenPipe, "Already closed")),
+        };
+        let encoded = to_string(&msg).expect("Messages are always serializable");
+        let at = match self.faults.latency {
+            Some((latency, _)) => Instant::now() + latency,
+            None => Instant::now(),
+        };
+        sender
+            .unbounded_send((at, from_str(&encoded)))
+            .map_err(|_| IoError::new(ErrorKind::BrokenPipe, "The other half is gone"))?;
+        Ok(AsyncSink::Ready)
+    }
+    fn poll_complete(&mut self) -> Poll<(), IoError> {
+        Ok(Async::Ready(()))
+    }
+    fn close(&mut self) -> Poll<(), IoError> {
+        self.sender.take();
+        Ok(Async::Ready(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio_core::reactor::Core;
+
+    use super::*;
+
+    fn numbers(count: usize) -> Vec<Message> {
+        (0..count)
+            .map(|i| Message::notification(format!("n{}", i), None))
+            .collect()
+    }
+
+    fn names(received: Vec<Parsed>) -> Vec<String> {
+        received
+            .into_iter()
+            .map(|parsed| match parsed {
+                Ok(Message::Notification(notification)) => notification.method,
+                other => panic!("Unexpected message {:?}", other),
+            })
+            .collect()
+    }
+
+    /// Send some messages through the given pair and collect them on the other side.
+    fn transfer(core: &mut Core, pair: Pair, count: usize) -> Vec<String> {
+        let (left, right) = pair.build();
+        let sent = left.send_all(::futures::stream::iter_ok::<_, IoError>(numbers(count)));
+        // Dropping the left half after sending ends the right stream
+        let received = sent.map(drop).and_then(|()| right.collect());
+        names(core.run(received).unwrap())
+    }
+
+    /// The perfect connection delivers everything in order.
+    #[test]
+    fn perfect() {
+        let mut core = Core::new().unwrap();
+        assert_eq!(
+            vec!["n0", "n1", "n2"],
+            transfer(&mut core, Pair::new(), 3)
+        );
+    }
+
+    /// Every n-th message gets lost.
+    #[test]
+    fn drops() {
+        let mut core = Core::new().unwrap();
+        assert_eq!(
+            vec!["n0", "n2", "n4"],
+            transfer(&mut core, Pair::new().drop_every(2), 6)
+        );
+    }
+
+    /// The messages ready at once arrive in reverse.
+    #[test]
+    fn reorder() {
+        let mut core = Core::new().unwrap();
+        assert_eq!(
+            vec!["n2", "n1", "n0"],
+            transfer(&mut core, Pair::new().reorder(true), 3)
+        );
+    }
+
+    /// The messages arrive only after the latency.
+    #[test]
+    fn latency() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let start = Instant::now();
+        let pair = Pair::new().latency(Duration::from_millis(50), &handle);
+        assert_eq!(vec!["n0", "n1"], transfer(&mut core, pair, 2));
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+}
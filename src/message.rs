@@ -13,32 +13,73 @@
 use std::fmt::{Formatter, Result as FmtResult};
 
 use serde::ser::{Serialize, SerializeStruct, Serializer};
-use serde::de::{Deserialize, Deserializer, Error, Unexpected, Visitor};
-use serde_json::{to_value, Result as JsonResult, Value};
+use serde::de::{Deserialize, Deserializer, Error, MapAccess, SeqAccess, Unexpected, Visitor};
+use serde::de::value::SeqAccessDeserializer;
+use serde_json::{from_str as json_from_str, to_value, Map, Result as JsonResult, Value};
+use serde_json::value::{to_raw_value, RawValue};
 use uuid::Uuid;
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
-struct Version;
+/// The prefix of method names reserved by the specification for extensions.
+pub const RESERVED_PREFIX: &str = "rpc.";
 
-impl Serialize for Version {
+/// Checks if the method name is reserved for the extensions.
+///
+/// The specification reserves all method names starting with `rpc.` for system extensions and
+/// says these must not be used for anything else.
+pub fn is_reserved(method: &str) -> bool {
+    method.starts_with(RESERVED_PREFIX)
+}
+
+/// The version of the protocol a message uses.
+///
+/// Messages of this library are JSON-RPC 2.0, unless an [`Endpoint`] is set to accept the 1.0
+/// ones too (see [`Endpoint::version`](../endpoint/struct.Endpoint.html#method.version)) or the
+/// version is changed with [`Message::with_version`](enum.Message.html#method.with_version). A
+/// response uses the version of its request.
+///
+/// The 1.0 messages have no `jsonrpc` field, their responses contain both the `result` and the
+/// `error` (one of them null) and notifications are requests with a null ID.
+///
+/// [`Endpoint`]: ../endpoint/struct.Endpoint.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Version {
+    /// The JSON-RPC 1.0.
+    V1,
+    /// The JSON-RPC 2.0.
+    V2,
+}
+
+impl Default for Version {
+    /// The 2.0, used unless asked otherwise.
+    fn default() -> Self {
+        Version::V2
+    }
+}
+
+/// The `jsonrpc` field on the wire.
+///
+/// Only the 2.0 version has the field, the 1.0 messages leave it out.
+struct V2Field;
+
+impl Serialize for V2Field {
     fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
         serializer.serialize_str("2.0")
     }
 }
 
-impl<'de> Deserialize<'de> for Version {
+impl<'de> Deserialize<'de> for V2Field {
     fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
         struct VersionVisitor;
         impl<'de> Visitor<'de> for VersionVisitor {
-            type Value = Version;
+            type Value = V2Field;
 
             fn expecting(&self, formatter: &mut Formatter) -> FmtResult {
                 formatter.write_str("a version string")
             }
 
-            fn visit_str<E: Error>(self, value: &str) -> Result<Version, E> {
+            fn visit_str<E: Error>(self, value: &str) -> Result<V2Field, E> {
                 match value {
-                    "2.0" => Ok(Version),
+                    "2.0" => Ok(V2Field),
                     _ => Err(E::invalid_value(Unexpected::Str(value), &"value 2.0")),
                 }
             }
@@ -47,24 +88,124 @@ impl<'de> Deserialize<'de> for Version {
     }
 }
 
+/// Deserializes the `jsonrpc` field of a message, which is always 2.0.
+fn v2_field<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Version, D::Error> {
+    V2Field::deserialize(deserializer).map(|V2Field| Version::V2)
+}
+
+/// A piece of JSON, kept as the raw text.
+///
+/// It is used for the parameters of incoming calls ([`RawParams`](type.RawParams.html)) and for
+/// pre-serialized results of outgoing responses (see
+/// [`Request::reply_serialized`](struct.Request.html#method.reply_serialized)). Unlike `Value`,
+/// no tree is built, the text is deserialized only once it is needed, directly into whatever
+/// type is wanted, and serialized verbatim.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RawJson(Box<RawValue>);
+
+impl RawJson {
+    /// Wraps a valid JSON text.
+    pub fn from_string(json: String) -> JsonResult<Self> {
+        RawValue::from_string(json).map(RawJson)
+    }
+    /// Serializes the value into the JSON text.
+    pub fn serialize<T: Serialize>(value: &T) -> JsonResult<Self> {
+        to_raw_value(value).map(RawJson)
+    }
+    /// Provides the JSON text.
+    pub fn get(&self) -> &str {
+        self.0.get()
+    }
+    /// Deserializes the JSON into the given type.
+    pub fn parse<'de, T: Deserialize<'de>>(&'de self) -> JsonResult<T> {
+        json_from_str(self.get())
+    }
+    /// Converts the JSON into a `Value`.
+    pub fn to_value(&self) -> Value {
+        self.parse().expect("Raw JSON is always valid")
+    }
+}
+
+impl PartialEq for RawJson {
+    fn eq(&self, other: &RawJson) -> bool {
+        self.get() == other.get()
+    }
+}
+
+/// Parameters of a request or notification, kept as the raw JSON text.
+///
+/// These are produced by [`from_slice_raw`](fn.from_slice_raw.html) (and the
+/// [`RawLine`](../codec/struct.RawLine.html) codec). The parameters are deserialized only once
+/// they are needed, directly into whatever type the handler wants.
+pub type RawParams = RawJson;
+
 /// An RPC request.
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+///
+/// The parameters are usually parsed into a `Value`, but they may also be kept as
+/// [`RawParams`](type.RawParams.html).
+#[derive(Debug, Deserialize, Clone, PartialEq)]
 #[serde(deny_unknown_fields)]
-pub struct Request {
+pub struct Request<Params = Value> {
+    #[serde(deserialize_with = "v2_field")]
     jsonrpc: Version,
     pub method: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub params: Option<Value>,
+    pub params: Option<Params>,
     pub id: Value,
 }
 
-impl Request {
+/// Serializes the fields common to requests and notifications.
+///
+/// The params must always be present in 1.0, as an array.
+fn serialize_call<S: SerializeStruct, Params: Serialize>(
+    sub: &mut S, version: Version, method: &str, params: &Option<Params>
+) -> Result<(), S::Error> {
+    if version == Version::V2 {
+        sub.serialize_field("jsonrpc", &V2Field)?;
+    }
+    sub.serialize_field("method", method)?;
+    match (version, params.as_ref()) {
+        (_, Some(params)) => sub.serialize_field("params", params),
+        (Version::V1, None) => sub.serialize_field("params", &[] as &[Value]),
+        (Version::V2, None) => Ok(()),
+    }
+}
+
+impl<Params: Serialize> Serialize for Request<Params> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut sub = serializer.serialize_struct("Request", 4)?;
+        serialize_call(&mut sub, self.jsonrpc, &self.method, &self.params)?;
+        sub.serialize_field("id", &self.id)?;
+        sub.end()
+    }
+}
+
+impl<Params> Request<Params> {
+    /// The version of the protocol the request uses.
+    pub fn version(&self) -> Version {
+        self.jsonrpc
+    }
     /// Answer the request with a (positive) reply.
     ///
     /// The ID is taken from the request.
     pub fn reply(&self, reply: Value) -> Message {
         Message::Response(Response {
-            jsonrpc: Version,
+            jsonrpc: self.jsonrpc,
+            result: Ok(reply),
+            id: self.id.clone(),
+        })
+    }
+    /// Answer the request with a (positive) reply, serialized right away.
+    ///
+    /// Unlike [`reply`](#method.reply), this doesn't build a `Value` of the reply. It is
+    /// serialized into the JSON text directly and sent as it is, which is cheaper for large
+    /// replies. It fails if the reply can't be represented as JSON.
+    pub fn reply_serialized<T: Serialize>(&self, reply: &T) -> JsonResult<Message> {
+        Ok(self.reply_raw(RawJson::serialize(reply)?))
+    }
+    /// Answer the request with an already serialized (positive) reply.
+    pub fn reply_raw(&self, reply: RawJson) -> Message {
+        Message::RawResponse(Response {
+            jsonrpc: self.jsonrpc,
             result: Ok(reply),
             id: self.id.clone(),
         })
@@ -72,13 +213,24 @@ impl Request {
     /// Answer the request with an error.
     pub fn error(&self, error: RpcError) -> Message {
         Message::Response(Response {
-            jsonrpc: Version,
+            jsonrpc: self.jsonrpc,
             result: Err(error),
             id: self.id.clone(),
         })
     }
 }
 
+/// The error code of an invalid JSON.
+pub const PARSE_ERROR: i64 = -32_700;
+/// The error code of a message that is not a valid request.
+pub const INVALID_REQUEST: i64 = -32_600;
+/// The error code of an unknown method.
+pub const METHOD_NOT_FOUND: i64 = -32_601;
+/// The error code of invalid method parameters.
+pub const INVALID_PARAMS: i64 = -32_602;
+/// The error code of a generic server error.
+pub const SERVER_ERROR: i64 = -32_000;
+
 /// An error code.
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 #[serde(deny_unknown_fields)]
@@ -102,28 +254,28 @@ impl RpcError {
     }
     /// Create an Invalid Param error.
     pub fn invalid_params(msg: Option<String>) -> Self {
-        RpcError::new(-32_602, "Invalid params".to_owned(), msg.map(Value::String))
+        RpcError::new(INVALID_PARAMS, "Invalid params".to_owned(), msg.map(Value::String))
     }
     /// Create a server error.
     pub fn server_error<E: Serialize>(e: Option<E>) -> Self {
         RpcError::new(
-            -32_000,
+            SERVER_ERROR,
             "Server error".to_owned(),
             e.map(|v| to_value(v).expect("Must be representable in JSON")),
         )
     }
     /// Create an invalid request error.
     pub fn invalid_request() -> Self {
-        RpcError::new(-32_600, "Invalid request".to_owned(), None)
+        RpcError::new(INVALID_REQUEST, "Invalid request".to_owned(), None)
     }
     /// Create a parse error.
     pub fn parse_error(e: String) -> Self {
-        RpcError::new(-32_700, "Parse error".to_owned(), Some(Value::String(e)))
+        RpcError::new(PARSE_ERROR, "Parse error".to_owned(), Some(Value::String(e)))
     }
     /// Create a method not found error.
     pub fn method_not_found(method: String) -> Self {
         RpcError::new(
-            -32_601,
+            METHOD_NOT_FOUND,
             "Method not found".to_owned(),
             Some(Value::String(method)),
         )
@@ -132,21 +284,57 @@ impl RpcError {
 
 /// A response to an RPC.
 ///
-/// It is created by the methods on [Request](struct.Request.html).
+/// It is created by the methods on [Request](struct.Request.html). The result is usually a
+/// `Value`, but it may also be pre-serialized into [`RawJson`](struct.RawJson.html).
 #[derive(Debug, Clone, PartialEq)]
-pub struct Response {
+pub struct Response<Success = Value> {
     jsonrpc: Version,
-    pub result: Result<Value, RpcError>,
+    pub result: Result<Success, RpcError>,
     pub id: Value,
 }
 
-impl Serialize for Response {
+impl<Success> Response<Success> {
+    /// The version of the protocol the response uses.
+    pub fn version(&self) -> Version {
+        self.jsonrpc
+    }
+}
+
+impl Response<RawJson> {
+    /// Converts the pre-serialized result into a `Value`.
+    ///
+    /// This is the response as the other side sees it.
+    pub fn parsed(&self) -> Response {
+        Response {
+            jsonrpc: self.jsonrpc,
+            result: self.result.as_ref().map(RawJson::to_value).map_err(Clone::clone),
+            id: self.id.clone(),
+        }
+    }
+}
+
+impl<Success: Serialize> Serialize for Response<Success> {
     fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
         let mut sub = serializer.serialize_struct("Response", 3)?;
-        sub.serialize_field("jsonrpc", &self.jsonrpc)?;
-        match self.result {
-            Ok(ref value) => sub.serialize_field("result", value),
-            Err(ref err) => sub.serialize_field("error", err),
+        match self.jsonrpc {
+            // Both the result and the error are present in 1.0, one of them null
+            Version::V1 => match self.result {
+                Ok(ref value) => {
+                    sub.serialize_field("result", value)?;
+                    sub.serialize_field("error", &Value::Null)
+                },
+                Err(ref err) => {
+                    sub.serialize_field("result", &Value::Null)?;
+                    sub.serialize_field("error", err)
+                },
+            },
+            Version::V2 => {
+                sub.serialize_field("jsonrpc", &V2Field)?;
+                match self.result {
+                    Ok(ref value) => sub.serialize_field("result", value),
+                    Err(ref err) => sub.serialize_field("error", err),
+                }
+            },
         }?;
         sub.serialize_field("id", &self.id)?;
         sub.end()
@@ -167,7 +355,7 @@ fn some_value<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Value
 struct WireResponse {
     // It is actually used to eat and sanity check the deserialized text
     #[allow(dead_code)]
-    jsonrpc: Version,
+    jsonrpc: V2Field,
     // Make sure we accept null as Some(Value::Null), instead of going to None
     #[serde(default, deserialize_with = "some_value")]
     result: Option<Value>,
@@ -191,7 +379,7 @@ impl<'de> Deserialize<'de> for Response {
             },
         };
         Ok(Response {
-            jsonrpc: Version,
+            jsonrpc: Version::V2,
             result,
             id: wr.id,
         })
@@ -199,13 +387,48 @@ impl<'de> Deserialize<'de> for Response {
 }
 
 /// A notification (doesn't expect an answer).
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+///
+/// Like with the [`Request`](struct.Request.html), the parameters may be kept raw.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
 #[serde(deny_unknown_fields)]
-pub struct Notification {
+pub struct Notification<Params = Value> {
+    #[serde(deserialize_with = "v2_field")]
     jsonrpc: Version,
     pub method: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub params: Option<Value>,
+    pub params: Option<Params>,
+}
+
+impl<Params: Serialize> Serialize for Notification<Params> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut sub = serializer.serialize_struct("Notification", 4)?;
+        serialize_call(&mut sub, self.jsonrpc, &self.method, &self.params)?;
+        // A notification is a request with a null ID in 1.0
+        if self.jsonrpc == Version::V1 {
+            sub.serialize_field("id", &Value::Null)?;
+        }
+        sub.end()
+    }
+}
+
+impl<Params> Notification<Params> {
+    /// The version of the protocol the notification uses.
+    pub fn version(&self) -> Version {
+        self.jsonrpc
+    }
+}
+
+impl Notification {
+    /// Creates a notification.
+    ///
+    /// Unlike [`Message::notification`](enum.Message.html#method.notification), this doesn't wrap
+    /// it into a `Message`.
+    pub fn new(method: String, params: Option<Value>) -> Self {
+        Notification {
+            jsonrpc: Version::V2,
+            method,
+            params,
+        }
+    }
 }
 
 /// One message of the JSON RPC protocol.
@@ -221,7 +444,11 @@ pub struct Notification {
 /// The `UnmatchedSub` variant is used when a request is an array and some of the subrequests
 /// aren't recognized as valid json rpc 2.0 messages. This is never returned as a top-level
 /// element, it is returned as `Err(Broken::Unmatched)`.
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+///
+/// When deserializing, the kind of the message is decided from the fields present (`method`,
+/// `id`, `result` and `error`), so each message is examined only once. Use
+/// [`from_slice`](fn.from_slice.html) to also learn why a message is not valid.
+#[derive(Debug, Clone, PartialEq, Serialize)]
 #[serde(untagged)]
 pub enum Message {
     /// An RPC request.
@@ -240,10 +467,26 @@ pub enum Message {
     /// An unmatched sub entry in a `Batch`.
     ///
     /// When there's a `Batch` and an element doesn't comform to the JSONRPC 2.0 format, that one
-    /// is represented by this. This is never produced as a top-level value when parsing, the
-    /// `Err(Broken::Unmatched)` is used instead. It is not possible to serialize.
+    /// is represented by this, together with the reason why it doesn't match. This is never
+    /// produced as a top-level value when parsing, the `Err(Broken::Unmatched)` is used instead.
+    /// It is not possible to serialize.
     #[serde(skip_serializing)]
-    UnmatchedSub(Value),
+    UnmatchedSub(Value, String),
+    /// An RPC request with raw parameters.
+    ///
+    /// This is produced only by [`from_slice_raw`](fn.from_slice_raw.html), never by the usual
+    /// parsing. It is serialized the same way as `Request`.
+    RawRequest(Request<RawParams>),
+    /// A notification with raw parameters.
+    ///
+    /// This is produced only by [`from_slice_raw`](fn.from_slice_raw.html), never by the usual
+    /// parsing. It is serialized the same way as `Notification`.
+    RawNotification(Notification<RawParams>),
+    /// A response with a pre-serialized result.
+    ///
+    /// It is created by [`Request::reply_serialized`](struct.Request.html#method.reply_serialized)
+    /// and never produced by parsing. It is serialized the same way as `Response`.
+    RawResponse(Response<RawJson>),
 }
 
 impl Message {
@@ -252,7 +495,7 @@ impl Message {
     /// The ID is auto-generated.
     pub fn request(method: String, params: Option<Value>) -> Self {
         Message::Request(Request {
-            jsonrpc: Version,
+            jsonrpc: Version::V2,
             method,
             params,
             id: Value::String(Uuid::new_v4().hyphenated().to_string()),
@@ -261,31 +504,66 @@ impl Message {
     /// Create a top-level error (without an ID).
     pub fn error(error: RpcError) -> Self {
         Message::Response(Response {
-            jsonrpc: Version,
+            jsonrpc: Version::V2,
             result: Err(error),
             id: Value::Null,
         })
     }
     /// A constructor for a notification.
     pub fn notification(method: String, params: Option<Value>) -> Self {
-        Message::Notification(Notification {
-            jsonrpc: Version,
-            method,
-            params,
-        })
+        Message::Notification(Notification::new(method, params))
+    }
+    /// Changes the version of the protocol the message uses.
+    ///
+    /// This applies to requests, notifications and responses, including the ones inside a batch
+    /// (even though 1.0 has no batches).
+    pub fn with_version(self, version: Version) -> Self {
+        match self {
+            Message::Request(request) => Message::Request(Request {
+                jsonrpc: version,
+                ..request
+            }),
+            Message::Response(response) => Message::Response(Response {
+                jsonrpc: version,
+                ..response
+            }),
+            Message::Notification(notification) => Message::Notification(Notification {
+                jsonrpc: version,
+                ..notification
+            }),
+            Message::Batch(batch) => Message::Batch(
+                batch
+                    .into_iter()
+                    .map(|message| message.with_version(version))
+                    .collect(),
+            ),
+            Message::RawRequest(request) => Message::RawRequest(Request {
+                jsonrpc: version,
+                ..request
+            }),
+            Message::RawNotification(notification) => Message::RawNotification(Notification {
+                jsonrpc: version,
+                ..notification
+            }),
+            Message::RawResponse(response) => Message::RawResponse(Response {
+                jsonrpc: version,
+                ..response
+            }),
+            unmatched @ Message::UnmatchedSub(..) => unmatched,
+        }
     }
 }
 
 /// A broken message.
 ///
 /// Protocol-level errors.
-#[derive(Debug, Clone, PartialEq, Deserialize)]
-#[serde(untagged)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Broken {
     /// It was valid JSON, but doesn't match the form of a JSONRPC 2.0 message.
-    Unmatched(Value),
+    ///
+    /// The reason why it doesn't match is included (eg. `missing jsonrpc version`).
+    Unmatched(Value, String),
     /// Invalid JSON.
-    #[serde(skip_deserializing)]
     SyntaxError(String),
 }
 
@@ -293,28 +571,347 @@ impl Broken {
     /// Generate an appropriate error message.
     ///
     /// The error message for these things are specified in the RFC, so this just creates an error
-    /// with the right values.
+    /// with the right values. If the broken message is a request with a detectable ID, the error
+    /// is sent with that ID, so the other side can pair it (see [`id`](#method.id)).
+    ///
+    /// The error is in JSON-RPC 2.0, see [`reply_in`](#method.reply_in) for the 1.0 peers.
     pub fn reply(&self) -> Message {
+        self.reply_in(Version::V2)
+    }
+    /// Generate an appropriate error message for an endpoint accepting the given version.
+    ///
+    /// With [`V1`](enum.Version.html#variant.V1), a broken object without the `jsonrpc` field is
+    /// answered in 1.0, as that is the dialect the peer used. Invalid JSON and values other than
+    /// objects are still answered in 2.0, their dialect can't be told.
+    pub fn reply_in(&self, version: Version) -> Message {
         match *self {
-            Broken::Unmatched(_) => Message::error(RpcError::invalid_request()),
+            Broken::Unmatched(ref value, ref reason) => {
+                let mut error = RpcError::invalid_request();
+                error.data = Some(Value::String(reason.clone()));
+                let jsonrpc = match *value {
+                    Value::Object(ref object) if !object.contains_key("jsonrpc") => version,
+                    _ => Version::V2,
+                };
+                Message::Response(Response {
+                    jsonrpc,
+                    result: Err(error),
+                    id: self.id().cloned().unwrap_or(Value::Null),
+                })
+            },
             Broken::SyntaxError(ref e) => Message::error(RpcError::parse_error(e.clone())),
         }
     }
+    /// The ID of the broken request, if it can be detected.
+    ///
+    /// This is the case if the message is an object with an ID of a valid type (a string, an
+    /// integer or null). Broken responses (having a `result` or `error`) don't count, as their ID
+    /// belongs to a request of this side.
+    pub fn id(&self) -> Option<&Value> {
+        let object = match *self {
+            Broken::Unmatched(Value::Object(ref object), _) => object,
+            _ => return None,
+        };
+        if object.contains_key("result") || object.contains_key("error") {
+            return None;
+        }
+        match object.get("id") {
+            Some(id) if valid_id(id) => Some(id),
+            _ => None,
+        }
+    }
 }
 
-/// A trick to easily deserialize and detect valid JSON, but invalid Message.
-#[derive(Deserialize)]
-#[serde(untagged)]
-pub(crate) enum WireMessage {
-    Message(Message),
-    Broken(Broken),
+/// The kind of a message, decided by looking at its fields.
+enum Kind {
+    Request,
+    Notification,
+    Response,
+    /// The error is already deserialized when checking it.
+    Error(RpcError),
+}
+
+/// The fields a message may have.
+const FIELDS: &[&str] = &["jsonrpc", "method", "params", "id", "result", "error"];
+
+/// Decides what kind of message the object is, or why it is not a message at all.
+///
+/// It only looks at the fields, so the object can be returned intact in the error. The strict
+/// mode doesn't take a null `params` or `error` as missing.
+fn kind(object: &Map<String, Value>, strict: bool) -> Result<Kind, String> {
+    match object.get("jsonrpc") {
+        Some(version) if version == "2.0" => (),
+        Some(version) => return Err(format!("unsupported jsonrpc version {}", version)),
+        None => return Err("missing jsonrpc version".to_owned()),
+    }
+    if let Some(unknown) = object.keys().find(|key| !FIELDS.contains(&key.as_str())) {
+        return Err(format!("unknown field {}", unknown));
+    }
+    if strict && object.get("params") == Some(&Value::Null) {
+        return Err("params is not an array or object".to_owned());
+    }
+    // A null error counts as missing
+    let error = match object.get("error") {
+        None => None,
+        Some(&Value::Null) if !strict => None,
+        Some(error) => Some(error),
+    };
+    let result = object.get("result");
+    let id = object.contains_key("id");
+    match object.get("method") {
+        Some(_) if result.is_some() || error.is_some() => {
+            Err("both method and result or error present".to_owned())
+        },
+        Some(&Value::String(_)) if id => Ok(Kind::Request),
+        Some(&Value::String(_)) => Ok(Kind::Notification),
+        Some(_) => Err("method is not a string".to_owned()),
+        None if object.contains_key("params") => Err("params present in a response".to_owned()),
+        None if result.is_some() && error.is_some() => {
+            Err("both result and error present".to_owned())
+        },
+        None if result.is_none() && error.is_none() => {
+            Err("neither method, result nor error present".to_owned())
+        },
+        None if !id => Err("missing id in a response".to_owned()),
+        None => match error {
+            Some(error) => RpcError::deserialize(error)
+                .map(Kind::Error)
+                .map_err(|e| format!("invalid error: {}", e)),
+            None => Ok(Kind::Response),
+        },
+    }
+}
+
+/// Classifies a parsed JSON value as a message.
+///
+/// The fields are moved into the message, nothing is parsed or cloned again.
+fn classify(value: Value, strict: bool) -> Parsed {
+    let object = match value {
+        Value::Object(object) => object,
+        Value::Array(items) => {
+            let batch = items
+                .into_iter()
+                .map(|item| match classify(item, strict) {
+                    Ok(message) => message,
+                    Err(Broken::Unmatched(item, reason)) => Message::UnmatchedSub(item, reason),
+                    Err(Broken::SyntaxError(_)) => unreachable!("The JSON is already parsed"),
+                })
+                .collect();
+            return Ok(Message::Batch(batch));
+        },
+        other => return Err(Broken::Unmatched(other, "not an object".to_owned())),
+    };
+    match kind(&object, strict) {
+        Ok(kind) => Ok(build(object, kind, Version::V2)),
+        Err(reason) => Err(Broken::Unmatched(Value::Object(object), reason)),
+    }
+}
+
+/// Moves the fields of an already checked object into the message.
+fn build(mut object: Map<String, Value>, kind: Kind, version: Version) -> Message {
+    let method = match object.remove("method") {
+        Some(Value::String(method)) => method,
+        _ => String::new(),
+    };
+    // A null params is the same as none, as with the derived deserialization
+    let params = match object.remove("params") {
+        None | Some(Value::Null) => None,
+        params => params,
+    };
+    let id = object.remove("id").unwrap_or(Value::Null);
+    match kind {
+        Kind::Request => Message::Request(Request {
+            jsonrpc: version,
+            method,
+            params,
+            id,
+        }),
+        Kind::Notification => Message::Notification(Notification {
+            jsonrpc: version,
+            method,
+            params,
+        }),
+        Kind::Response => Message::Response(Response {
+            jsonrpc: version,
+            // A 1.0 response may have only a null error
+            result: Ok(object.remove("result").unwrap_or(Value::Null)),
+            id,
+        }),
+        Kind::Error(error) => Message::Response(Response {
+            jsonrpc: version,
+            result: Err(error),
+            id,
+        }),
+    }
+}
+
+/// Decides what kind of message a 1.0 object is, or why it is not a message at all.
+fn kind_v1(object: &Map<String, Value>) -> Result<Kind, String> {
+    if let Some(unknown) = object.keys().find(|key| !FIELDS.contains(&key.as_str())) {
+        return Err(format!("unknown field {}", unknown));
+    }
+    let not_null = |key| match object.get(key) {
+        None | Some(&Value::Null) => None,
+        Some(value) => Some(value),
+    };
+    let result = object.contains_key("result");
+    let error = not_null("error");
+    match object.get("method") {
+        Some(_) if result || error.is_some() => {
+            Err("both method and result or error present".to_owned())
+        },
+        // A null ID marks a notification
+        Some(&Value::String(_)) if not_null("id").is_some() => Ok(Kind::Request),
+        Some(&Value::String(_)) => Ok(Kind::Notification),
+        Some(_) => Err("method is not a string".to_owned()),
+        None if not_null("result").is_some() && error.is_some() => {
+            Err("both result and error present".to_owned())
+        },
+        None if !result && !object.contains_key("error") => {
+            Err("neither method, result nor error present".to_owned())
+        },
+        None if !object.contains_key("id") => Err("missing id in a response".to_owned()),
+        // The 1.0 errors may be anything, wrap those that don't look like the 2.0 ones
+        None => match error {
+            Some(error) => Ok(Kind::Error(
+                RpcError::deserialize(error)
+                    .unwrap_or_else(|_| RpcError::server_error(Some(error))),
+            )),
+            None => Ok(Kind::Response),
+        },
+    }
+}
+
+/// Reinterprets a message that is not valid JSON-RPC 2.0 as a 1.0 one.
+///
+/// Only objects without the `jsonrpc` field that weren't recognized are examined. If they are
+/// valid 1.0 messages, these are provided with the [`V1`](enum.Version.html#variant.V1)
+/// version, so they are also answered in 1.0. Anything else is returned unchanged, only the
+/// reason of a still broken 1.0 message is updated.
+pub fn accept_v1(parsed: Parsed) -> Parsed {
+    match parsed {
+        Err(Broken::Unmatched(Value::Object(object), reason)) => {
+            if object.contains_key("jsonrpc") {
+                return Err(Broken::Unmatched(Value::Object(object), reason));
+            }
+            match kind_v1(&object) {
+                Ok(kind) => Ok(build(object, kind, Version::V1)),
+                Err(reason) => Err(Broken::Unmatched(Value::Object(object), reason)),
+            }
+        },
+        other => other,
+    }
+}
+
+/// Checks the ID is a string, an integer or null.
+///
+/// The specification allows any number, but fractions lead to problems with comparing them.
+fn valid_id(id: &Value) -> bool {
+    id.is_string() || id.is_i64() || id.is_u64() || id.is_null()
+}
+
+/// Finds a violation of the specification inside an otherwise valid message.
+fn violation(message: &Message) -> Result<(), String> {
+    let unstructured = || Err("params is not an array or object".to_owned());
+    let params = |params: &Option<Value>| match *params {
+        Some(ref params) if !params.is_array() && !params.is_object() => unstructured(),
+        _ => Ok(()),
+    };
+    let raw_params = |params: &Option<RawParams>| match *params {
+        Some(ref params) if !params.get().starts_with(&['[', '{'][..]) => unstructured(),
+        _ => Ok(()),
+    };
+    let id = |id: &Value| if valid_id(id) {
+        Ok(())
+    } else {
+        Err(format!("invalid id {}", id))
+    };
+    match *message {
+        Message::Request(ref request) => params(&request.params).and_then(|()| id(&request.id)),
+        Message::RawRequest(ref request) => {
+            raw_params(&request.params).and_then(|()| id(&request.id))
+        },
+        Message::Notification(ref notification) => params(&notification.params),
+        Message::RawNotification(ref notification) => raw_params(&notification.params),
+        Message::Response(ref response) => id(&response.id),
+        Message::RawResponse(ref response) => id(&response.id),
+        Message::Batch(_) => Err("nested batch".to_owned()),
+        Message::UnmatchedSub(..) => Ok(()),
+    }
+}
+
+/// Enforces the requirements of the specification the usual parsing is lenient about.
+///
+/// The usual parsing accepts some messages the specification forbids, as they are unambiguous
+/// and found in the wild. In the strict mode, these are turned into
+/// [`Broken::Unmatched`](enum.Broken.html#variant.Unmatched) with the reason, or into
+/// [`Message::UnmatchedSub`](enum.Message.html#variant.UnmatchedSub) inside a batch:
+///
+/// * Parameters that are not an array or an object.
+/// * IDs that are not a string, an integer or null.
+/// * Empty batches and batches inside batches.
+///
+/// Some violations (a null `params` or a response with a null `error` besides the `result`) can
+/// be detected only in the original JSON. Use [`from_slice_strict`](fn.from_slice_strict.html)
+/// (or the strict mode of the [codecs](../codec/index.html)) to catch these too.
+pub fn strict(parsed: Parsed) -> Parsed {
+    match parsed {
+        Ok(Message::Batch(ref batch)) if batch.is_empty() => {
+            Err(Broken::Unmatched(Value::Array(Vec::new()), "empty batch".to_owned()))
+        },
+        Ok(Message::Batch(batch)) => {
+            let batch = batch
+                .into_iter()
+                .map(|sub| match violation(&sub) {
+                    Ok(()) => sub,
+                    Err(reason) => Message::UnmatchedSub(message_value(&sub), reason),
+                })
+                .collect();
+            Ok(Message::Batch(batch))
+        },
+        Ok(message) => match violation(&message) {
+            Ok(()) => Ok(message),
+            Err(reason) => Err(Broken::Unmatched(message_value(&message), reason)),
+        },
+        broken => broken,
+    }
+}
+
+/// Turns a message back into the JSON value.
+///
+/// Unlike serialization, this works with the unmatched entries of (possibly nested) batches too,
+/// they are represented by their original JSON.
+pub(crate) fn message_value(message: &Message) -> Value {
+    match *message {
+        Message::UnmatchedSub(ref value, _) => value.clone(),
+        Message::Batch(ref batch) => Value::Array(batch.iter().map(message_value).collect()),
+        ref message => to_value(message).expect("Matched messages are always serializable"),
+    }
+}
+
+impl<'de> Deserialize<'de> for Message {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match classify(Value::deserialize(deserializer)?, false) {
+            Ok(message) => Ok(message),
+            Err(Broken::Unmatched(_, reason)) => Err(D::Error::custom(reason)),
+            Err(Broken::SyntaxError(e)) => Err(D::Error::custom(e)),
+        }
+    }
+}
+
+/// A helper to deserialize either a message or the reason why it is not one.
+///
+/// Invalid JSON is still an error.
+pub(crate) struct WireMessage(Parsed);
+
+impl<'de> Deserialize<'de> for WireMessage {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Value::deserialize(deserializer).map(|value| WireMessage(classify(value, false)))
+    }
 }
 
 pub(crate) fn decoded_to_parsed(res: JsonResult<WireMessage>) -> Parsed {
     match res {
-        Ok(WireMessage::Message(Message::UnmatchedSub(value))) => Err(Broken::Unmatched(value)),
-        Ok(WireMessage::Message(m)) => Ok(m),
-        Ok(WireMessage::Broken(b)) => Err(b),
+        Ok(WireMessage(parsed)) => parsed,
         Err(e) => Err(Broken::SyntaxError(format!("{}", e))),
     }
 }
@@ -335,6 +932,180 @@ pub fn from_str(s: &str) -> Parsed {
     from_slice(s.as_bytes())
 }
 
+/// Read a [Message](enum.Message.html) from a slice, in the strict mode.
+///
+/// This is like [`from_slice`](fn.from_slice.html), but all the requirements of the specification
+/// are enforced (see [`strict`](fn.strict.html)).
+pub fn from_slice_strict(s: &[u8]) -> Parsed {
+    match ::serde_json::de::from_slice(s) {
+        Ok(value) => strict(classify(value, true)),
+        Err(e) => Err(Broken::SyntaxError(format!("{}", e))),
+    }
+}
+
+/// The strict form of `classify`, for the codecs parsing the values themselves.
+pub(crate) fn classify_strict(value: Value) -> Parsed {
+    strict(classify(value, true))
+}
+
+/// An object with the `params` and `result` kept raw, or any other JSON.
+///
+/// This is what [`from_slice_raw`](fn.from_slice_raw.html) parses the input into, in a single
+/// pass. The other fields of an object are small, so they are parsed right away.
+enum RawTop {
+    Object {
+        fields: Map<String, Value>,
+        params: Option<RawJson>,
+        result: Option<RawJson>,
+    },
+    Other(Value),
+}
+
+impl<'de> Deserialize<'de> for RawTop {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct RawTopVisitor;
+        impl<'de> Visitor<'de> for RawTopVisitor {
+            type Value = RawTop;
+            fn expecting(&self, formatter: &mut Formatter) -> FmtResult {
+                formatter.write_str("JSON")
+            }
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<RawTop, A::Error> {
+                let mut fields = Map::new();
+                let mut params = None;
+                let mut result = None;
+                while let Some(key) = map.next_key::<String>()? {
+                    match key.as_str() {
+                        "params" => params = Some(RawJson(map.next_value()?)),
+                        "result" => result = Some(RawJson(map.next_value()?)),
+                        _ => {
+                            let value = map.next_value()?;
+                            fields.insert(key, value);
+                        },
+                    }
+                }
+                Ok(RawTop::Object {
+                    fields,
+                    params,
+                    result,
+                })
+            }
+            fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<RawTop, A::Error> {
+                Value::deserialize(SeqAccessDeserializer::new(seq)).map(RawTop::Other)
+            }
+            fn visit_bool<E: Error>(self, v: bool) -> Result<RawTop, E> {
+                Ok(RawTop::Other(Value::from(v)))
+            }
+            fn visit_i64<E: Error>(self, v: i64) -> Result<RawTop, E> {
+                Ok(RawTop::Other(Value::from(v)))
+            }
+            fn visit_u64<E: Error>(self, v: u64) -> Result<RawTop, E> {
+                Ok(RawTop::Other(Value::from(v)))
+            }
+            fn visit_f64<E: Error>(self, v: f64) -> Result<RawTop, E> {
+                Ok(RawTop::Other(Value::from(v)))
+            }
+            fn visit_str<E: Error>(self, v: &str) -> Result<RawTop, E> {
+                Ok(RawTop::Other(Value::from(v)))
+            }
+            fn visit_string<E: Error>(self, v: String) -> Result<RawTop, E> {
+                Ok(RawTop::Other(Value::from(v)))
+            }
+            fn visit_unit<E: Error>(self) -> Result<RawTop, E> {
+                Ok(RawTop::Other(Value::Null))
+            }
+        }
+        deserializer.deserialize_any(RawTopVisitor)
+    }
+}
+
+/// Classifies the raw form of a message, the same way as `classify` does.
+///
+/// The raw `params` and `result` are parsed into values only if needed ‒ the result of a response
+/// and both of them in a broken message.
+fn classify_raw(top: RawTop, strict: bool) -> Parsed {
+    let (mut object, params, result) = match top {
+        RawTop::Object {
+            fields,
+            params,
+            result,
+        } => (fields, params, result),
+        RawTop::Other(value) => return classify(value, strict),
+    };
+    // The kind cares only if they are present and if they are null
+    let shape = |raw: &RawJson| if raw.get() == "null" {
+        Value::Null
+    } else {
+        Value::Bool(true)
+    };
+    if let Some(ref params) = params {
+        object.insert("params".to_owned(), shape(params));
+    }
+    if let Some(ref result) = result {
+        object.insert("result".to_owned(), shape(result));
+    }
+    match kind(&object, strict) {
+        Ok(Kind::Request) | Ok(Kind::Notification) => {
+            let method = match object.remove("method") {
+                Some(Value::String(method)) => method,
+                _ => unreachable!("The kind checks the method"),
+            };
+            // A null params is the same as none, unless in the strict mode
+            let params = params.filter(|params| strict || params.get() != "null");
+            Ok(match object.remove("id") {
+                Some(id) => Message::RawRequest(Request {
+                    jsonrpc: Version::V2,
+                    method,
+                    params,
+                    id,
+                }),
+                None => Message::RawNotification(Notification {
+                    jsonrpc: Version::V2,
+                    method,
+                    params,
+                }),
+            })
+        },
+        Ok(kind) => {
+            if let Some(result) = result {
+                object.insert("result".to_owned(), result.to_value());
+            }
+            Ok(build(object, kind, Version::V2))
+        },
+        Err(reason) => {
+            if let Some(params) = params {
+                object.insert("params".to_owned(), params.to_value());
+            }
+            if let Some(result) = result {
+                object.insert("result".to_owned(), result.to_value());
+            }
+            Err(Broken::Unmatched(Value::Object(object), reason))
+        },
+    }
+}
+
+/// Read a [Message](enum.Message.html) from a slice, keeping the parameters raw.
+///
+/// The input is parsed in a single pass. A request or notification becomes
+/// [`Message::RawRequest`](enum.Message.html#variant.RawRequest) or
+/// [`Message::RawNotification`](enum.Message.html#variant.RawNotification), without building a
+/// `Value` of the parameters. Anything else (responses, batches, broken messages) results in the
+/// same message as with [`from_slice`](fn.from_slice.html).
+pub fn from_slice_raw(s: &[u8]) -> Parsed {
+    parse_raw(s, false)
+}
+
+/// The strict form of `from_slice_raw`, for the codecs.
+pub(crate) fn from_slice_raw_strict(s: &[u8]) -> Parsed {
+    strict(parse_raw(s, true))
+}
+
+fn parse_raw(s: &[u8], strict: bool) -> Parsed {
+    match ::serde_json::de::from_slice(s) {
+        Ok(top) => classify_raw(top, strict),
+        Err(e) => Err(Broken::SyntaxError(format!("{}", e))),
+    }
+}
+
 impl Into<String> for Message {
     fn into(self) -> String {
         ::serde_json::ser::to_string(&self).unwrap()
@@ -374,7 +1145,7 @@ mod tests {
         one(
             r#"{"jsonrpc": "2.0", "method": "call", "id": 1}"#,
             &Message::Request(Request {
-                jsonrpc: Version,
+                jsonrpc: Version::V2,
                 method: "call".to_owned(),
                 params: None,
                 id: json!(1),
@@ -384,7 +1155,7 @@ mod tests {
         one(
             r#"{"jsonrpc": "2.0", "method": "call", "params": [1, 2, 3], "id": 2}"#,
             &Message::Request(Request {
-                jsonrpc: Version,
+                jsonrpc: Version::V2,
                 method: "call".to_owned(),
                 params: Some(json!([1, 2, 3])),
                 id: json!(2),
@@ -394,7 +1165,7 @@ mod tests {
         one(
             r#"{"jsonrpc": "2.0", "method": "notif", "params": {"x": "y"}}"#,
             &Message::Notification(Notification {
-                jsonrpc: Version,
+                jsonrpc: Version::V2,
                 method: "notif".to_owned(),
                 params: Some(json!({"x": "y"})),
             }),
@@ -403,7 +1174,7 @@ mod tests {
         one(
             r#"{"jsonrpc": "2.0", "result": 42, "id": 3}"#,
             &Message::Response(Response {
-                jsonrpc: Version,
+                jsonrpc: Version::V2,
                 result: Ok(json!(42)),
                 id: json!(3),
             }),
@@ -412,7 +1183,7 @@ mod tests {
         one(
             r#"{"jsonrpc": "2.0", "result": null, "id": 3}"#,
             &Message::Response(Response {
-                jsonrpc: Version,
+                jsonrpc: Version::V2,
                 result: Ok(Value::Null),
                 id: json!(3),
             }),
@@ -421,7 +1192,7 @@ mod tests {
         one(
             r#"{"jsonrpc": "2.0", "error": {"code": 42, "message": "Wrong!"}, "id": null}"#,
             &Message::Response(Response {
-                jsonrpc: Version,
+                jsonrpc: Version::V2,
                 result: Err(RpcError::new(42, "Wrong!".to_owned(), None)),
                 id: Value::Null,
             }),
@@ -434,12 +1205,12 @@ mod tests {
             ]"#,
             &Message::Batch(vec![
                 Message::Notification(Notification {
-                    jsonrpc: Version,
+                    jsonrpc: Version::V2,
                     method: "notif".to_owned(),
                     params: None,
                 }),
                 Message::Request(Request {
-                    jsonrpc: Version,
+                    jsonrpc: Version::V2,
                     method: "call".to_owned(),
                     params: None,
                     id: json!(42),
@@ -457,21 +1228,21 @@ mod tests {
         assert_eq!(
             Message::Batch(vec![
                 Message::Notification(Notification {
-                    jsonrpc: Version,
+                    jsonrpc: Version::V2,
                     method: "notif".to_owned(),
                     params: None,
                 }),
                 Message::Request(Request {
-                    jsonrpc: Version,
+                    jsonrpc: Version::V2,
                     method: "call".to_owned(),
                     params: None,
                     id: json!(42),
                 }),
-                Message::UnmatchedSub(Value::Bool(true)),
+                Message::UnmatchedSub(Value::Bool(true), "not an object".to_owned()),
             ]),
             parsed
         );
-        to_vec(&Message::UnmatchedSub(Value::Null)).unwrap_err();
+        to_vec(&Message::UnmatchedSub(Value::Null, String::new())).unwrap_err();
     }
 
     /// A helper for the `broken` test.
@@ -484,26 +1255,49 @@ mod tests {
     #[test]
     fn broken() {
         // A helper with one test
-        fn one(input: &str) {
+        fn one(input: &str, expected: &str) {
             let msg = from_str(input);
             match msg {
-                Err(Broken::Unmatched(_)) => (),
+                Err(Broken::Unmatched(_, ref reason)) => assert_eq!(expected, reason),
                 _ => panic!("{} recognized as an RPC message: {:?}!", input, msg),
             }
         }
 
         // Missing the version
-        one(r#"{"method": "notif"}"#);
+        one(r#"{"method": "notif"}"#, "missing jsonrpc version");
         // Wrong version
-        one(r#"{"jsonrpc": 2.0, "method": "notif"}"#);
+        one(
+            r#"{"jsonrpc": 2.0, "method": "notif"}"#,
+            "unsupported jsonrpc version 2.0",
+        );
         // A response with both result and error
-        one(r#"{"jsonrpc": "2.0", "result": 42, "error": {"code": 42, "message": "!"}, "id": 1}"#);
+        one(
+            r#"{"jsonrpc": "2.0", "result": 42, "error": {"code": 42, "message": "!"}, "id": 1}"#,
+            "both result and error present",
+        );
         // A response without an id
-        one(r#"{"jsonrpc": "2.0", "result": 42}"#);
+        one(r#"{"jsonrpc": "2.0", "result": 42}"#, "missing id in a response");
+        // A response with a malformed error
+        one(
+            r#"{"jsonrpc": "2.0", "error": {"code": "42"}, "id": 1}"#,
+            "invalid error: invalid type: string \"42\", expected i64",
+        );
         // An extra field
-        one(r#"{"jsonrpc": "2.0", "method": "weird", "params": 42, "others": 43, "id": 2}"#);
+        one(
+            r#"{"jsonrpc": "2.0", "method": "weird", "params": 42, "others": 43, "id": 2}"#,
+            "unknown field others",
+        );
+        // A method that is not a string
+        one(r#"{"jsonrpc": "2.0", "method": 7, "id": 2}"#, "method is not a string");
+        // A mix of a request and a response
+        one(
+            r#"{"jsonrpc": "2.0", "method": "call", "result": 42, "id": 2}"#,
+            "both method and result or error present",
+        );
         // Something completely different
-        one(r#"{"x": [1, 2, 3]}"#);
+        one(r#"{"x": [1, 2, 3]}"#, "missing jsonrpc version");
+        one(r#"{"jsonrpc": "2.0"}"#, "neither method, result nor error present");
+        one("42", "not an object");
 
         match from_str(r#"{]"#) {
             Err(Broken::SyntaxError(_)) => (),
@@ -511,6 +1305,289 @@ mod tests {
         };
     }
 
+    /// The error reply to a broken request has its ID, if it can be detected.
+    #[test]
+    fn broken_reply_id() {
+        fn reply_id(input: &str) -> Value {
+            match from_str(input).unwrap_err().reply() {
+                Message::Response(Response {
+                    result: Err(ref error),
+                    ref id,
+                    ..
+                }) => {
+                    assert_eq!(-32_600, error.code);
+                    id.clone()
+                },
+                other => panic!("Unexpected reply {:?}", other),
+            }
+        }
+
+        assert_eq!(json!(5), reply_id(r#"{"jsonrpc": "2.0", "id": 5, "method": 7}"#));
+        assert_eq!(json!("x"), reply_id(r#"{"jsonrpc": "2.0", "id": "x", "params": []}"#));
+        assert_eq!(json!(6), reply_id(r#"{"jsonrpc": "1.0", "id": 6, "method": "m"}"#));
+        // Not a valid ID
+        assert_eq!(Value::Null, reply_id(r#"{"jsonrpc": "2.0", "id": [1], "method": 7}"#));
+        // Broken responses are not requests
+        let response = r#"{"jsonrpc": "2.0", "id": 7, "result": 1, "error": 2}"#;
+        assert_eq!(Value::Null, reply_id(response));
+        // No ID at all
+        assert_eq!(Value::Null, reply_id(r#"{"jsonrpc": "2.0", "method": 7}"#));
+    }
+
+    /// The 1.0 messages are accepted only on request and answered in 1.0.
+    #[test]
+    fn version_v1() {
+        fn v1(input: &str) -> Message {
+            let parsed = from_str(input);
+            match parsed {
+                Err(Broken::Unmatched(_, ref reason)) => {
+                    assert_eq!("missing jsonrpc version", reason)
+                },
+                ref other => panic!("Accepted as 2.0: {:?}", other),
+            }
+            let message = accept_v1(parsed).unwrap();
+            // It is written the same way
+            assert_eq!(
+                ::serde_json::from_str::<Value>(input).unwrap(),
+                to_value(&message).unwrap()
+            );
+            message
+        }
+
+        let request = match v1(r#"{"method": "call", "params": [1], "id": 2}"#) {
+            Message::Request(request) => request,
+            other => panic!("Not a request: {:?}", other),
+        };
+        assert_eq!(Version::V1, request.version());
+        assert_eq!(Some(json!([1])), request.params);
+        assert_eq!(
+            json!({"result": 42, "error": null, "id": 2}),
+            to_value(request.reply(json!(42))).unwrap()
+        );
+        assert_eq!(
+            json!({"result": null, "error": {"code": 1, "message": "!"}, "id": 2}),
+            to_value(request.error(RpcError::new(1, "!".to_owned(), None))).unwrap()
+        );
+        match v1(r#"{"method": "notif", "params": [], "id": null}"#) {
+            Message::Notification(ref notification) => assert_eq!("notif", notification.method),
+            other => panic!("Not a notification: {:?}", other),
+        }
+        match v1(r#"{"result": null, "error": {"code": 1, "message": "!"}, "id": 3}"#) {
+            Message::Response(ref response) => {
+                assert_eq!(1, response.result.as_ref().unwrap_err().code)
+            },
+            other => panic!("Not a response: {:?}", other),
+        }
+        match v1(r#"{"result": [1], "error": null, "id": 4}"#) {
+            Message::Response(ref response) => assert_eq!(Ok(json!([1])), response.result),
+            other => panic!("Not a response: {:?}", other),
+        }
+        // Errors that don't look like the 2.0 ones are wrapped
+        match accept_v1(from_str(r#"{"result": null, "error": "Oops", "id": 5}"#)) {
+            Ok(Message::Response(ref response)) => {
+                assert_eq!(Some(json!("Oops")), response.result.as_ref().unwrap_err().data)
+            },
+            other => panic!("Not a response: {:?}", other),
+        }
+        // Still broken, with a 1.0 reason
+        match accept_v1(from_str(r#"{"result": 1, "error": 2, "id": 6}"#)) {
+            Err(Broken::Unmatched(_, ref reason)) => {
+                assert_eq!("both result and error present", reason)
+            },
+            other => panic!("Something unexpected: {:?}", other),
+        }
+        // The 2.0 ones are left alone
+        let broken = from_str(r#"{"jsonrpc": "2.0", "method": 7, "id": 8}"#);
+        assert_eq!(broken, accept_v1(broken.clone()));
+        // Converting our own 2.0 messages
+        let notification =
+            Message::notification("notif".to_owned(), None).with_version(Version::V1);
+        assert_eq!(
+            json!({"method": "notif", "params": [], "id": null}),
+            to_value(&notification).unwrap()
+        );
+        // A broken 1.0 message is answered in 1.0, on a 1.0 endpoint
+        let broken = accept_v1(from_str(r#"{"result": 1, "error": 2, "id": 6}"#)).unwrap_err();
+        let reply = to_value(broken.reply_in(Version::V1)).unwrap();
+        assert!(reply.get("jsonrpc").is_none());
+        assert_eq!(-32_600, reply["error"]["code"]);
+        assert_eq!(Value::Null, reply["result"]);
+        assert_eq!(json!("2.0"), to_value(broken.reply()).unwrap()["jsonrpc"]);
+        // But the 2.0 ones stay in 2.0
+        let broken = from_str(r#"{"jsonrpc": "2.0", "method": 7, "id": 8}"#).unwrap_err();
+        assert_eq!(json!("2.0"), to_value(broken.reply_in(Version::V1)).unwrap()["jsonrpc"]);
+    }
+
+    /// The strict mode rejects what the specification forbids, even if it is unambiguous.
+    #[test]
+    fn strict_mode() {
+        fn reason(input: &str) -> String {
+            assert!(from_str(input).is_ok(), "{} not accepted by the lenient parsing", input);
+            match from_slice_strict(input.as_bytes()) {
+                Err(Broken::Unmatched(_, reason)) => reason,
+                other => panic!("{} accepted in the strict mode: {:?}", input, other),
+            }
+        }
+
+        let unstructured = "params is not an array or object";
+        let request = r#"{"jsonrpc": "2.0", "method": "m", "params": 1, "id": 1}"#;
+        assert_eq!(unstructured, reason(request));
+        assert_eq!(unstructured, reason(r#"{"jsonrpc": "2.0", "method": "m", "params": "x"}"#));
+        assert_eq!(unstructured, reason(r#"{"jsonrpc": "2.0", "method": "m", "params": null}"#));
+        let request = r#"{"jsonrpc": "2.0", "method": "m", "id": 1.5}"#;
+        assert_eq!("invalid id 1.5", reason(request));
+        // The ID is not detected for the error reply either
+        match from_slice_strict(request.as_bytes()).unwrap_err().reply() {
+            Message::Response(ref response) => assert_eq!(Value::Null, response.id),
+            other => panic!("Unexpected reply {:?}", other),
+        }
+        let request = r#"{"jsonrpc": "2.0", "method": "m", "id": {"x": 1}}"#;
+        assert_eq!(r#"invalid id {"x":1}"#, reason(request));
+        assert_eq!("invalid id 1.5", reason(r#"{"jsonrpc": "2.0", "result": 1, "id": 1.5}"#));
+        let response = r#"{"jsonrpc": "2.0", "result": 1, "error": null, "id": 1}"#;
+        assert_eq!("both result and error present", reason(response));
+        assert_eq!("empty batch", reason("[]"));
+        // Inside a batch, only the violating messages are unmatched
+        let batch = concat!(
+            r#"[[], {"jsonrpc": "2.0", "method": "m", "params": 7},"#,
+            r#" {"jsonrpc": "2.0", "method": "m"}]"#
+        );
+        match from_slice_strict(batch.as_bytes()) {
+            Ok(Message::Batch(ref batch)) => {
+                assert_eq!(
+                    Message::UnmatchedSub(json!([]), "nested batch".to_owned()),
+                    batch[0]
+                );
+                match batch[1] {
+                    Message::UnmatchedSub(_, ref reason) => assert_eq!(unstructured, reason),
+                    ref other => panic!("Unexpected message {:?}", other),
+                }
+                assert_eq!(Message::notification("m".to_owned(), None), batch[2]);
+            },
+            other => panic!("Unexpected message {:?}", other),
+        }
+        // A nested batch keeps its original JSON, even with unmatched entries inside
+        let nested = Message::Batch(vec![
+            Message::UnmatchedSub(json!([[1], {"foo": 1}]), "nested batch".to_owned()),
+        ]);
+        assert_eq!(Ok(nested.clone()), from_slice_strict(br#"[[[1], {"foo": 1}]]"#));
+        assert_eq!(Ok(nested), strict(from_str(r#"[[[1], {"foo": 1}]]"#)));
+        // Valid messages pass
+        for valid in &[
+            r#"{"jsonrpc": "2.0", "method": "m", "params": [1], "id": "x"}"#,
+            r#"{"jsonrpc": "2.0", "method": "m", "params": {"a": 1}, "id": null}"#,
+            r#"{"jsonrpc": "2.0", "error": {"code": 1, "message": "!"}, "id": null}"#,
+            r#"[{"jsonrpc": "2.0", "method": "m"}, {"jsonrpc": "2.0", "result": 1, "id": -3}]"#,
+        ] {
+            assert_eq!(from_str(valid), from_slice_strict(valid.as_bytes()));
+        }
+        // The checks apply to already parsed messages as well
+        let request = Message::Request(Request {
+            jsonrpc: Version::V2,
+            method: "m".to_owned(),
+            params: Some(json!(true)),
+            id: json!(1),
+        });
+        match strict(Ok(request)) {
+            Err(Broken::Unmatched(_, ref reason)) => assert_eq!(unstructured, reason),
+            other => panic!("Unexpected message {:?}", other),
+        }
+        // Including the raw parameters
+        let raw = br#"{"jsonrpc": "2.0", "method": "m", "params": null, "id": 1}"#;
+        match from_slice_raw(raw) {
+            Ok(Message::RawRequest(ref request)) => assert!(request.params.is_none()),
+            other => panic!("Unexpected message {:?}", other),
+        }
+        match from_slice_raw_strict(raw) {
+            Err(Broken::Unmatched(_, ref reason)) => assert_eq!(unstructured, reason),
+            other => panic!("Unexpected message {:?}", other),
+        }
+        let raw = br#"{"jsonrpc": "2.0", "method": "m", "params": [null]}"#;
+        assert_eq!(from_slice_raw(raw), from_slice_raw_strict(raw));
+    }
+
+    /// Parsing with raw parameters keeps them as they were on the wire.
+    #[test]
+    fn raw_params() {
+        let request =
+            r#"{"jsonrpc": "2.0", "method": "call", "params": [1, {"x": 2}], "id": null}"#;
+        let parsed = from_slice_raw(request.as_bytes());
+        match parsed {
+            Ok(Message::RawRequest(ref request)) => {
+                assert_eq!("call", request.method);
+                assert_eq!(Value::Null, request.id);
+                let params = request.params.as_ref().unwrap();
+                assert_eq!(r#"[1, {"x": 2}]"#, params.get());
+                let (a, b): (u32, Value) = params.parse().unwrap();
+                assert_eq!((1, json!({"x": 2})), (a, b));
+            },
+            ref other => panic!("Not a raw request: {:?}", other),
+        }
+        // It serializes the same as a parsed one
+        let serialized = to_vec(&parsed.unwrap()).unwrap();
+        assert_eq!(from_str(request), super::from_slice(&serialized));
+
+        let notification = br#"{"jsonrpc": "2.0", "method": "notif"}"#;
+        match from_slice_raw(notification) {
+            Ok(Message::RawNotification(Notification {
+                ref method,
+                params: None,
+                ..
+            })) if method == "notif" => (),
+            other => panic!("Not a raw notification: {:?}", other),
+        }
+        // Other things end up the same as with the usual parsing, including the broken ones
+        for other in &[
+            r#"{"jsonrpc": "2.0", "result": {"a": [1, 2]}, "id": 1}"#,
+            r#"{"jsonrpc": "2.0", "error": {"code": 1, "message": "!"}, "id": 1}"#,
+            r#"{"jsonrpc": "2.0", "method": "weird", "others": 43, "id": 2}"#,
+            r#"{"jsonrpc": "2.0", "method": "m", "params": [1], "result": 2, "id": 3}"#,
+            r#"{"jsonrpc": "2.0", "params": [1], "id": 4}"#,
+            r#"{"jsonrpc": "2.0", "method": 42, "params": {"x": null}}"#,
+            r#"[{"jsonrpc": "2.0", "method": "m"}, 1]"#,
+            r#""hello""#,
+            "42",
+            "null",
+            "{",
+        ] {
+            assert_eq!(super::from_slice(other.as_bytes()), from_slice_raw(other.as_bytes()));
+            assert_eq!(
+                from_slice_strict(other.as_bytes()),
+                from_slice_raw_strict(other.as_bytes())
+            );
+        }
+    }
+
+    /// A pre-serialized reply looks the same on the wire as a usual one.
+    #[test]
+    fn reply_serialized() {
+        #[derive(Serialize)]
+        struct Answer {
+            items: Vec<u32>,
+            name: &'static str,
+        }
+
+        let answer = Answer {
+            items: vec![1, 2, 3],
+            name: "answer",
+        };
+        let request = match Message::request("call".to_owned(), None) {
+            Message::Request(request) => request,
+            _ => unreachable!(),
+        };
+        let raw = request.reply_serialized(&answer).unwrap();
+        let usual = request.reply(to_value(&answer).unwrap());
+        assert_eq!(to_vec(&usual).unwrap(), to_vec(&raw).unwrap());
+        match raw {
+            Message::RawResponse(ref response) => {
+                let result = response.result.as_ref().unwrap();
+                assert_eq!(r#"{"items":[1,2,3],"name":"answer"}"#, result.get());
+                assert_eq!(usual, Message::Response(response.parsed()));
+            },
+            ref other => panic!("Not a raw response: {:?}", other),
+        }
+    }
+
     /// Test some non-trivial aspects of the constructors
     ///
     /// This doesn't have a full coverage, because there's not much to actually test there.
@@ -536,7 +1613,7 @@ mod tests {
             assert_eq!(
                 *resp,
                 Response {
-                    jsonrpc: Version,
+                    jsonrpc: Version::V2,
                     result: Ok(json!([1, 2, 3])),
                     id: id1,
                 }
@@ -552,7 +1629,7 @@ mod tests {
             assert_eq!(
                 *resp,
                 Response {
-                    jsonrpc: Version,
+                    jsonrpc: Version::V2,
                     result: Err(RpcError::new(42, "Wrong!".to_owned(), None)),
                     id: id2,
                 }
@@ -567,7 +1644,7 @@ mod tests {
             assert_eq!(
                 *resp,
                 Response {
-                    jsonrpc: Version,
+                    jsonrpc: Version::V2,
                     result: Err(RpcError::new(43, "Also wrong!".to_owned(), None)),
                     id: Value::Null,
                 }
@@ -0,0 +1,78 @@
+// Copyright 2017 tokio-jsonrpc Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Observing what happens on an endpoint.
+//!
+//! The [`Metrics`](trait.Metrics.html) trait is a hook the
+//! [`Endpoint`](../endpoint/struct.Endpoint.html) calls whenever something interesting happens on
+//! the connection. The library doesn't interpret the events in any way, it is up to the
+//! implementation to turn them into counters, histograms or whatever the monitoring system of the
+//! application understands.
+
+use std::fmt::{Debug, Formatter, Result as FmtResult};
+use std::time::Duration;
+
+use message::{Broken, RpcError};
+
+/// A receiver of events happening on an endpoint.
+///
+/// Set it with [`Endpoint::metrics`](../endpoint/struct.Endpoint.html#method.metrics). All the
+/// callbacks have an empty default implementation, so an implementation can pick only the events
+/// it cares about.
+///
+/// The callbacks are called synchronously from within the endpoint, so they should be cheap (eg.
+/// bumping a counter or recording a value into a histogram).
+pub trait Metrics {
+    /// An RPC request arrived and is being passed to the server.
+    fn request_received(&self, _method: &str) {}
+    /// An answer to an RPC request has been produced.
+    ///
+    /// The `result` contains the error sent to the other side if there was one. This includes the
+    /// case when the server doesn't know the method. The latency is measured from the time the
+    /// request was passed to the server until the answer was ready.
+    fn request_answered(&self, _method: &str, _result: Result<(), &RpcError>, _latency: Duration) {
+    }
+    /// A notification arrived and is being passed to the server.
+    fn notification_received(&self, _method: &str) {}
+    /// The server finished handling a notification.
+    ///
+    /// This is not called if the server doesn't know the notification.
+    fn notification_handled(&self, _method: &str, _latency: Duration) {}
+    /// A message that is not valid JSON or not a JSON RPC message arrived.
+    fn broken(&self, _broken: &Broken) {}
+    /// A response from the other side arrived.
+    ///
+    /// The `expected` is false if the response doesn't belong to any outstanding call.
+    fn response_received(&self, _expected: bool) {}
+    /// An RPC call has been sent to the other side by the
+    /// [`Client`](../endpoint/struct.Client.html).
+    fn call_sent(&self, _method: &str) {}
+    /// An answer to an RPC call made by the client arrived.
+    ///
+    /// The latency is measured from the time the call was made.
+    fn call_answered(&self, _method: &str, _latency: Duration) {}
+    /// An RPC call made by the client timed out.
+    fn call_timed_out(&self, _method: &str) {}
+    /// The number of outstanding RPC calls changed.
+    ///
+    /// These are the calls made by the client that are waiting for an answer.
+    fn outstanding_calls(&self, _count: usize) {}
+}
+
+impl Debug for Metrics {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "Metrics")
+    }
+}
+
+/// Metrics that throw everything away.
+///
+/// This is what the endpoint uses unless told otherwise.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct NoMetrics;
+
+impl Metrics for NoMetrics {}
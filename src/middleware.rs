@@ -0,0 +1,568 @@
+// Copyright 2017 tokio-jsonrpc Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Wrapping cross-cutting behaviour around servers.
+//!
+//! A [`Middleware`](trait.Middleware.html) sits in front of a
+//! [`Server`](../server/trait.Server.html) and sees every call before the server does. It may pass
+//! the call on unchanged, modify the parameters, refuse it with an error or modify the result. The
+//! middleware is put in front of a server with [`Layered`](struct.Layered.html) and multiple
+//! middlewares can be composed with [`Stack`](struct.Stack.html).
+//!
+//! Some ready-made middlewares are provided as well ‒ [`Logging`](struct.Logging.html),
+//! [`Guard`](struct.Guard.html), [`MapParams`](struct.MapParams.html) and
+//! [`MapResult`](struct.MapResult.html).
+//!
+//! Similar to [`AbstractServer`](../server/struct.AbstractServer.html), the middlewares work with
+//! the type-erased results, so they incur some runtime costs. The results of RPCs are
+//! pre-serialized into [`RawJson`](../message/struct.RawJson.html) by the server, so passing them
+//! through the middlewares doesn't serialize them again.
+//!
+//! # Examples
+//!
+//! ```rust
+//! # extern crate tokio_jsonrpc;
+//! # extern crate slog;
+//! # use tokio_jsonrpc::{RpcError, Server};
+//! # use tokio_jsonrpc::middleware::{Guard, Layered, Logging, Stack};
+//! # use slog::{Discard, Logger};
+//! # fn main() {
+//! # let logger = Logger::root(Discard, slog::o!());
+//! struct Real;
+//! impl Server for Real {
+//!     type Success = ();
+//!     type RpcCallResult = Result<(), RpcError>;
+//!     type NotificationResult = Result<(), ()>;
+//! }
+//!
+//! // Nobody is allowed to call the `shutdown` method. Everything is logged, including the refused
+//! // calls.
+//! let guard = Guard::new(|_ctl, method, _params| if method == "shutdown" {
+//!     Err(RpcError::new(-32_001, "Forbidden".to_owned(), None))
+//! } else {
+//!     Ok(())
+//! });
+//! let server = Layered::new(Real, Stack::new(Logging::new(logger), guard));
+//! # let _ = server;
+//! # }
+//! ```
+
+use std::rc::Rc;
+use std::time::Instant;
+
+use futures::{Future, IntoFuture};
+use serde_json::Value;
+use slog::Logger;
+
+use endpoint::ServerCtl;
+use introspection::MethodInfo;
+use message::{RawJson, RpcError};
+use server::{AbstractServer, BoxNotificationResult, BoxRpcCallResult, Server};
+
+/// The server behind a middleware.
+///
+/// This is whatever the middleware wraps ‒ either the real server or the next middleware in a
+/// [`Stack`](struct.Stack.html), in its type-erased form.
+pub type Next<'a> = Server<
+    Success = RawJson,
+    RpcCallResult = BoxRpcCallResult,
+    NotificationResult = BoxNotificationResult,
+> + 'a;
+
+/// A layer of cross-cutting behaviour in front of a server.
+///
+/// Each callback gets the `next` server in the chain, in addition to the usual parameters of the
+/// corresponding [`Server`](../server/trait.Server.html) callback. The default implementations
+/// simply pass the call on, so an implementation needs to override only the ones it is interested
+/// in.
+///
+/// The callback may:
+///
+/// * Do something before passing the call to `next`.
+/// * Pass different method name or parameters to `next`.
+/// * Not call `next` at all and return an answer (eg. an error) on its own.
+/// * Modify the future returned from `next` to do something with the result.
+pub trait Middleware {
+    /// Called when the client requests something.
+    fn rpc(
+        &self, next: &Next, ctl: &ServerCtl, method: &str, params: &Option<Value>
+    ) -> Option<BoxRpcCallResult> {
+        next.rpc(ctl, method, params)
+    }
+    /// Called when the client sends a notification.
+    fn notification(
+        &self, next: &Next, ctl: &ServerCtl, method: &str, params: &Option<Value>
+    ) -> Option<BoxNotificationResult> {
+        next.notification(ctl, method, params)
+    }
+    /// Called when the endpoint is initialized.
+    fn initialized(&self, next: &Next, ctl: &ServerCtl) {
+        next.initialized(ctl)
+    }
+    /// Describes the methods.
+    ///
+    /// A middleware that hides or adds methods may want to reflect that in here.
+    fn methods(&self, next: &Next) -> Vec<MethodInfo> {
+        next.methods()
+    }
+}
+
+/// A server with a middleware in front of it.
+///
+/// The result is a server again, so it can be wrapped in another `Layered`, put into a
+/// [`ServerChain`](../server/struct.ServerChain.html) or used by an endpoint directly.
+pub struct Layered<S: Server, M> {
+    server: AbstractServer<S>,
+    middleware: M,
+}
+
+impl<S: Server, M: Middleware> Layered<S, M> {
+    /// Puts the middleware in front of the server.
+    pub fn new(server: S, middleware: M) -> Self {
+        Layered {
+            server: AbstractServer::new(server),
+            middleware,
+        }
+    }
+    /// Unwraps the server and the middleware.
+    pub fn into_inner(self) -> (S, M) {
+        (self.server.into_inner(), self.middleware)
+    }
+}
+
+impl<S: Server, M: Middleware> Server for Layered<S, M> {
+    type Success = RawJson;
+    type RpcCallResult = BoxRpcCallResult;
+    type NotificationResult = BoxNotificationResult;
+    fn rpc(
+        &self, ctl: &ServerCtl, method: &str, params: &Option<Value>
+    ) -> Option<Self::RpcCallResult> {
+        self.middleware.rpc(&self.server, ctl, method, params)
+    }
+    fn notification(
+        &self, ctl: &ServerCtl, method: &str, params: &Option<Value>
+    ) -> Option<Self::NotificationResult> {
+        self.middleware
+            .notification(&self.server, ctl, method, params)
+    }
+    fn initialized(&self, ctl: &ServerCtl) {
+        self.middleware.initialized(&self.server, ctl)
+    }
+    fn methods(&self) -> Vec<MethodInfo> {
+        self.middleware.methods(&self.server)
+    }
+}
+
+/// Two middlewares composed into one.
+///
+/// The `outer` one sees the calls first and its `next` is the `inner` one. Longer stacks can be
+/// built by nesting.
+pub struct Stack<Outer, Inner> {
+    outer: Outer,
+    inner: Inner,
+}
+
+impl<Outer: Middleware, Inner: Middleware> Stack<Outer, Inner> {
+    /// Composes the middlewares.
+    pub fn new(outer: Outer, inner: Inner) -> Self {
+        Stack { outer, inner }
+    }
+    /// Decomposes the middlewares back.
+    pub fn into_inner(self) -> (Outer, Inner) {
+        (self.outer, self.inner)
+    }
+}
+
+/// A middleware bound to the server behind it, so it looks like a server itself.
+struct Bound<'a, M: 'a> {
+    middleware: &'a M,
+    next: &'a Next<'a>,
+}
+
+impl<'a, M: Middleware> Server for Bound<'a, M> {
+    type Success = RawJson;
+    type RpcCallResult = BoxRpcCallResult;
+    type NotificationResult = BoxNotificationResult;
+    fn rpc(
+        &self, ctl: &ServerCtl, method: &str, params: &Option<Value>
+    ) -> Option<Self::RpcCallResult> {
+        self.middleware.rpc(self.next, ctl, method, params)
+    }
+    fn notification(
+        &self, ctl: &ServerCtl, method: &str, params: &Option<Value>
+    ) -> Option<Self::NotificationResult> {
+        self.middleware.notification(self.next, ctl, method, params)
+    }
+    fn initialized(&self, ctl: &ServerCtl) {
+        self.middleware.initialized(self.next, ctl)
+    }
+    fn methods(&self) -> Vec<MethodInfo> {
+        self.middleware.methods(self.next)
+    }
+}
+
+impl<Outer: Middleware, Inner: Middleware> Middleware for Stack<Outer, Inner> {
+    fn rpc(
+        &self, next: &Next, ctl: &ServerCtl, method: &str, params: &Option<Value>
+    ) -> Option<BoxRpcCallResult> {
+        let bound = Bound {
+            middleware: &self.inner,
+            next,
+        };
+        self.outer.rpc(&bound, ctl, method, params)
+    }
+    fn notification(
+        &self, next: &Next, ctl: &ServerCtl, method: &str, params: &Option<Value>
+    ) -> Option<BoxNotificationResult> {
+        let bound = Bound {
+            middleware: &self.inner,
+            next,
+        };
+        self.outer.notification(&bound, ctl, method, params)
+    }
+    fn initialized(&self, next: &Next, ctl: &ServerCtl) {
+        let bound = Bound {
+            middleware: &self.inner,
+            next,
+        };
+        self.outer.initialized(&bound, ctl)
+    }
+    fn methods(&self, next: &Next) -> Vec<MethodInfo> {
+        let bound = Bound {
+            middleware: &self.inner,
+            next,
+        };
+        self.outer.methods(&bound)
+    }
+}
+
+/// A middleware logging the calls and how long they took.
+///
+/// The calls are logged on the debug level, refused methods and errors on the info level.
+pub struct Logging(Logger);
+
+impl Logging {
+    /// Creates the middleware logging into the given logger.
+    pub fn new(logger: Logger) -> Self {
+        Logging(logger)
+    }
+}
+
+impl Middleware for Logging {
+    fn rpc(
+        &self, next: &Next, ctl: &ServerCtl, method: &str, params: &Option<Value>
+    ) -> Option<BoxRpcCallResult> {
+        debug!(self.0, "RPC called"; "method" => method);
+        let start = Instant::now();
+        let logger = self.0.clone();
+        let method = method.to_owned();
+        match next.rpc(ctl, &method, params) {
+            None => {
+                info!(logger, "RPC refused"; "method" => method);
+                None
+            },
+            Some(future) => {
+                let logged = future.then(move |result| {
+                    let elapsed = start.elapsed();
+                    let elapsed = format!("{}.{:06}s", elapsed.as_secs(), elapsed.subsec_micros());
+                    match result {
+                        Ok(_) => debug!(logger, "RPC answered"; "method" => method,
+                                        "elapsed" => elapsed),
+                        Err(ref e) => info!(logger, "RPC failed"; "method" => method,
+                                            "elapsed" => elapsed, "code" => e.code),
+                    }
+                    result
+                });
+                Some(Box::new(logged))
+            },
+        }
+    }
+    fn notification(
+        &self, next: &Next, ctl: &ServerCtl, method: &str, params: &Option<Value>
+    ) -> Option<BoxNotificationResult> {
+        debug!(self.0, "Notification received"; "method" => method);
+        let result = next.notification(ctl, method, params);
+        if result.is_none() {
+            info!(self.0, "Notification refused"; "method" => method);
+        }
+        result
+    }
+}
+
+/// A middleware that checks each call before it is passed on.
+///
+/// The check is a closure. If it returns an error, the RPC is answered with the error without
+/// reaching the server and a notification is dropped.
+pub struct Guard<F>(F);
+
+impl<F> Guard<F>
+where
+    F: Fn(&ServerCtl, &str, &Option<Value>) -> Result<(), RpcError>,
+{
+    /// Creates the guard with the given check.
+    pub fn new(check: F) -> Self {
+        Guard(check)
+    }
+}
+
+impl<F> Middleware for Guard<F>
+where
+    F: Fn(&ServerCtl, &str, &Option<Value>) -> Result<(), RpcError>,
+{
+    fn rpc(
+        &self, next: &Next, ctl: &ServerCtl, method: &str, params: &Option<Value>
+    ) -> Option<BoxRpcCallResult> {
+        match (self.0)(ctl, method, params) {
+            Ok(()) => next.rpc(ctl, method, params),
+            Err(e) => Some(Box::new(Err(e).into_future())),
+        }
+    }
+    fn notification(
+        &self, next: &Next, ctl: &ServerCtl, method: &str, params: &Option<Value>
+    ) -> Option<BoxNotificationResult> {
+        match (self.0)(ctl, method, params) {
+            Ok(()) => next.notification(ctl, method, params),
+            Err(_) => Some(Box::new(Err(()).into_future())),
+        }
+    }
+}
+
+/// A middleware rewriting the parameters.
+///
+/// The closure gets the method name and the original parameters and returns the parameters to
+/// pass on. It is used for both RPCs and notifications.
+pub struct MapParams<F>(F);
+
+impl<F: Fn(&str, &Option<Value>) -> Option<Value>> MapParams<F> {
+    /// Creates the middleware with the given rewriting function.
+    pub fn new(map: F) -> Self {
+        MapParams(map)
+    }
+}
+
+impl<F: Fn(&str, &Option<Value>) -> Option<Value>> Middleware for MapParams<F> {
+    fn rpc(
+        &self, next: &Next, ctl: &ServerCtl, method: &str, params: &Option<Value>
+    ) -> Option<BoxRpcCallResult> {
+        next.rpc(ctl, method, &(self.0)(method, params))
+    }
+    fn notification(
+        &self, next: &Next, ctl: &ServerCtl, method: &str, params: &Option<Value>
+    ) -> Option<BoxNotificationResult> {
+        next.notification(ctl, method, &(self.0)(method, params))
+    }
+}
+
+/// A middleware modifying the results of RPCs.
+///
+/// The closure gets the method name and the result the server produced and returns the one to
+/// send to the client. It can turn a success into an error or the other way around.
+///
+/// The success is the already serialized JSON. The closure may inspect it by
+/// [`RawJson::parse`](../message/struct.RawJson.html#method.parse) and produce a new one by
+/// [`RawJson::serialize`](../message/struct.RawJson.html#method.serialize). Passing it through
+/// untouched costs nothing.
+pub struct MapResult<F>(Rc<F>);
+
+impl<F> MapResult<F>
+where
+    F: Fn(&str, Result<RawJson, RpcError>) -> Result<RawJson, RpcError> + 'static,
+{
+    /// Creates the middleware with the given modifying function.
+    pub fn new(map: F) -> Self {
+        MapResult(Rc::new(map))
+    }
+}
+
+impl<F> Middleware for MapResult<F>
+where
+    F: Fn(&str, Result<RawJson, RpcError>) -> Result<RawJson, RpcError> + 'static,
+{
+    fn rpc(
+        &self, next: &Next, ctl: &ServerCtl, method: &str, params: &Option<Value>
+    ) -> Option<BoxRpcCallResult> {
+        next.rpc(ctl, method, params).map(|future| {
+            let map = self.0.clone();
+            let method = method.to_owned();
+            let mapped: BoxRpcCallResult =
+                Box::new(future.then(move |result| map(&method, result)));
+            mapped
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+
+    /// A server echoing the method and the parameters back.
+    ///
+    /// The `"error"` method returns an error and the `"notif"` notification is accepted.
+    struct Echo;
+
+    impl Server for Echo {
+        type Success = Value;
+        type RpcCallResult = Result<Value, RpcError>;
+        type NotificationResult = Result<(), ()>;
+        fn rpc(
+            &self, _ctl: &ServerCtl, method: &str, params: &Option<Value>
+        ) -> Option<Self::RpcCallResult> {
+            match method {
+                "error" => Some(Err(RpcError::invalid_params(None))),
+                "unknown" => None,
+                _ => Some(Ok(json!([method, params]))),
+            }
+        }
+        fn notification(
+            &self, _ctl: &ServerCtl, method: &str, _params: &Option<Value>
+        ) -> Option<Self::NotificationResult> {
+            match method {
+                "notif" => Some(Ok(())),
+                _ => None,
+            }
+        }
+    }
+
+    /// A middleware remembering in which order it was called.
+    struct Record(&'static str, Rc<RefCell<Vec<&'static str>>>);
+
+    impl Middleware for Record {
+        fn rpc(
+            &self, next: &Next, ctl: &ServerCtl, method: &str, params: &Option<Value>
+        ) -> Option<BoxRpcCallResult> {
+            self.1.borrow_mut().push(self.0);
+            next.rpc(ctl, method, params)
+        }
+        fn initialized(&self, next: &Next, ctl: &ServerCtl) {
+            self.1.borrow_mut().push(self.0);
+            next.initialized(ctl)
+        }
+    }
+
+    /// The default implementation just passes everything through.
+    #[test]
+    fn passthrough() {
+        struct Nothing;
+        impl Middleware for Nothing {}
+
+        let server = Layered::new(Echo, Nothing);
+        let (ctl, _, _) = ServerCtl::new_test();
+        assert_eq!(
+            json!(["hello", [1]]),
+            server
+                .rpc(&ctl, "hello", &Some(json!([1])))
+                .unwrap()
+                .wait()
+                .unwrap()
+                .to_value()
+        );
+        server
+            .rpc(&ctl, "error", &None)
+            .unwrap()
+            .wait()
+            .unwrap_err();
+        assert!(server.rpc(&ctl, "unknown", &None).is_none());
+        server
+            .notification(&ctl, "notif", &None)
+            .unwrap()
+            .wait()
+            .unwrap();
+        assert!(server.notification(&ctl, "other", &None).is_none());
+    }
+
+    /// The stack calls the outer middleware first.
+    #[test]
+    fn stack() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let stack = Stack::new(
+            Record("outer", log.clone()),
+            Stack::new(Record("middle", log.clone()), Record("inner", log.clone())),
+        );
+        let server = Layered::new(Echo, stack);
+        let (ctl, _, _) = ServerCtl::new_test();
+        server.initialized(&ctl);
+        server.rpc(&ctl, "hello", &None).unwrap().wait().unwrap();
+        assert_eq!(
+            vec!["outer", "middle", "inner", "outer", "middle", "inner"],
+            *log.borrow()
+        );
+    }
+
+    /// The guard short-circuits the refused calls.
+    #[test]
+    fn guard() {
+        let guard = Guard::new(|_ctl: &ServerCtl, method: &str, _params: &Option<Value>| {
+            if method.starts_with("admin.") {
+                Err(RpcError::new(-32_001, "Forbidden".to_owned(), None))
+            } else {
+                Ok(())
+            }
+        });
+        let server = Layered::new(Echo, guard);
+        let (ctl, _, _) = ServerCtl::new_test();
+        assert_eq!(
+            -32_001,
+            server
+                .rpc(&ctl, "admin.unknown", &None)
+                .unwrap()
+                .wait()
+                .unwrap_err()
+                .code
+        );
+        server.rpc(&ctl, "hello", &None).unwrap().wait().unwrap();
+        server
+            .notification(&ctl, "admin.notif", &None)
+            .unwrap()
+            .wait()
+            .unwrap_err();
+    }
+
+    /// Test modifications of the parameters and results.
+    #[test]
+    fn map() {
+        let params = MapParams::new(|method: &str, params: &Option<Value>| {
+            if method == "wrap" {
+                Some(json!({ "wrapped": params }))
+            } else {
+                params.clone()
+            }
+        });
+        let result = MapResult::new(|method: &str, result: Result<RawJson, RpcError>| {
+            match result {
+                Err(_) => Ok(RawJson::serialize(&method).unwrap()),
+                Ok(_) => Err(RpcError::new(42, method.to_owned(), None)),
+            }
+        });
+        let server = Layered::new(Echo, Stack::new(result, params));
+        let (ctl, _, _) = ServerCtl::new_test();
+        assert_eq!(
+            json!("error"),
+            server.rpc(&ctl, "error", &None).unwrap().wait().unwrap().to_value()
+        );
+        assert_eq!(
+            RpcError::new(42, "wrap".to_owned(), None),
+            server
+                .rpc(&ctl, "wrap", &Some(json!(1)))
+                .unwrap()
+                .wait()
+                .unwrap_err()
+        );
+        // Without the result mapping we see the rewritten params
+        let server = Layered::new(Echo, MapParams::new(|_: &str, _: &Option<Value>| None));
+        assert_eq!(
+            json!(["hello", null]),
+            server
+                .rpc(&ctl, "hello", &Some(json!(1)))
+                .unwrap()
+                .wait()
+                .unwrap()
+                .to_value()
+        );
+    }
+}
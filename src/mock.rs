@@ -0,0 +1,494 @@
+// Copyright 2017 tokio-jsonrpc Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! A scripted peer for tests.
+//!
+//! The [`MockPeer`](struct.MockPeer.html) plays the other side of a connection according to a
+//! script. The script is a sequence of steps ‒ sending messages, expecting messages and waiting.
+//! The steps are performed in order and the first message that doesn't match the expectation
+//! fails the whole script with a [`MockError`](struct.MockError.html) describing what went wrong.
+//!
+//! Once the script is finished, the mock peer closes the connection.
+//!
+//! # Examples
+//!
+//! Checking a client makes the right calls:
+//!
+//! ```rust
+//! # extern crate futures;
+//! # extern crate tokio_core;
+//! # extern crate tokio_jsonrpc;
+//! # #[macro_use]
+//! # extern crate serde_json;
+//! #
+//! # use std::time::Duration;
+//! # use futures::Future;
+//! # use tokio_core::reactor::Core;
+//! # use tokio_jsonrpc::Endpoint;
+//! # use tokio_jsonrpc::mock::MockPeer;
+//! #
+//! # fn main() {
+//! let mut core = Core::new().unwrap();
+//! let handle = core.handle();
+//! let (connection, verified) = MockPeer::new()
+//!     .expect_request("hello", Some(json!(["world"])))
+//!     .wait(Duration::from_millis(10))
+//!     .reply(Ok(json!("Hello world")))
+//!     .notify("bye", None)
+//!     .start(&handle);
+//! let (client, _finished) = Endpoint::client_only(connection).start(&handle);
+//! let answered = client.call("hello".to_owned(), Some(json!(["world"])), None)
+//!     .and_then(|(_client, answered)| answered);
+//! let (response, ()) = core.run(answered.join(verified.map_err(|e| panic!("{}", e))))
+//!     .unwrap();
+//! assert_eq!(json!("Hello world"), response.unwrap().result.unwrap());
+//! # }
+//! ```
+
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::io::Error as IoError;
+use std::time::Duration;
+
+use futures::{Future, IntoFuture, Sink, Stream};
+use futures::future::{loop_fn, Either, Loop};
+use serde_json::Value;
+use tokio_core::reactor::{Handle, Timeout};
+
+use memory::{pair, Memory};
+use message::{Message, Parsed, Request, RpcError};
+
+/// A failure of the script.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct MockError {
+    /// The index of the failed step, counted from 0.
+    pub step: usize,
+    /// What went wrong.
+    pub description: String,
+}
+
+impl Display for MockError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "Step {} failed: {}", self.step, self.description)
+    }
+}
+
+impl Error for MockError {
+    fn description(&self) -> &str {
+        &self.description
+    }
+}
+
+/// A future resolving once the whole script has been performed.
+pub type Verified = Box<Future<Item = (), Error = MockError>>;
+
+#[derive(Clone, Debug)]
+enum Step {
+    ExpectRequest(String, Option<Value>),
+    ExpectNotification(String, Option<Value>),
+    Reply(Result<Value, RpcError>),
+    Notify(String, Option<Value>),
+    Call(String, Option<Value>),
+    ExpectResponse(Result<Value, RpcError>),
+    Wait(Duration),
+    ExpectSilence(Duration),
+    ExpectEnd,
+}
+
+/// The builder of the script of a mock peer.
+///
+/// Each method adds a step to the end of the script.
+#[derive(Clone, Debug, Default)]
+pub struct MockPeer {
+    steps: Vec<Step>,
+}
+
+impl MockPeer {
+    /// Creates an empty script.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    fn step(mut self, step: Step) -> Self {
+        self.steps.push(step);
+        self
+    }
+    /// Expects a request with the given method and parameters to come next.
+    pub fn expect_request(self, method: &str, params: Option<Value>) -> Self {
+        self.step(Step::ExpectRequest(method.to_owned(), params))
+    }
+    /// Expects a notification with the given method and parameters to come next.
+    pub fn expect_notification(self, method: &str, params: Option<Value>) -> Self {
+        self.step(Step::ExpectNotification(method.to_owned(), params))
+    }
+    /// Replies to a request.
+    ///
+    /// It is the oldest expected request that hasn't been replied to yet.
+    pub fn reply(self, result: Result<Value, RpcError>) -> Self {
+        self.step(Step::Reply(result))
+    }
+    /// Sends a notification.
+    pub fn notify(self, method: &str, params: Option<Value>) -> Self {
+        self.step(Step::Notify(method.to_owned(), params))
+    }
+    /// Sends a request.
+    ///
+    /// The answer is checked by [`expect_response`](#method.expect_response).
+    pub fn call(self, method: &str, params: Option<Value>) -> Self {
+        self.step(Step::Call(method.to_owned(), params))
+    }
+    /// Expects a response to come next.
+    ///
+    /// It must answer the oldest request sent by [`call`](#method.call) that hasn't been answered
+    /// yet.
+    pub fn expect_response(self, result: Result<Value, RpcError>) -> Self {
+        self.step(Step::ExpectResponse(result))
+    }
+    /// Waits for the given time before the next step.
+    pub fn wait(self, time: Duration) -> Self {
+        self.step(Step::Wait(time))
+    }
+    /// Expects nothing to arrive for the given time.
+    pub fn expect_silence(self, time: Duration) -> Self {
+        self.step(Step::ExpectSilence(time))
+    }
+    /// Expects the other side to close the connection.
+    pub fn expect_end(self) -> Self {
+        self.step(Step::ExpectEnd)
+    }
+    /// Starts the script on an in-memory connection.
+    ///
+    /// It returns the other half of the connection (to be passed to the endpoint under test) and
+    /// the future performing the script. The future must be run for the script to progress.
+    pub fn start(self, handle: &Handle) -> (Memory, Verified) {
+        let (ours, theirs) = pair();
+        (theirs, self.run(ours, handle))
+    }
+    /// Runs the script on the given connection.
+    pub fn run<Connection>(self, connection: Connection, handle: &Handle) -> Verified
+    where
+        Connection: Stream<Item = Parsed, Error = IoError>,
+        Connection: Sink<SinkItem = Message, SinkError = IoError>,
+        Connection: 'static,
+    {
+        let state = State {
+            connection,
+            handle: handle.clone(),
+            requests: VecDeque::new(),
+            calls: VecDeque::new(),
+        };
+        let steps = self.steps.into_iter().enumerate().collect::<VecDeque<_>>();
+        let performed = loop_fn((state, steps), |(state, mut steps)| match steps.pop_front() {
+            None => Either::A(Ok(Loop::Break(())).into_future()),
+            Some((idx, step)) => {
+                let performed = state.perform(step).map_err(move |description| MockError {
+                    step: idx,
+                    description,
+                });
+                Either::B(performed.map(|state| Loop::Continue((state, steps))))
+            },
+        });
+        Box::new(performed)
+    }
+}
+
+/// What the script got so far.
+struct State<Connection> {
+    connection: Connection,
+    handle: Handle,
+    /// The expected requests not replied to yet.
+    requests: VecDeque<Request>,
+    /// The IDs of the calls not answered yet.
+    calls: VecDeque<Value>,
+}
+
+type StepResult<T> = Box<Future<Item = T, Error = String>>;
+
+impl<Connection> State<Connection>
+where
+    Connection: Stream<Item = Parsed, Error = IoError>,
+    Connection: Sink<SinkItem = Message, SinkError = IoError>,
+    Connection: 'static,
+{
+    fn perform(self, step: Step) -> StepResult<Self> {
+        match step {
+            Step::ExpectRequest(method, params) => self.receive(move |state, msg| match msg {
+                Message::Request(request) => {
+                    check_call(&method, &params, &request.method, &request.params)?;
+                    state.requests.push_back(request);
+                    Ok(())
+                },
+                other => Err(format!("Expected request {}, got {:?}", method, other)),
+            }),
+            Step::ExpectNotification(method, params) => {
+                self.receive(move |_state, msg| match msg {
+                    Message::Notification(notification) => check_call(
+                        &method,
+                        &params,
+                        &notification.method,
+                        &notification.params,
+                    ),
+                    other => Err(format!("Expected notification {}, got {:?}", method, other)),
+                })
+            },
+            Step::Reply(result) => {
+                let mut state = self;
+                match state.requests.pop_front() {
+                    None => Box::new(Err("No request to reply to".to_owned()).into_future()),
+                    Some(request) => {
+                        let reply = match result {
+                            Ok(value) => request.reply(value),
+                            Err(error) => request.error(error),
+                        };
+                        state.send(reply)
+                    },
+                }
+            },
+            Step::Notify(method, params) => self.send(Message::notification(method, params)),
+            Step::Call(method, params) => {
+                let msg = Message::request(method, params);
+                let mut state = self;
+                if let Message::Request(ref request) = msg {
+                    state.calls.push_back(request.id.clone());
+                }
+                state.send(msg)
+            },
+            Step::ExpectResponse(result) => self.receive(move |state, msg| match msg {
+                Message::Response(response) => {
+                    let expected_id = state
+                        .calls
+                        .pop_front()
+                        .ok_or_else(|| format!("Unexpected response {:?}", response))?;
+                    if response.id != expected_id {
+                        return Err(format!(
+                            "Expected response to {}, got response to {}",
+                            expected_id, response.id
+                        ));
+                    }
+                    if response.result != result {
+                        return Err(format!(
+                            "Expected result {:?}, got {:?}",
+                            result, response.result
+                        ));
+                    }
+                    Ok(())
+                },
+                other => Err(format!("Expected response, got {:?}", other)),
+            }),
+            Step::Wait(time) => {
+                let waited = Timeout::new(time, &self.handle)
+                    .into_future()
+                    .flatten()
+                    .map(|()| self)
+                    .map_err(|e| format!("Failed to wait: {}", e));
+                Box::new(waited)
+            },
+            Step::ExpectSilence(time) => self.expect_silence(time),
+            Step::ExpectEnd => {
+                let State {
+                    connection,
+                    handle,
+                    requests,
+                    calls,
+                } = self;
+                let ended = connection
+                    .into_future()
+                    .map_err(|(e, _connection)| format!("Connection failed: {}", e))
+                    .and_then(|(msg, connection)| match msg {
+                        None => Ok(State {
+                            connection,
+                            handle,
+                            requests,
+                            calls,
+                        }),
+                        Some(msg) => Err(format!("Expected end of connection, got {:?}", msg)),
+                    });
+                Box::new(ended)
+            },
+        }
+    }
+    fn send(self, msg: Message) -> StepResult<Self> {
+        let State {
+            connection,
+            handle,
+            requests,
+            calls,
+        } = self;
+        let sent = connection
+            .send(msg)
+            .map_err(|e| format!("Failed to send: {}", e))
+            .map(|connection| State {
+                connection,
+                handle,
+                requests,
+                calls,
+            });
+        Box::new(sent)
+    }
+    /// Receives a message and checks it by the closure.
+    fn receive<F>(self, check: F) -> StepResult<Self>
+    where
+        F: FnOnce(&mut Self, Message) -> Result<(), String> + 'static,
+    {
+        let State {
+            connection,
+            handle,
+            requests,
+            calls,
+        } = self;
+        let received = connection
+            .into_future()
+            .map_err(|(e, _connection)| format!("Connection failed: {}", e))
+            .and_then(move |(msg, connection)| {
+                let mut state = State {
+                    connection,
+                    handle,
+                    requests,
+                    calls,
+                };
+                match msg {
+                    None => Err("The connection was closed".to_owned()),
+                    Some(Err(broken)) => Err(format!("Received a broken message {:?}", broken)),
+                    Some(Ok(msg)) => check(&mut state, msg).map(|()| state),
+                }
+            });
+        Box::new(received)
+    }
+    fn expect_silence(self, time: Duration) -> StepResult<Self> {
+        let timeout = match Timeout::new(time, &self.handle) {
+            Ok(timeout) => timeout,
+            Err(e) => return Box::new(Err(format!("Failed to wait: {}", e)).into_future()),
+        };
+        let State {
+            connection,
+            handle,
+            requests,
+            calls,
+        } = self;
+        let silent = timeout
+            .map_err(|e| format!("Failed to wait: {}", e))
+            .select2(connection.into_future())
+            .then(|result| match result {
+                Ok(Either::A(((), received))) => {
+                    let connection = received
+                        .into_inner()
+                        .expect("The stream future didn't resolve yet");
+                    Ok(State {
+                        connection,
+                        handle,
+                        requests,
+                        calls,
+                    })
+                },
+                Ok(Either::B(((msg, _connection), _timeout))) => {
+                    Err(format!("Expected silence, got {:?}", msg))
+                },
+                Err(Either::A((e, _received))) => Err(e),
+                Err(Either::B(((e, _connection), _timeout))) => {
+                    Err(format!("Connection failed: {}", e))
+                },
+            });
+        Box::new(silent)
+    }
+}
+
+fn check_call(
+    method: &str, params: &Option<Value>, got_method: &str, got_params: &Option<Value>
+) -> Result<(), String> {
+    if method != got_method {
+        Err(format!("Expected method {}, got {}", method, got_method))
+    } else if params != got_params {
+        Err(format!(
+            "Expected parameters {:?} of {}, got {:?}",
+            params, method, got_params
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio_core::reactor::Core;
+
+    use super::*;
+    use endpoint::{Endpoint, ServerCtl};
+    use server::Server;
+
+    /// A server under test.
+    struct Echo;
+
+    impl Server for Echo {
+        type Success = Option<Value>;
+        type RpcCallResult = Result<Option<Value>, RpcError>;
+        type NotificationResult = Result<(), ()>;
+        fn rpc(
+            &self, _ctl: &ServerCtl, method: &str, params: &Option<Value>
+        ) -> Option<Self::RpcCallResult> {
+            match method {
+                "echo" => Some(Ok(params.clone())),
+                _ => None,
+            }
+        }
+    }
+
+    /// Script a conversation with a server.
+    #[test]
+    fn server() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (connection, verified) = MockPeer::new()
+            .call("echo", Some(json!([1, 2])))
+            .call("other", None)
+            .expect_response(Ok(json!([1, 2])))
+            .expect_response(Err(RpcError::method_not_found("other".to_owned())))
+            .expect_silence(Duration::from_millis(20))
+            .start(&handle);
+        let (_, finished) = Endpoint::new(connection, Echo).start(&handle);
+        core.run(verified.join(finished.map_err(|e| panic!("{}", e))))
+            .unwrap();
+    }
+
+    /// A mismatch is reported with the step that failed.
+    #[test]
+    fn mismatch() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (connection, verified) = MockPeer::new()
+            .expect_notification("hello", None)
+            .expect_notification("world", None)
+            .start(&handle);
+        let (client, _finished) = Endpoint::client_only(connection).start(&handle);
+        let sent = client
+            .notify("hello".to_owned(), None)
+            .and_then(|client| client.notify("world".to_owned(), Some(json!([]))))
+            .map_err(|e| panic!("{}", e));
+        let (_client, error) = core.run(sent.join(verified.then(|result| Ok(result.unwrap_err()))))
+            .unwrap();
+        assert_eq!(1, error.step);
+        assert!(error.description.contains("parameters"));
+    }
+
+    /// The end of the connection is detected.
+    #[test]
+    fn end() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (connection, verified) = MockPeer::new()
+            .expect_request("bye", None)
+            .reply(Ok(json!(true)))
+            .expect_end()
+            .start(&handle);
+        let (client, _finished) = Endpoint::client_only(connection).start(&handle);
+        let called = client
+            .call("bye".to_owned(), None, None)
+            .and_then(|(_client, answered)| answered)
+            .map(|response| assert_eq!(json!(true), response.unwrap().result.unwrap()))
+            .map_err(|e| panic!("{}", e));
+        core.run(called.join(verified)).unwrap();
+    }
+}
@@ -0,0 +1,562 @@
+// Copyright 2017 tokio-jsonrpc Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Subscriptions on top of notifications.
+//!
+//! JSON RPC itself knows only requests and notifications, but it is common to let the client
+//! subscribe to a stream of events. This module provides both sides of such protocol:
+//!
+//! * The [`Publisher`](struct.Publisher.html) is a server. Each *topic* is an RPC method that
+//!   subscribes to a stream of values. The publisher answers the call with a new subscription ID
+//!   and then sends each value of the stream as a notification. The subscription ends when the
+//!   stream ends, when the client calls the unsubscribe method or when the connection terminates.
+//! * The [`Subscriber`](struct.Subscriber.html) is a server for the other side. It receives the
+//!   notifications and routes them into [`Subscription`](struct.Subscription.html) streams.
+//!
+//! The notifications carry the subscription ID and the value as named parameters, eg:
+//!
+//! ```json
+//! {"jsonrpc": "2.0", "method": "subscription", "params": {"subscription": "id", "result": 42}}
+//! ```
+//!
+//! The unsubscribe method takes the subscription ID as its only parameter and returns `true` if
+//! such subscription existed.
+//!
+//! The names of the notification and of the unsubscribe method can be configured, but they have
+//! to match on both sides.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::io::{Error as IoError, ErrorKind};
+use std::rc::Rc;
+
+use futures::{Async, Future, Poll, Stream};
+use futures::stream;
+use futures::unsync::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use futures::unsync::oneshot::{channel as one_channel, Sender as OneSender};
+use serde::Serialize;
+use serde_json::{to_value, Value};
+use tokio_core::reactor::Handle;
+use uuid::Uuid;
+
+use endpoint::{Client, RpcSent, ServerCtl};
+use message::{Response, RpcError};
+use server::Server;
+// The module with the macro comes later, so it needs to be imported
+use jsonrpc_params;
+
+/// The default name of the notification carrying the subscribed values.
+pub const DEFAULT_NOTIFICATION: &str = "subscription";
+
+/// The default name of the method to end a subscription.
+pub const DEFAULT_UNSUBSCRIBE: &str = "unsubscribe";
+
+/// How many notifications for a yet unknown subscription the subscriber keeps.
+///
+/// It is also the number of yet unknown subscriptions to keep them for and the number of ended
+/// subscriptions remembered. See [`Subscriber`](struct.Subscriber.html) for why they may arrive.
+const MAX_EARLY: usize = 128;
+
+/// A stream of values for a single subscription, with the type erased.
+pub type Feed = Box<Stream<Item = Value, Error = ()>>;
+
+type Topic = Box<Fn(&ServerCtl, &Option<Value>) -> Result<Feed, RpcError>>;
+
+type Cancels = Rc<RefCell<HashMap<String, OneSender<()>>>>;
+
+/// The server side of subscriptions.
+///
+/// As with other servers, there's a separate instance for each connection. Register the topics
+/// before passing it to the endpoint. It can be combined with other servers by the
+/// [`ServerChain`](../server/struct.ServerChain.html).
+///
+/// # Examples
+///
+/// ```rust
+/// # extern crate futures;
+/// # extern crate tokio_core;
+/// # extern crate tokio_jsonrpc;
+/// # use futures::stream;
+/// # use tokio_core::reactor::Core;
+/// # use tokio_jsonrpc::RpcError;
+/// # use tokio_jsonrpc::pubsub::Publisher;
+/// # fn main() {
+/// # let core = Core::new().unwrap();
+/// # let handle = core.handle();
+/// // Calling `count` sends the numbers 0 to 9 as notifications.
+/// let publisher = Publisher::new(handle)
+///     .topic("count", |_ctl, _params| -> Result<_, RpcError> {
+///         Ok(stream::iter_ok::<_, ()>(0..10))
+///     });
+/// # let _ = publisher;
+/// # }
+/// ```
+pub struct Publisher {
+    handle: Handle,
+    topics: HashMap<String, Topic>,
+    notification: String,
+    unsubscribe: String,
+    subscriptions: Cancels,
+}
+
+impl Publisher {
+    /// Creates a publisher without any topics.
+    ///
+    /// The handle is used to run the subscriptions.
+    pub fn new(handle: Handle) -> Self {
+        Publisher {
+            handle,
+            topics: HashMap::new(),
+            notification: DEFAULT_NOTIFICATION.to_owned(),
+            unsubscribe: DEFAULT_UNSUBSCRIBE.to_owned(),
+            subscriptions: Rc::new(RefCell::new(HashMap::new())),
+        }
+    }
+    /// Sets the name of the notification the values are sent with.
+    pub fn notification_method(self, notification: String) -> Self {
+        Publisher {
+            notification,
+            ..self
+        }
+    }
+    /// Sets the name of the method ending a subscription.
+    pub fn unsubscribe_method(self, unsubscribe: String) -> Self {
+        Publisher {
+            unsubscribe,
+            ..self
+        }
+    }
+    /// Registers a topic.
+    ///
+    /// Calling the `method` subscribes to the topic. The `subscribe` callback gets the parameters
+    /// of the call and provides the stream of values, or an error to answer the call with.
+    ///
+    /// An error in the stream ends the subscription.
+    pub fn topic<F, S>(mut self, method: &str, subscribe: F) -> Self
+    where
+        F: Fn(&ServerCtl, &Option<Value>) -> Result<S, RpcError> + 'static,
+        S: Stream + 'static,
+        S::Item: Serialize,
+    {
+        let topic = move |ctl: &ServerCtl, params: &Option<Value>| -> Result<Feed, RpcError> {
+            let values = subscribe(ctl, params)?.map(|value| {
+                to_value(value).expect("Your value type is not convertible to JSON, which is a bug")
+            });
+            Ok(Box::new(values.map_err(|_| ())))
+        };
+        self.topics.insert(method.to_owned(), Box::new(topic));
+        self
+    }
+    /// Number of the currently active subscriptions.
+    pub fn subscriptions(&self) -> usize {
+        self.subscriptions.borrow().len()
+    }
+    fn subscribe(
+        &self, ctl: &ServerCtl, topic: &Topic, params: &Option<Value>
+    ) -> Result<Value, RpcError> {
+        let feed = topic(ctl, params)?;
+        let client = ctl.client()
+            .map_err(|e| RpcError::server_error(Some(format!("{}", e))))?;
+        let id = Uuid::new_v4().hyphenated().to_string();
+        let (cancel_sender, cancel_receiver) = one_channel();
+        self.subscriptions
+            .borrow_mut()
+            .insert(id.clone(), cancel_sender);
+        // A marker to stop the stream, either on unsubscription or termination of the connection.
+        // The end of the feed is marked the same way.
+        let stop = cancel_receiver
+            .then(|_| -> Result<(), ()> { Ok(()) })
+            .select(ctl.terminated())
+            .then(|_| Ok(None))
+            .into_stream();
+        let feed = feed.map(Some).chain(stream::once(Ok(None)));
+        let notification = self.notification.clone();
+        let subscriptions = self.subscriptions.clone();
+        let id_notified = id.clone();
+        let id_finished = id.clone();
+        let notified = feed.select(stop)
+            .take_while(|value| Ok(value.is_some()))
+            .fold(client, move |client, value| {
+                let params = json!({
+                    "subscription": id_notified,
+                    "result": value.unwrap(),
+                });
+                client
+                    .notify(notification.clone(), Some(params))
+                    .map_err(|_| ())
+            })
+            .then(move |_| -> Result<(), ()> {
+                subscriptions.borrow_mut().remove(&id_finished);
+                Ok(())
+            });
+        self.handle.spawn(notified);
+        Ok(Value::String(id))
+    }
+    fn unsubscribe(&self, params: &Option<Value>) -> Option<Result<Value, RpcError>> {
+        let (id,) = jsonrpc_params!(params, "subscription" => String);
+        let cancel = self.subscriptions.borrow_mut().remove(&id);
+        let found = cancel.map(|cancel| cancel.send(())).is_some();
+        Some(Ok(Value::Bool(found)))
+    }
+}
+
+impl Server for Publisher {
+    type Success = Value;
+    type RpcCallResult = Result<Value, RpcError>;
+    type NotificationResult = Result<(), ()>;
+    fn rpc(
+        &self, ctl: &ServerCtl, method: &str, params: &Option<Value>
+    ) -> Option<Self::RpcCallResult> {
+        if method == self.unsubscribe {
+            self.unsubscribe(params)
+        } else {
+            self.topics
+                .get(method)
+                .map(|topic| self.subscribe(ctl, topic, params))
+        }
+    }
+}
+
+#[derive(Default)]
+struct SubscriberState {
+    /// The subscriptions with someone listening.
+    active: HashMap<String, UnboundedSender<Value>>,
+    /// Notifications that came before the subscription was known.
+    early: HashMap<String, Vec<Value>>,
+    /// The IDs in the `early`, the oldest first.
+    early_order: VecDeque<String>,
+    /// Recently ended subscriptions, the notifications still on the way for them are dropped.
+    ended: VecDeque<String>,
+}
+
+impl SubscriberState {
+    /// Keeps a notification of a yet unknown subscription.
+    ///
+    /// If there are too many unknown subscriptions, the oldest one is forgotten.
+    fn keep_early(&mut self, id: &str, value: &Value) {
+        if self.ended.iter().any(|ended| ended == id) {
+            return;
+        }
+        if let Some(early) = self.early.get_mut(id) {
+            if early.len() < MAX_EARLY {
+                early.push(value.clone());
+            }
+            return;
+        }
+        if self.early_order.len() >= MAX_EARLY {
+            let oldest = self.early_order.pop_front().expect("Can't be empty if full");
+            self.early.remove(&oldest);
+        }
+        self.early_order.push_back(id.to_owned());
+        self.early.insert(id.to_owned(), vec![value.clone()]);
+    }
+    /// Takes the early notifications of a subscription.
+    fn claim_early(&mut self, id: &str) -> Vec<Value> {
+        self.early_order.retain(|early| early != id);
+        self.early.remove(id).unwrap_or_default()
+    }
+    /// Forgets a subscription, ignoring its notifications from now on.
+    fn end(&mut self, id: &str) {
+        self.active.remove(id);
+        self.claim_early(id);
+        if self.ended.len() >= MAX_EARLY {
+            self.ended.pop_front();
+        }
+        self.ended.push_back(id.to_owned());
+    }
+}
+
+/// The client side of subscriptions.
+///
+/// This is a server that receives the notifications and routes them to the right
+/// [`Subscription`](struct.Subscription.html). Pass one clone of it to the endpoint (possibly
+/// through a [`ServerChain`](../server/struct.ServerChain.html)) and keep another one to
+/// subscribe with.
+///
+/// The notifications for a subscription may arrive before the answer to the call that created
+/// it. Therefore, notifications of unknown subscriptions are kept for a while in the hope they'll
+/// be claimed soon.
+#[derive(Clone)]
+pub struct Subscriber {
+    notification: String,
+    unsubscribe: String,
+    state: Rc<RefCell<SubscriberState>>,
+}
+
+impl Subscriber {
+    /// Creates a subscriber without any subscriptions.
+    pub fn new() -> Self {
+        Subscriber {
+            notification: DEFAULT_NOTIFICATION.to_owned(),
+            unsubscribe: DEFAULT_UNSUBSCRIBE.to_owned(),
+            state: Rc::new(RefCell::new(SubscriberState::default())),
+        }
+    }
+    /// Sets the name of the notification the values come with.
+    pub fn notification_method(self, notification: String) -> Self {
+        Subscriber {
+            notification,
+            ..self
+        }
+    }
+    /// Sets the name of the method ending a subscription.
+    pub fn unsubscribe_method(self, unsubscribe: String) -> Self {
+        Subscriber {
+            unsubscribe,
+            ..self
+        }
+    }
+    /// Subscribes to a topic.
+    ///
+    /// This calls the `method` on the other side, through the client. If the other side answers
+    /// with a subscription ID, the values of the subscription are provided by the returned
+    /// [`Subscription`](struct.Subscription.html). Otherwise, the error of the call is returned.
+    ///
+    /// The client needs to belong to the endpoint this subscriber is used in.
+    pub fn subscribe(
+        &self, client: Client, method: String, params: Option<Value>
+    ) -> Box<Future<Item = (Client, Result<Subscription, RpcError>), Error = IoError>> {
+        let subscriber = self.clone();
+        let subscribed = client
+            .call(method, params, None)
+            .and_then(|(client, answered)| answered.map(|response| (client, response)))
+            .and_then(move |(client, response)| match response {
+                // We don't set any timeout, so we must get some answer
+                None => Err(IoError::new(ErrorKind::Other, "No answer to subscription")),
+                Some(Response { result: Err(e), .. }) => Ok((client, Err(e))),
+                Some(Response {
+                    result: Ok(Value::String(id)),
+                    ..
+                }) => {
+                    let subscription = subscriber.register(&client, id);
+                    Ok((client, Ok(subscription)))
+                },
+                Some(Response {
+                    result: Ok(id), ..
+                }) => {
+                    let msg = format!("Invalid subscription ID {}", id);
+                    Ok((client, Err(RpcError::server_error(Some(msg)))))
+                },
+            });
+        Box::new(subscribed)
+    }
+    fn register(&self, client: &Client, id: String) -> Subscription {
+        let (sender, receiver) = unbounded();
+        let mut state = self.state.borrow_mut();
+        for value in state.claim_early(&id) {
+            // Can't fail, we hold the receiver
+            sender.unbounded_send(value).unwrap();
+        }
+        state.active.insert(id.clone(), sender);
+        Subscription {
+            id,
+            receiver,
+            terminated: client.server_ctl().terminated(),
+            unsubscribe: self.unsubscribe.clone(),
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl Default for Subscriber {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Server for Subscriber {
+    type Success = ();
+    type RpcCallResult = Result<(), RpcError>;
+    type NotificationResult = Result<(), ()>;
+    fn notification(
+        &self, _ctl: &ServerCtl, method: &str, params: &Option<Value>
+    ) -> Option<Self::NotificationResult> {
+        if method != self.notification {
+            return None;
+        }
+        let (id, value) = match *params {
+            Some(Value::Object(ref map)) => match (map.get("subscription"), map.get("result")) {
+                (Some(Value::String(id)), Some(value)) => (id, value),
+                _ => return Some(Err(())),
+            },
+            _ => return Some(Err(())),
+        };
+        let mut state = self.state.borrow_mut();
+        let closed = match state.active.get(id) {
+            Some(sender) => sender.unbounded_send(value.clone()).is_err(),
+            None => {
+                state.keep_early(id, value);
+                false
+            },
+        };
+        if closed {
+            state.end(id);
+        }
+        Some(Ok(()))
+    }
+}
+
+/// A stream of values of a single subscription.
+///
+/// Created by [`Subscriber::subscribe`](struct.Subscriber.html#method.subscribe). It ends when
+/// the connection terminates. Dropping it stops routing values into it and the values that still
+/// come are ignored, but it doesn't tell the other side ‒ use
+/// [`unsubscribe`](#method.unsubscribe) for that.
+pub struct Subscription {
+    id: String,
+    receiver: UnboundedReceiver<Value>,
+    terminated: Box<Future<Item = (), Error = ()>>,
+    unsubscribe: String,
+    state: Rc<RefCell<SubscriberState>>,
+}
+
+impl Subscription {
+    /// The ID of the subscription, as assigned by the other side.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+    /// Ends the subscription.
+    ///
+    /// Calls the unsubscribe method on the other side through the client.
+    pub fn unsubscribe(self, client: Client) -> RpcSent {
+        let params = json!([self.id]);
+        client.call(self.unsubscribe.clone(), Some(params), None)
+    }
+}
+
+impl Stream for Subscription {
+    type Item = Value;
+    type Error = ();
+    fn poll(&mut self) -> Poll<Option<Value>, ()> {
+        match self.receiver.poll()? {
+            Async::NotReady => (),
+            ready => return Ok(ready),
+        }
+        // Deliver everything that arrived before terminating.
+        match self.terminated.poll()? {
+            Async::Ready(()) => Ok(Async::Ready(None)),
+            Async::NotReady => Ok(Async::NotReady),
+        }
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.state.borrow_mut().end(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use futures::{future, Sink};
+    use futures::unsync::mpsc::channel;
+    use tokio_core::reactor::Core;
+
+    use super::*;
+
+    /// Test the publisher alone.
+    ///
+    /// The notifications would go into the void, but we can check the subscriptions are handled.
+    #[test]
+    fn publisher() {
+        let mut core = Core::new().unwrap();
+        let (ctl, _dropped, _killed) = ServerCtl::new_test();
+        let (sender, receiver) = channel::<()>(1);
+        // Count how many subscriptions were asked for. The stream never ends on its own.
+        let asked = Rc::new(Cell::new(0));
+        let asked_cloned = asked.clone();
+        let receiver = RefCell::new(Some(receiver));
+        let publisher = Publisher::new(core.handle()).topic(
+            "ticks",
+            move |_ctl: &ServerCtl, params: &Option<Value>| {
+                assert_eq!(&Some(json!([1])), params);
+                asked_cloned.set(asked_cloned.get() + 1);
+                Ok(receiver.borrow_mut().take().unwrap())
+            },
+        );
+        assert!(publisher.rpc(&ctl, "other", &None).is_none());
+        let id = publisher
+            .rpc(&ctl, "ticks", &Some(json!([1])))
+            .unwrap()
+            .unwrap();
+        assert_eq!(1, asked.get());
+        assert_eq!(1, publisher.subscriptions());
+        core.run(sender.send(())).unwrap();
+        assert_eq!(
+            Ok(Value::Bool(true)),
+            publisher.rpc(&ctl, "unsubscribe", &Some(json!([id]))).unwrap()
+        );
+        assert_eq!(0, publisher.subscriptions());
+        assert_eq!(
+            Ok(Value::Bool(false)),
+            publisher.rpc(&ctl, "unsubscribe", &Some(json!([id]))).unwrap()
+        );
+        publisher
+            .rpc(&ctl, "unsubscribe", &None)
+            .unwrap()
+            .unwrap_err();
+    }
+
+    /// The subscriber keeps the notifications of subscriptions it doesn't know yet.
+    #[test]
+    fn subscriber_early() {
+        let subscriber = Subscriber::new().notification_method("tick".to_owned());
+        let (ctl, _dropped, _killed) = ServerCtl::new_test();
+        let notify = |method: &str, params: Value| {
+            subscriber
+                .notification(&ctl, method, &Some(params))
+                .map(|r| r.is_ok())
+        };
+        assert_eq!(None, notify("subscription", json!({})));
+        assert_eq!(Some(false), notify("tick", json!([1, 2])));
+        assert_eq!(
+            Some(true),
+            notify("tick", json!({"subscription": "a", "result": 1}))
+        );
+        assert_eq!(
+            Some(true),
+            notify("tick", json!({"subscription": "a", "result": 2}))
+        );
+        let early = subscriber.state.borrow().early.get("a").cloned();
+        assert_eq!(Some(vec![json!(1), json!(2)]), early);
+    }
+
+    /// Stale notifications don't push out the early ones of new subscriptions.
+    #[test]
+    fn subscriber_stale() {
+        let subscriber = Subscriber::new();
+        let (ctl, _dropped, _killed) = ServerCtl::new_test();
+        let notify = |id: &str| {
+            let params = json!({"subscription": id, "result": 1});
+            subscriber
+                .notification(&ctl, DEFAULT_NOTIFICATION, &Some(params))
+                .unwrap()
+                .unwrap();
+        };
+        // The values still coming to a dropped subscription are ignored
+        let (_sender, receiver) = unbounded();
+        drop(Subscription {
+            id: "dead".to_owned(),
+            receiver,
+            terminated: Box::new(future::empty()),
+            unsubscribe: DEFAULT_UNSUBSCRIBE.to_owned(),
+            state: subscriber.state.clone(),
+        });
+        notify("dead");
+        assert!(subscriber.state.borrow().early.is_empty());
+        // Too many unknown ones push out the oldest
+        for i in 0..MAX_EARLY + 1 {
+            notify(&i.to_string());
+        }
+        let state = subscriber.state.borrow();
+        assert_eq!(MAX_EARLY, state.early.len());
+        assert!(!state.early.contains_key("0"));
+        assert!(state.early.contains_key(&MAX_EARLY.to_string()));
+    }
+}
@@ -0,0 +1,324 @@
+// Copyright 2017 tokio-jsonrpc Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Rate limiting of the incoming calls.
+//!
+//! While the [`limit`](../limit/index.html) module caps how many calls run at once, this one caps
+//! how often they may come. The [`RateLimits`](struct.RateLimits.html), set by
+//! [`Endpoint::rate_limits`](../endpoint/struct.Endpoint.html#method.rate_limits), configure
+//! token buckets for the whole connection and for single methods. Each call takes a token from
+//! the bucket of the connection and from the bucket of its method. If there's none, the call is
+//! throttled ‒ an RPC is answered with the configured error and a notification is dropped. The
+//! server doesn't see either of them.
+//!
+//! The buckets belong to the endpoint, so each connection has its own. Accept the connections as
+//! usual and set the same limits on each of their endpoints.
+//!
+//! The throttled calls are logged with the logger of the endpoint, together with counters of how
+//! many calls were throttled on the connection so far, and a summary is logged once the
+//! connection ends. Put something identifying the peer (eg. its address) into the logger to see
+//! who is being throttled.
+//!
+//! # Examples
+//!
+//! ```rust
+//! # extern crate tokio_jsonrpc;
+//! #
+//! # use std::time::Duration;
+//! #
+//! # use tokio_jsonrpc::RpcError;
+//! # use tokio_jsonrpc::rate::{Rate, RateLimits};
+//! #
+//! # fn main() {
+//! // 100 calls a second with bursts of up to 200 and at most one report a minute
+//! let limits = RateLimits::new()
+//!     .connection(Rate::new(100, Duration::from_secs(1)).burst(200))
+//!     .method("report", Rate::new(1, Duration::from_secs(60)))
+//!     .reject(RpcError::new(-32_000, "Slow down".to_owned(), None));
+//! # }
+//! ```
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use slog::Logger;
+
+use message::RpcError;
+
+/// The error code the throttled RPCs are answered with by default.
+pub const RATE_LIMITED: i64 = -32_029;
+
+/// A rate of calls, for a token bucket.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Rate {
+    count: u32,
+    per: Duration,
+    burst: u32,
+}
+
+impl Rate {
+    /// Allows `count` calls per the `per` time on average.
+    ///
+    /// By default, all the `count` calls may come at once.
+    ///
+    /// # Panics
+    ///
+    /// If either `count` or `per` is zero.
+    pub fn new(count: u32, per: Duration) -> Self {
+        assert!(count > 0, "The rate needs to allow at least one call");
+        assert!(per > Duration::from_secs(0), "The rate needs a non-zero time");
+        Rate {
+            count,
+            per,
+            burst: count,
+        }
+    }
+    /// Sets how many calls may come at once, after a quiet period.
+    ///
+    /// This is the size of the bucket.
+    ///
+    /// # Panics
+    ///
+    /// If `burst` is zero.
+    pub fn burst(self, burst: u32) -> Self {
+        assert!(burst > 0, "The bucket needs to hold at least one call");
+        Rate { burst, ..self }
+    }
+}
+
+/// Configuration of the rate limits of an endpoint.
+///
+/// By default, there are no limits and the throttled RPCs are answered with the
+/// [`RATE_LIMITED`](constant.RATE_LIMITED.html) error code.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RateLimits {
+    connection: Option<Rate>,
+    methods: Vec<(String, Rate)>,
+    reject: RpcError,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        RateLimits {
+            connection: None,
+            methods: Vec::new(),
+            reject: RpcError::new(RATE_LIMITED, "Rate limit exceeded".to_owned(), None),
+        }
+    }
+}
+
+impl RateLimits {
+    /// Creates the configuration without any limits.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Limits the rate of all the calls on the connection.
+    pub fn connection(self, rate: Rate) -> Self {
+        RateLimits {
+            connection: Some(rate),
+            ..self
+        }
+    }
+    /// Limits the rate of calls of a single method.
+    ///
+    /// The calls still count towards the limit of the connection. Setting the same method again
+    /// replaces its rate.
+    pub fn method(mut self, method: &str, rate: Rate) -> Self {
+        self.methods.push((method.to_owned(), rate));
+        self
+    }
+    /// Sets the error the throttled RPCs are answered with.
+    pub fn reject(self, error: RpcError) -> Self {
+        RateLimits {
+            reject: error,
+            ..self
+        }
+    }
+}
+
+/// Seconds in a duration, as a float.
+fn seconds(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) * 1e-9
+}
+
+/// A token bucket.
+struct Bucket {
+    rate: Rate,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(rate: Rate, now: Instant) -> Self {
+        Bucket {
+            rate,
+            tokens: f64::from(rate.burst),
+            updated: now,
+        }
+    }
+    /// Adds the tokens for the time since the last refill and checks there's one to take.
+    fn refill(&mut self, now: Instant) -> bool {
+        if now > self.updated {
+            let added = seconds(now - self.updated) * f64::from(self.rate.count)
+                / seconds(self.rate.per);
+            self.tokens = (self.tokens + added).min(f64::from(self.rate.burst));
+            self.updated = now;
+        }
+        self.tokens >= 1.0
+    }
+    fn take(&mut self) {
+        self.tokens -= 1.0;
+    }
+}
+
+/// How many calls went through and how many were throttled.
+#[derive(Default)]
+struct Counters {
+    passed: u64,
+    throttled_rpcs: u64,
+    throttled_notifications: u64,
+}
+
+struct State {
+    connection: Option<Bucket>,
+    methods: HashMap<String, Bucket>,
+    counters: Counters,
+}
+
+/// The rate limits of an endpoint, in action.
+pub(crate) struct RateLimiter {
+    state: RefCell<State>,
+    reject: RpcError,
+    logger: Logger,
+}
+
+impl RateLimiter {
+    pub fn new(limits: RateLimits, logger: Logger) -> Self {
+        let now = Instant::now();
+        let methods = limits
+            .methods
+            .into_iter()
+            .map(|(method, rate)| (method, Bucket::new(rate, now)))
+            .collect();
+        RateLimiter {
+            state: RefCell::new(State {
+                connection: limits.connection.map(|rate| Bucket::new(rate, now)),
+                methods,
+                counters: Counters::default(),
+            }),
+            reject: limits.reject,
+            logger,
+        }
+    }
+    /// Takes the tokens for a call.
+    ///
+    /// Returns the error to reject it with, if it is throttled.
+    pub fn throttle(&self, method: &str, notification: bool) -> Option<RpcError> {
+        self.throttle_at(method, notification, Instant::now())
+    }
+    fn throttle_at(&self, method: &str, notification: bool, now: Instant) -> Option<RpcError> {
+        let mut state = self.state.borrow_mut();
+        let state = &mut *state;
+        // Take the tokens only if there are both of them, a throttled call doesn't cost anything
+        let allowed = {
+            let mut buckets = state
+                .connection
+                .iter_mut()
+                .chain(state.methods.get_mut(method))
+                .collect::<Vec<_>>();
+            let allowed = buckets.iter_mut().all(|bucket| bucket.refill(now));
+            if allowed {
+                for bucket in buckets {
+                    bucket.take();
+                }
+            }
+            allowed
+        };
+        let counters = &mut state.counters;
+        if allowed {
+            counters.passed += 1;
+            return None;
+        }
+        if notification {
+            counters.throttled_notifications += 1;
+        } else {
+            counters.throttled_rpcs += 1;
+        }
+        debug!(
+            self.logger, "Call throttled";
+            "method" => method,
+            "notification" => notification,
+            "throttled_rpcs" => counters.throttled_rpcs,
+            "throttled_notifications" => counters.throttled_notifications
+        );
+        Some(self.reject.clone())
+    }
+}
+
+impl Drop for RateLimiter {
+    fn drop(&mut self) {
+        let counters = &self.state.borrow().counters;
+        if counters.throttled_rpcs + counters.throttled_notifications > 0 {
+            info!(
+                self.logger, "Calls were throttled";
+                "passed" => counters.passed,
+                "throttled_rpcs" => counters.throttled_rpcs,
+                "throttled_notifications" => counters.throttled_notifications,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use slog::Discard;
+
+    use super::*;
+
+    /// A bucket that would never hold a token is refused.
+    #[test]
+    #[should_panic]
+    fn zero_burst() {
+        Rate::new(1, Duration::from_secs(1)).burst(0);
+    }
+
+    /// The tokens refill with time, both in the connection and the method buckets.
+    #[test]
+    fn buckets() {
+        let limiter = RateLimiter::new(
+            RateLimits::new()
+                .connection(Rate::new(10, Duration::from_secs(1)).burst(3))
+                .method("slow", Rate::new(1, Duration::from_secs(1))),
+            Logger::root(Discard, o!()),
+        );
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+        assert!(limiter.throttle_at("slow", false, at(0)).is_none());
+        // The method is throttled, but the connection still has tokens
+        let error = limiter.throttle_at("slow", false, at(0)).unwrap();
+        assert_eq!(RATE_LIMITED, error.code);
+        assert!(limiter.throttle_at("fast", true, at(0)).is_none());
+        assert!(limiter.throttle_at("fast", true, at(0)).is_none());
+        // Now the connection is exhausted too
+        assert!(limiter.throttle_at("fast", true, at(0)).is_some());
+        // One token for the connection in 100 ms
+        assert!(limiter.throttle_at("fast", true, at(100)).is_none());
+        assert!(limiter.throttle_at("fast", true, at(100)).is_some());
+        // The throttled slow call didn't take the token of the connection
+        assert!(limiter.throttle_at("slow", false, at(1000)).is_none());
+        // The burst caps the refill
+        for _ in 0..3 {
+            assert!(limiter.throttle_at("fast", true, at(10_000)).is_none());
+        }
+        assert!(limiter.throttle_at("fast", true, at(10_000)).is_some());
+        let state = limiter.state.borrow();
+        assert_eq!(8, state.counters.passed);
+        assert_eq!(1, state.counters.throttled_rpcs);
+        assert_eq!(3, state.counters.throttled_notifications);
+    }
+}
@@ -0,0 +1,618 @@
+// Copyright 2017 tokio-jsonrpc Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! A client that survives a lost connection.
+//!
+//! The [`Endpoint`](../endpoint/struct.Endpoint.html) is bound to a single connection. Once the
+//! connection is lost, the endpoint terminates and its [`Client`](../endpoint/struct.Client.html)
+//! is of no further use. The [`ReconnectingClient`](struct.ReconnectingClient.html) creates a
+//! new connection (and a new endpoint on top of it) whenever the current one is lost.
+//!
+//! It is configured by the [`Reconnect`](struct.Reconnect.html) builder:
+//!
+//! * It takes a factory that creates the connections.
+//! * The delay between attempts grows according to the [`Backoff`](struct.Backoff.html).
+//! * The calls that were in progress when the connection got lost are either failed or sent again
+//!   over the new connection, according to the [`PendingPolicy`](enum.PendingPolicy.html).
+//! * A handshake may be run on each new connection before it is used (eg. to log in or to
+//!   subscribe to something).
+//!
+//! The changes of the connection [`State`](enum.State.html) can be watched through a stream.
+
+use std::cell::RefCell;
+use std::cmp;
+use std::io::{Error as IoError, ErrorKind};
+use std::rc::Rc;
+use std::time::Duration;
+
+use futures::{Future, IntoFuture, Poll, Sink, Stream};
+use futures::unsync::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use futures::unsync::oneshot::{channel as one_channel, Sender as OneSender};
+use serde_json::Value;
+use slog::{Discard, Logger};
+use tokio_core::reactor::{Handle, Timeout};
+
+use endpoint::{Client, Endpoint};
+use message::{Message, Parsed, Response, RpcError};
+use server::Server;
+
+/// How long to wait between the connection attempts.
+///
+/// The first attempt after a lost connection waits the initial delay. Each failed attempt
+/// multiplies the delay by the factor, up to the maximum.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    factor: u32,
+    attempts: Option<usize>,
+}
+
+impl Backoff {
+    /// Creates a backoff doubling the delay each time.
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Backoff {
+            initial,
+            max,
+            factor: 2,
+            attempts: None,
+        }
+    }
+    /// Sets how many times the delay grows after each failed attempt.
+    pub fn factor(self, factor: u32) -> Self {
+        Backoff { factor, ..self }
+    }
+    /// Gives up after this many failed attempts in a row.
+    ///
+    /// By default, it never gives up.
+    pub fn attempts(self, attempts: usize) -> Self {
+        Backoff {
+            attempts: Some(attempts),
+            ..self
+        }
+    }
+    /// The delay before the given attempt (counted from 0).
+    pub fn delay(&self, attempt: usize) -> Duration {
+        let mut delay = self.initial;
+        for _ in 0..attempt {
+            if delay >= self.max {
+                break;
+            }
+            delay *= self.factor;
+        }
+        cmp::min(delay, self.max)
+    }
+}
+
+impl Default for Backoff {
+    /// Starts at 100ms and grows up to 30s.
+    fn default() -> Self {
+        Backoff::new(Duration::from_millis(100), Duration::from_secs(30))
+    }
+}
+
+/// What happens to the calls that were waiting for an answer when the connection got lost.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum PendingPolicy {
+    /// Fail them with the error of the connection.
+    Fail,
+    /// Send them again once a new connection is established.
+    ///
+    /// Note that the other side might have already executed the call before the connection got
+    /// lost, so it may be executed twice. Use this only for idempotent calls.
+    Reissue,
+}
+
+impl Default for PendingPolicy {
+    /// Fails the calls, as sending them again is not safe in general.
+    fn default() -> Self {
+        PendingPolicy::Fail
+    }
+}
+
+/// The state of the connection of a [`ReconnectingClient`](struct.ReconnectingClient.html).
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum State {
+    /// A connection is being established (including the handshake).
+    Connecting,
+    /// The client is connected.
+    Connected,
+    /// The connection got lost and the client waits before trying again.
+    Disconnected,
+    /// The client ran out of connection attempts. This is a final state.
+    GaveUp,
+    /// The client was closed. This is a final state.
+    Closed,
+}
+
+impl State {
+    fn is_final(&self) -> bool {
+        *self == State::GaveUp || *self == State::Closed
+    }
+}
+
+type BoxFuture<T> = Box<Future<Item = T, Error = IoError>>;
+
+type Connect = Box<Fn(&Handle, &Logger) -> BoxFuture<Client>>;
+
+type Handshake = Box<Fn(Client) -> BoxFuture<Client>>;
+
+/// A server that knows nothing, but doesn't terminate either.
+struct Idle;
+
+impl Server for Idle {
+    type Success = ();
+    type RpcCallResult = Result<(), RpcError>;
+    type NotificationResult = Result<(), ()>;
+}
+
+/// The builder of a [`ReconnectingClient`](struct.ReconnectingClient.html).
+pub struct Reconnect {
+    connect: Connect,
+    backoff: Backoff,
+    policy: PendingPolicy,
+    handshake: Option<Handshake>,
+    logger: Logger,
+}
+
+impl Reconnect {
+    /// Creates the builder.
+    ///
+    /// The `connect` factory is called each time a new connection is needed. The endpoints on
+    /// the connections don't answer any calls from the other side.
+    pub fn new<F, R, Connection>(connect: F) -> Self
+    where
+        F: Fn(&Handle) -> R + 'static,
+        R: IntoFuture<Item = Connection, Error = IoError>,
+        R::Future: 'static,
+        Connection: Stream<Item = Parsed, Error = IoError>,
+        Connection: Sink<SinkItem = Message, SinkError = IoError>,
+        Connection: 'static,
+    {
+        Self::with_server(connect, || Idle)
+    }
+    /// Creates the builder with a server for the other side to call.
+    ///
+    /// As each connection needs its own endpoint, a new server is created for each connection
+    /// by the `server` factory. The server shall not terminate its endpoint, as that is considered
+    /// to be a lost connection.
+    pub fn with_server<F, R, Connection, S, RpcServer>(connect: F, server: S) -> Self
+    where
+        F: Fn(&Handle) -> R + 'static,
+        R: IntoFuture<Item = Connection, Error = IoError>,
+        R::Future: 'static,
+        Connection: Stream<Item = Parsed, Error = IoError>,
+        Connection: Sink<SinkItem = Message, SinkError = IoError>,
+        Connection: 'static,
+        S: Fn() -> RpcServer + 'static,
+        RpcServer: Server + 'static,
+    {
+        let server = Rc::new(server);
+        let connect = move |handle: &Handle, logger: &Logger| -> BoxFuture<Client> {
+            let handle = handle.clone();
+            let logger = logger.clone();
+            let server = server.clone();
+            let started = connect(&handle).into_future().map(move |connection| {
+                let (client, finished) = Endpoint::new(connection, server())
+                    .logger(logger.clone())
+                    .start(&handle);
+                let reported = finished.map_err(move |e| {
+                    debug!(logger, "Connection failed"; "error" => format!("{}", e));
+                });
+                handle.spawn(reported);
+                client
+            });
+            Box::new(started)
+        };
+        Reconnect {
+            connect: Box::new(connect),
+            backoff: Backoff::default(),
+            policy: PendingPolicy::default(),
+            handshake: None,
+            logger: Logger::root(Discard, o!()),
+        }
+    }
+    /// Sets the backoff between connection attempts.
+    pub fn backoff(self, backoff: Backoff) -> Self {
+        Reconnect { backoff, ..self }
+    }
+    /// Sets what happens to the calls in progress when the connection is lost.
+    pub fn pending(self, policy: PendingPolicy) -> Self {
+        Reconnect { policy, ..self }
+    }
+    /// Sets a handshake to run on each new connection.
+    ///
+    /// The handshake gets the client of the new connection and yields it back once done. The
+    /// connection is used for the calls only after that. If the handshake fails, the connection
+    /// is dropped and another one is tried.
+    pub fn handshake<H, R>(self, handshake: H) -> Self
+    where
+        H: Fn(Client) -> R + 'static,
+        R: IntoFuture<Item = Client, Error = IoError>,
+        R::Future: 'static,
+    {
+        let handshake = move |client| -> BoxFuture<Client> {
+            Box::new(handshake(client).into_future())
+        };
+        Reconnect {
+            handshake: Some(Box::new(handshake)),
+            ..self
+        }
+    }
+    /// Sets the logger used by the client and the endpoints.
+    pub fn logger(self, logger: Logger) -> Self {
+        Reconnect { logger, ..self }
+    }
+    /// Starts connecting.
+    ///
+    /// The first connection attempt is made right away.
+    pub fn start(self, handle: &Handle) -> ReconnectingClient {
+        let shared = Rc::new(Shared {
+            handle: handle.clone(),
+            connect: self.connect,
+            backoff: self.backoff,
+            policy: self.policy,
+            handshake: self.handshake,
+            logger: self.logger,
+            inner: RefCell::new(Inner {
+                state: State::Disconnected,
+                client: None,
+                generation: 0,
+                queue: Vec::new(),
+                watchers: Vec::new(),
+            }),
+        });
+        Shared::connect(&shared, 0);
+        ReconnectingClient {
+            _closer: Rc::new(Closer(shared.clone())),
+            shared,
+        }
+    }
+}
+
+/// A call waiting for an answer.
+struct PendingCall {
+    method: String,
+    params: Option<Value>,
+    timeout: Option<Duration>,
+    sender: OneSender<Result<Option<Response>, IoError>>,
+}
+
+impl PendingCall {
+    fn fail(self, kind: ErrorKind, msg: &str) {
+        // If nobody waits for the answer, nobody cares
+        let _ = self.sender.send(Err(IoError::new(kind, msg)));
+    }
+}
+
+struct Inner {
+    state: State,
+    /// The client of the current connection, if connected.
+    client: Option<Client>,
+    /// Serial number of the connections, to recognize events of the old ones.
+    generation: usize,
+    /// Calls waiting for a connection.
+    queue: Vec<PendingCall>,
+    watchers: Vec<UnboundedSender<State>>,
+}
+
+struct Shared {
+    handle: Handle,
+    connect: Connect,
+    backoff: Backoff,
+    policy: PendingPolicy,
+    handshake: Option<Handshake>,
+    logger: Logger,
+    inner: RefCell<Inner>,
+}
+
+impl Shared {
+    fn set_state(&self, state: State) {
+        let mut inner = self.inner.borrow_mut();
+        if inner.state == state {
+            return;
+        }
+        debug!(self.logger, "Connection state changed"; "state" => format!("{:?}", state));
+        inner.state = state;
+        inner
+            .watchers
+            .retain(|watcher| watcher.unbounded_send(state).is_ok());
+        if state.is_final() {
+            // Ends the streams
+            inner.watchers.clear();
+        }
+    }
+    fn is_final(&self) -> bool {
+        self.inner.borrow().state.is_final()
+    }
+    /// Fails all the queued calls and moves to the final state.
+    fn finish(&self, state: State, msg: &str) {
+        let (client, queue) = {
+            let mut inner = self.inner.borrow_mut();
+            (inner.client.take(), inner.queue.drain(..).collect::<Vec<_>>())
+        };
+        self.set_state(state);
+        if let Some(client) = client {
+            client.server_ctl().terminate();
+        }
+        for call in queue {
+            call.fail(ErrorKind::NotConnected, msg);
+        }
+    }
+    fn connect(shared: &Rc<Self>, attempt: usize) {
+        if shared.is_final() {
+            return;
+        }
+        shared.set_state(State::Connecting);
+        let shared_handshake = shared.clone();
+        let shared_done = shared.clone();
+        let connected = (shared.connect)(&shared.handle, &shared.logger)
+            .and_then(move |client| -> BoxFuture<Client> {
+                match shared_handshake.handshake {
+                    None => Box::new(Ok(client).into_future()),
+                    Some(ref handshake) => {
+                        let ctl = client.server_ctl().clone();
+                        Box::new(handshake(client).map_err(move |e| {
+                            // Get rid of the half-initialized connection
+                            ctl.kill();
+                            e
+                        }))
+                    },
+                }
+            })
+            .then(move |result| {
+                match result {
+                    Ok(client) => Shared::connected(&shared_done, client),
+                    Err(e) => {
+                        info!(shared_done.logger, "Failed to connect";
+                              "error" => format!("{}", e), "attempt" => attempt);
+                        Shared::retry(&shared_done, attempt + 1);
+                    },
+                }
+                Ok(())
+            });
+        shared.handle.spawn(connected);
+    }
+    fn connected(shared: &Rc<Self>, client: Client) {
+        if shared.is_final() {
+            client.server_ctl().terminate();
+            return;
+        }
+        let (generation, queue) = {
+            let mut inner = shared.inner.borrow_mut();
+            inner.generation += 1;
+            inner.client = Some(client.clone());
+            (inner.generation, inner.queue.drain(..).collect::<Vec<_>>())
+        };
+        shared.set_state(State::Connected);
+        let shared_cloned = shared.clone();
+        let watch = client.server_ctl().terminated().then(move |_| {
+            Shared::disconnected(&shared_cloned, generation);
+            Ok(())
+        });
+        shared.handle.spawn(watch);
+        for call in queue {
+            Shared::dispatch(shared, call);
+        }
+    }
+    fn disconnected(shared: &Rc<Self>, generation: usize) {
+        {
+            let mut inner = shared.inner.borrow_mut();
+            if inner.generation != generation || inner.state.is_final() {
+                return;
+            }
+            inner.client.take();
+        }
+        info!(shared.logger, "Connection lost");
+        Shared::retry(shared, 0);
+    }
+    fn retry(shared: &Rc<Self>, attempt: usize) {
+        if shared.is_final() {
+            return;
+        }
+        match shared.backoff.attempts {
+            Some(attempts) if attempt >= attempts => {
+                info!(shared.logger, "Giving up reconnecting"; "attempts" => attempt);
+                shared.finish(State::GaveUp, "Gave up reconnecting");
+                return;
+            },
+            _ => (),
+        }
+        shared.set_state(State::Disconnected);
+        let delay = shared.backoff.delay(attempt);
+        let shared_cloned = shared.clone();
+        match Timeout::new(delay, &shared.handle) {
+            Ok(timeout) => {
+                let delayed = timeout.then(move |_| {
+                    Shared::connect(&shared_cloned, attempt);
+                    Ok(())
+                });
+                shared.handle.spawn(delayed);
+            },
+            // No way to wait, so try right away
+            Err(_) => Shared::connect(shared, attempt),
+        }
+    }
+    fn dispatch(shared: &Rc<Self>, call: PendingCall) {
+        let (client, generation) = {
+            let mut inner = shared.inner.borrow_mut();
+            if inner.state.is_final() {
+                drop(inner);
+                return call.fail(ErrorKind::NotConnected, "The client is not connecting anymore");
+            }
+            match inner.client {
+                Some(ref client) => (client.clone(), inner.generation),
+                None => return inner.queue.push(call),
+            }
+        };
+        let PendingCall {
+            method,
+            params,
+            timeout,
+            sender,
+        } = call;
+        let shared_cloned = shared.clone();
+        let called = client
+            .call(method.clone(), params.clone(), timeout)
+            .and_then(|(_client, answered)| answered)
+            .then(move |result| {
+                match result {
+                    Ok(response) => drop(sender.send(Ok(response))),
+                    // Refused by the client itself, sending again won't help
+                    Err(ref e) if e.kind() == ErrorKind::InvalidInput => {
+                        drop(sender.send(Err(IoError::new(e.kind(), e.to_string()))))
+                    },
+                    Err(e) => match shared_cloned.policy {
+                        PendingPolicy::Fail => drop(sender.send(Err(e))),
+                        PendingPolicy::Reissue => {
+                            let call = PendingCall {
+                                method,
+                                params,
+                                timeout,
+                                sender,
+                            };
+                            let current = shared_cloned.inner.borrow().generation;
+                            if current == generation {
+                                // The loss of the connection wasn't noticed yet, wait for the
+                                // next one.
+                                shared_cloned.inner.borrow_mut().queue.push(call);
+                            } else {
+                                Shared::dispatch(&shared_cloned, call);
+                            }
+                        },
+                    },
+                }
+                Ok(())
+            });
+        shared.handle.spawn(called);
+    }
+}
+
+/// The client part of a connection that is re-established when lost.
+///
+/// Created by [`Reconnect::start`](struct.Reconnect.html#method.start). It can be cloned and all
+/// the clones share the same connection.
+///
+/// Calls made while there's no connection wait until one is established. The client keeps
+/// reconnecting until it gives up (according to the [`Backoff`](struct.Backoff.html)), until it
+/// is [`close`](#method.close)d or until all its clones are dropped.
+#[derive(Clone)]
+pub struct ReconnectingClient {
+    shared: Rc<Shared>,
+    // Shared by the clones, only to be dropped with the last of them
+    _closer: Rc<Closer>,
+}
+
+impl ReconnectingClient {
+    /// Calls an RPC.
+    ///
+    /// Unlike [`Client::call`](../endpoint/struct.Client.html#method.call), this resolves only
+    /// once the answer comes. The timeout (if any) applies to each attempt separately.
+    pub fn call(
+        &self, method: String, params: Option<Value>, timeout: Option<Duration>
+    ) -> Box<Future<Item = Option<Response>, Error = IoError>> {
+        let (sender, receiver) = one_channel();
+        Shared::dispatch(
+            &self.shared,
+            PendingCall {
+                method,
+                params,
+                timeout,
+                sender,
+            },
+        );
+        let answered = receiver
+            .map_err(|_| IoError::new(ErrorKind::Other, "Lost connection"))
+            .and_then(|result| result);
+        Box::new(answered)
+    }
+    /// Sends a notification.
+    ///
+    /// Notifications don't wait for a connection, it fails if there's no connection right now.
+    pub fn notify(
+        &self, method: String, params: Option<Value>
+    ) -> Box<Future<Item = (), Error = IoError>> {
+        match self.client() {
+            Some(client) => Box::new(client.notify(method, params).map(|_client| ())),
+            None => {
+                let error = IoError::new(ErrorKind::NotConnected, "Not connected");
+                Box::new(Err(error).into_future())
+            },
+        }
+    }
+    /// The client of the current connection, if there's one.
+    pub fn client(&self) -> Option<Client> {
+        self.shared.inner.borrow().client.clone()
+    }
+    /// The current state of the connection.
+    pub fn state(&self) -> State {
+        self.shared.inner.borrow().state
+    }
+    /// A stream of the state changes.
+    ///
+    /// The first item is the current state. The stream ends after a final state.
+    pub fn states(&self) -> StateStream {
+        let (sender, receiver) = unbounded();
+        let mut inner = self.shared.inner.borrow_mut();
+        // Can't fail, we have the receiver
+        sender.unbounded_send(inner.state).unwrap();
+        if !inner.state.is_final() {
+            inner.watchers.push(sender);
+        }
+        StateStream(receiver)
+    }
+    /// Stops reconnecting.
+    ///
+    /// The current connection is terminated (once the answers to the calls in progress arrive)
+    /// and the calls waiting for a connection fail.
+    pub fn close(&self) {
+        self.shared.finish(State::Closed, "The client was closed");
+    }
+}
+
+/// Closes the client once the last clone of it is dropped.
+///
+/// The background tasks hold the shared state too, so they'd keep reconnecting forever without
+/// this.
+struct Closer(Rc<Shared>);
+
+impl Drop for Closer {
+    fn drop(&mut self) {
+        if !self.0.is_final() {
+            self.0.finish(State::Closed, "The client was dropped");
+        }
+    }
+}
+
+/// A stream of state changes of a [`ReconnectingClient`](struct.ReconnectingClient.html).
+pub struct StateStream(UnboundedReceiver<State>);
+
+impl Stream for StateStream {
+    type Item = State;
+    type Error = ();
+    fn poll(&mut self) -> Poll<Option<State>, ()> {
+        self.0.poll()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Check the delays grow as they should.
+    #[test]
+    fn backoff() {
+        let backoff = Backoff::new(Duration::from_millis(100), Duration::from_secs(1));
+        assert_eq!(Duration::from_millis(100), backoff.delay(0));
+        assert_eq!(Duration::from_millis(200), backoff.delay(1));
+        assert_eq!(Duration::from_millis(800), backoff.delay(3));
+        assert_eq!(Duration::from_secs(1), backoff.delay(4));
+        assert_eq!(Duration::from_secs(1), backoff.delay(1000));
+        let backoff = backoff.factor(10);
+        assert_eq!(Duration::from_secs(1), backoff.delay(1));
+    }
+}
@@ -0,0 +1,451 @@
+// Copyright 2017 tokio-jsonrpc Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Recording and replaying the traffic.
+//!
+//! To debug problems that are hard to reproduce, the [`Recorder`](struct.Recorder.html) can be
+//! put around the connection passed to an [`Endpoint`](../endpoint/struct.Endpoint.html). It
+//! writes every message that goes through it, in both directions, as a line of JSON
+//! ([JSON Lines](http://jsonlines.org)). Each line is an [`Entry`](struct.Entry.html).
+//!
+//! The recording can be read back by [`read_entries`](fn.read_entries.html) and its inbound part
+//! replayed against a server by [`replay`](fn.replay.html). The responses the server produces
+//! are compared with the recorded ones.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::io::{BufRead, Error as IoError, ErrorKind, Write};
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use futures::{Async, AsyncSink, Future, IntoFuture, Poll, Sink, StartSend, Stream};
+use serde_json::{from_str as json_from_str, from_value, to_vec, Value};
+use tokio_core::reactor::Handle;
+
+use endpoint::Endpoint;
+use message::{decoded_to_parsed, message_value, Broken, Message, Parsed, Response};
+use server::Server;
+
+/// Which way a message went.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    /// Received from the other side.
+    In,
+    /// Sent to the other side.
+    Out,
+}
+
+/// A single recorded message.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Entry {
+    /// When it happened, in milliseconds since the UNIX epoch.
+    pub time: u64,
+    /// Which way the message went.
+    pub direction: Direction,
+    /// The message itself.
+    ///
+    /// For a received message that isn't valid JSON RPC, this is the JSON that was received. The
+    /// same goes for the invalid entries of a batch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<Value>,
+    /// The error if a received message wasn't even valid JSON.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+fn now() -> u64 {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    since_epoch.as_secs() * 1000 + u64::from(since_epoch.subsec_millis())
+}
+
+impl Entry {
+    /// Records a received message.
+    pub fn inbound(parsed: &Parsed) -> Self {
+        let (message, error) = match *parsed {
+            Ok(ref message) => (Some(message_value(message)), None),
+            Err(Broken::Unmatched(ref value, _)) => (Some(value.clone()), None),
+            Err(Broken::SyntaxError(ref error)) => (None, Some(error.clone())),
+        };
+        Entry {
+            time: now(),
+            direction: Direction::In,
+            message,
+            error,
+        }
+    }
+    /// Records a sent message.
+    pub fn outbound(message: &Message) -> Self {
+        Entry {
+            time: now(),
+            direction: Direction::Out,
+            message: Some(message_value(message)),
+            error: None,
+        }
+    }
+    /// Reconstructs the message.
+    pub fn parsed(&self) -> Parsed {
+        match (self.message.as_ref(), self.error.as_ref()) {
+            (Some(message), _) => decoded_to_parsed(from_value(message.clone())),
+            (None, Some(error)) => Err(Broken::SyntaxError(error.clone())),
+            (None, None) => Err(Broken::SyntaxError("Nothing recorded".to_owned())),
+        }
+    }
+}
+
+/// A connection wrapper recording all the traffic.
+///
+/// Each message is written to the output as soon as it is received or accepted for sending. Each
+/// entry is written by a single `write_all` call. The output is flushed whenever the connection
+/// is flushed.
+///
+/// Failing to write the recording fails the connection.
+pub struct Recorder<Connection, Output> {
+    connection: Connection,
+    output: Output,
+}
+
+impl<Connection, Output: Write> Recorder<Connection, Output> {
+    /// Wraps the connection.
+    pub fn new(connection: Connection, output: Output) -> Self {
+        Recorder { connection, output }
+    }
+    /// Unwraps the connection and the output back.
+    pub fn into_inner(self) -> (Connection, Output) {
+        (self.connection, self.output)
+    }
+    fn write(&mut self, entry: &Entry) -> Result<(), IoError> {
+        let mut line = to_vec(entry)?;
+        line.push(b'\n');
+        self.output.write_all(&line)
+    }
+}
+
+impl<Connection, Output> Stream for Recorder<Connection, Output>
+where
+    Connection: Stream<Item = Parsed, Error = IoError>,
+    Output: Write,
+{
+    type Item = Parsed;
+    type Error = IoError;
+    fn poll(&mut self) -> Poll<Option<Parsed>, IoError> {
+        let polled = self.connection.poll()?;
+        if let Async::Ready(Some(ref parsed)) = polled {
+            self.write(&Entry::inbound(parsed))?;
+        }
+        Ok(polled)
+    }
+}
+
+impl<Connection, Output> Sink for Recorder<Connection, Output>
+where
+    Connection: Sink<SinkItem = Message, SinkError = IoError>,
+    Output: Write,
+{
+    type SinkItem = Message;
+    type SinkError = IoError;
+    fn start_send(&mut self, message: Message) -> StartSend<Message, IoError> {
+        let entry = Entry::outbound(&message);
+        let result = self.connection.start_send(message)?;
+        if let AsyncSink::Ready = result {
+            self.write(&entry)?;
+        }
+        Ok(result)
+    }
+    fn poll_complete(&mut self) -> Poll<(), IoError> {
+        let polled = self.connection.poll_complete()?;
+        self.output.flush()?;
+        Ok(polled)
+    }
+    fn close(&mut self) -> Poll<(), IoError> {
+        let polled = self.connection.close()?;
+        self.output.flush()?;
+        Ok(polled)
+    }
+}
+
+/// Reads a recording.
+///
+/// Empty lines are skipped.
+pub fn read_entries<Input: BufRead>(input: Input) -> Result<Vec<Entry>, IoError> {
+    let mut entries = Vec::new();
+    for line in input.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        entries.push(json_from_str(&line)?);
+    }
+    Ok(entries)
+}
+
+/// A response that differs between the recording and the replay.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Difference {
+    /// The ID of the request the response belongs to.
+    pub id: Value,
+    /// The recorded response, if there was one.
+    pub recorded: Option<Response>,
+    /// The response produced during the replay, if there was one.
+    pub replayed: Option<Response>,
+}
+
+/// The connection used for the replay.
+///
+/// It feeds the recorded messages to the endpoint and collects whatever is sent.
+struct Replayed {
+    inbound: VecDeque<Parsed>,
+    outbound: Rc<RefCell<Vec<Message>>>,
+}
+
+impl Stream for Replayed {
+    type Item = Parsed;
+    type Error = IoError;
+    fn poll(&mut self) -> Poll<Option<Parsed>, IoError> {
+        Ok(Async::Ready(self.inbound.pop_front()))
+    }
+}
+
+impl Sink for Replayed {
+    type SinkItem = Message;
+    type SinkError = IoError;
+    fn start_send(&mut self, message: Message) -> StartSend<Message, IoError> {
+        self.outbound.borrow_mut().push(message);
+        Ok(AsyncSink::Ready)
+    }
+    fn poll_complete(&mut self) -> Poll<(), IoError> {
+        Ok(Async::Ready(()))
+    }
+}
+
+/// Puts the responses (including the ones inside batches) into the output.
+fn responses(messages: Vec<Message>, output: &mut Vec<Response>) {
+    for message in messages {
+        match message {
+            Message::Response(response) => output.push(response),
+            Message::RawResponse(response) => output.push(response.parsed()),
+            Message::Batch(batch) => responses(batch, output),
+            _ => (),
+        }
+    }
+}
+
+/// Pairs the responses by their IDs and returns the ones that differ.
+fn compare(recorded: Vec<Response>, replayed: Vec<Response>) -> Vec<Difference> {
+    // More responses may have the same ID (eg. the null for parse errors), these are paired in
+    // order.
+    let mut by_id = HashMap::<String, VecDeque<Response>>::new();
+    for response in replayed {
+        by_id
+            .entry(response.id.to_string())
+            .or_default()
+            .push_back(response);
+    }
+    let mut differences = Vec::new();
+    for response in recorded {
+        let replayed = by_id
+            .get_mut(&response.id.to_string())
+            .and_then(VecDeque::pop_front);
+        if replayed.as_ref() != Some(&response) {
+            differences.push(Difference {
+                id: response.id.clone(),
+                recorded: Some(response),
+                replayed,
+            });
+        }
+    }
+    let mut extra = by_id.into_values().flatten().collect::<Vec<_>>();
+    extra.sort_by_key(|response| response.id.to_string());
+    differences.extend(extra.into_iter().map(|response| Difference {
+        id: response.id.clone(),
+        recorded: None,
+        replayed: Some(response),
+    }));
+    differences
+}
+
+/// Replays the received messages of a recording against a server.
+///
+/// The recorded inbound messages are fed to an endpoint with the server, as if they came from
+/// the other side. Once the endpoint terminates, the responses it sent are compared with the
+/// recorded outbound responses. The future resolves with the differences (empty if the server
+/// behaves the same as during the recording).
+///
+/// Only the responses are compared. Note that if the server calls RPCs on the other side, these
+/// get new IDs, so the recorded answers to them don't match.
+pub fn replay<RpcServer>(
+    entries: &[Entry], server: RpcServer, handle: &Handle
+) -> Box<Future<Item = Vec<Difference>, Error = IoError>>
+where
+    RpcServer: Server + 'static,
+{
+    let inbound = entries
+        .iter()
+        .filter(|entry| entry.direction == Direction::In)
+        .map(Entry::parsed)
+        .collect();
+    let mut recorded = Vec::new();
+    let recorded_messages = entries
+        .iter()
+        .filter(|entry| entry.direction == Direction::Out)
+        .map(Entry::parsed)
+        .collect::<Result<Vec<_>, _>>();
+    match recorded_messages {
+        Ok(messages) => responses(messages, &mut recorded),
+        Err(broken) => {
+            let msg = format!("Broken message in the recording: {:?}", broken);
+            return Box::new(Err(IoError::new(ErrorKind::InvalidData, msg)).into_future());
+        },
+    }
+    let outbound = Rc::new(RefCell::new(Vec::new()));
+    let connection = Replayed {
+        inbound,
+        outbound: outbound.clone(),
+    };
+    // Drop the client right away, so the endpoint terminates once everything is answered
+    let (_, finished) = Endpoint::new(connection, server).start(handle);
+    let compared = finished.map(move |()| {
+        let mut replayed = Vec::new();
+        responses(outbound.replace(Vec::new()), &mut replayed);
+        compare(recorded, replayed)
+    });
+    Box::new(compared)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use tokio_core::reactor::Core;
+
+    use super::*;
+    use endpoint::ServerCtl;
+    use memory::pair;
+    use message::RpcError;
+
+    /// An output we can look into afterwards.
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, IoError> {
+            self.0.borrow_mut().write(buf)
+        }
+        fn flush(&mut self) -> Result<(), IoError> {
+            Ok(())
+        }
+    }
+
+    /// Answers with a counter, so each run differs after a change of the start.
+    struct Counter(Cell<u64>);
+
+    impl Server for Counter {
+        type Success = u64;
+        type RpcCallResult = Result<u64, RpcError>;
+        type NotificationResult = Result<(), ()>;
+        fn rpc(
+            &self, _ctl: &ServerCtl, method: &str, _params: &Option<Value>
+        ) -> Option<Self::RpcCallResult> {
+            match method {
+                "next" => {
+                    let value = self.0.get();
+                    self.0.set(value + 1);
+                    Some(Ok(value))
+                },
+                _ => None,
+            }
+        }
+    }
+
+    /// Record a conversation with a server and replay it.
+    #[test]
+    fn record_replay() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let output = Shared::default();
+        let (left, right) = pair();
+        let recorded = Recorder::new(left, output.clone());
+        let (_, server_finished) = Endpoint::new(recorded, Counter(Cell::new(0))).start(&handle);
+        let (client, _finished) = Endpoint::client_only(right).start(&handle);
+        let calls = client
+            .call("next".to_owned(), None, None)
+            .and_then(|(client, answered)| answered.map(|_| client))
+            .and_then(|client| client.call("other".to_owned(), None, None))
+            .and_then(|(client, answered)| answered.map(|_| client))
+            .and_then(|client| client.notify("hello".to_owned(), None))
+            // Drop the client, so everything terminates
+            .map(drop);
+        core.run(calls.join(server_finished)).unwrap();
+
+        let entries = read_entries(&output.0.borrow()[..]).unwrap();
+        let directions = entries
+            .iter()
+            .map(|entry| entry.direction)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                Direction::In,
+                Direction::Out,
+                Direction::In,
+                Direction::Out,
+                Direction::In,
+            ],
+            directions
+        );
+
+        // The same server does the same
+        let same = replay(&entries, Counter(Cell::new(0)), &handle);
+        assert!(core.run(same).unwrap().is_empty());
+        // But a changed one doesn't
+        let different = replay(&entries, Counter(Cell::new(1)), &handle);
+        let differences = core.run(different).unwrap();
+        assert_eq!(1, differences.len());
+        assert_eq!(Ok(json!(0)), differences[0].recorded.as_ref().unwrap().result);
+        assert_eq!(Ok(json!(1)), differences[0].replayed.as_ref().unwrap().result);
+    }
+
+    /// Broken messages survive the recording.
+    #[test]
+    fn broken() {
+        let syntax = Err(Broken::SyntaxError("Oops".to_owned()));
+        let unmatched = Err(Broken::Unmatched(
+            json!({"hello": "world"}),
+            "missing jsonrpc version".to_owned(),
+        ));
+        for parsed in &[syntax, unmatched] {
+            assert_eq!(*parsed, Entry::inbound(parsed).parsed());
+        }
+    }
+
+    /// A batch with invalid entries is recorded as it came and replays the same.
+    #[test]
+    fn broken_batch() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let batch = json!([1, {"jsonrpc": "2.0", "method": "x"}]);
+        let parsed = decoded_to_parsed(from_value(batch.clone()));
+        let output = Shared::default();
+        let connection = Replayed {
+            inbound: vec![parsed.clone()].into(),
+            outbound: Rc::new(RefCell::new(Vec::new())),
+        };
+        let recorded = Recorder::new(connection, output.clone());
+        let (_, finished) = Endpoint::new(recorded, Counter(Cell::new(0))).start(&handle);
+        core.run(finished).unwrap();
+
+        let entries = read_entries(&output.0.borrow()[..]).unwrap();
+        assert_eq!(2, entries.len());
+        assert_eq!(Some(batch), entries[0].message);
+        assert_eq!(parsed, entries[0].parsed());
+        // The invalid entry is answered and the replay answers the same
+        assert_eq!(Direction::Out, entries[1].direction);
+        let replayed = replay(&entries, Counter(Cell::new(0)), &handle);
+        assert!(core.run(replayed).unwrap().is_empty());
+    }
+}
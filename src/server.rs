@@ -11,12 +11,18 @@
 //! here. Furthermore, some helpers for convenient creation and composition of servers are
 //! available. Note that not all of these helpers are necessarily zero-cost, at least at this time.
 
-use futures::{Future, IntoFuture};
+use std::collections::HashSet;
+use std::fmt::{Debug, Formatter, Result as FmtResult};
+use std::collections::btree_map::{BTreeMap, Keys};
+
+use futures::{Future, IntoFuture, Poll, Stream};
+use futures::unsync::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
 use serde::Serialize;
-use serde_json::{to_value, Value};
+use serde_json::Value;
 
 use endpoint::ServerCtl;
-use message::RpcError;
+use introspection::MethodInfo;
+use message::{Notification, RawJson, RawParams, RpcError};
 
 /// The server endpoint.
 ///
@@ -69,11 +75,43 @@ pub trait Server {
     ) -> Option<Self::NotificationResult> {
         None
     }
+    /// Called when the client requests something, with the parameters kept raw.
+    ///
+    /// This is called instead of [`rpc`](#method.rpc) if the connection parses the messages with
+    /// raw parameters (eg. with the [`RawLine`](../codec/struct.RawLine.html) codec). Overriding it
+    /// allows deserializing the parameters directly into the needed type, without building a
+    /// `Value` first. The `raw` form of the [`jsonrpc_params`](../macro.jsonrpc_params.html) macro
+    /// does that.
+    ///
+    /// The default implementation converts the parameters to a `Value` and calls `rpc`.
+    fn rpc_raw(
+        &self, ctl: &ServerCtl, method: &str, params: &Option<RawParams>
+    ) -> Option<Self::RpcCallResult> {
+        self.rpc(ctl, method, &params.as_ref().map(RawParams::to_value))
+    }
+    /// Called when the client sends a notification, with the parameters kept raw.
+    ///
+    /// This is the counterpart of [`rpc_raw`](#method.rpc_raw) for notifications. The default
+    /// implementation converts the parameters to a `Value` and calls
+    /// [`notification`](#method.notification).
+    fn notification_raw(
+        &self, ctl: &ServerCtl, method: &str, params: &Option<RawParams>
+    ) -> Option<Self::NotificationResult> {
+        self.notification(ctl, method, &params.as_ref().map(RawParams::to_value))
+    }
     /// Called when the endpoint is initialized.
     ///
     /// It provides a default empty implementation, which can be overriden to hook onto the
     /// initialization.
     fn initialized(&self, _ctl: &ServerCtl) {}
+    /// Describes the methods the server provides.
+    ///
+    /// This is used for introspection only (see the
+    /// [`introspection`](../introspection/index.html) module), the endpoint doesn't check the
+    /// calls against it. The default implementation describes nothing.
+    fn methods(&self) -> Vec<MethodInfo> {
+        Vec::new()
+    }
 }
 
 /// A RPC server that knows no methods.
@@ -93,13 +131,114 @@ impl Server for Empty {
     }
 }
 
+/// A server that passes the notifications into a stream.
+///
+/// This is for clients that only want to react to some notifications from the other side,
+/// without implementing the whole [`Server`](trait.Server.html). The notifications come out of the
+/// [`NotificationStream`](struct.NotificationStream.html) created together with the forwarder.
+/// It can be used directly by the endpoint or combined with other servers in a
+/// [`ServerChain`](struct.ServerChain.html).
+///
+/// Notifications of methods not accepted by the forwarder are refused, as are all RPCs. The
+/// notifications are refused also after the stream has been dropped.
+///
+/// Unlike [`Empty`](struct.Empty.html), this doesn't terminate the server part, so the connection
+/// is kept open until the other side closes it or it is terminated through the
+/// [`ServerCtl`](../endpoint/struct.ServerCtl.html).
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// # extern crate futures;
+/// # extern crate tokio_core;
+/// # extern crate tokio_io;
+/// # extern crate tokio_jsonrpc;
+/// # use futures::{Future, Stream};
+/// # use tokio_core::reactor::Core;
+/// # use tokio_core::net::TcpStream;
+/// # use tokio_io::AsyncRead;
+/// # use tokio_jsonrpc::{Endpoint, LineCodec};
+/// # use tokio_jsonrpc::server::NotificationForwarder;
+/// # fn main() {
+/// let mut core = Core::new().unwrap();
+/// let handle = core.handle();
+/// let stream = core.run(TcpStream::connect(&"127.0.0.1:2345".parse().unwrap(), &handle))
+///     .unwrap();
+/// let (forwarder, notifications) = NotificationForwarder::methods(vec!["time"]);
+/// let (_client, _finished) = Endpoint::new(stream.framed(LineCodec::new()), forwarder)
+///     .start(&handle);
+/// let printed = notifications.for_each(|notification| {
+///     println!("Time is {:?}", notification.params);
+///     Ok(())
+/// });
+/// core.run(printed).unwrap();
+/// # }
+/// ```
+pub struct NotificationForwarder {
+    methods: Option<HashSet<String>>,
+    sender: UnboundedSender<Notification>,
+}
+
+impl NotificationForwarder {
+    /// Creates a forwarder accepting all notifications.
+    pub fn new() -> (Self, NotificationStream) {
+        Self::create(None)
+    }
+    /// Creates a forwarder accepting only notifications with the given method names.
+    pub fn methods<I, S>(methods: I) -> (Self, NotificationStream)
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self::create(Some(methods.into_iter().map(Into::into).collect()))
+    }
+    fn create(methods: Option<HashSet<String>>) -> (Self, NotificationStream) {
+        let (sender, receiver) = unbounded();
+        (
+            NotificationForwarder { methods, sender },
+            NotificationStream(receiver),
+        )
+    }
+}
+
+impl Server for NotificationForwarder {
+    type Success = ();
+    type RpcCallResult = Result<(), RpcError>;
+    type NotificationResult = Result<(), ()>;
+    fn notification(
+        &self, _ctl: &ServerCtl, method: &str, params: &Option<Value>
+    ) -> Option<Self::NotificationResult> {
+        match self.methods {
+            Some(ref methods) if !methods.contains(method) => return None,
+            _ => (),
+        }
+        let notification = Notification::new(method.to_owned(), params.clone());
+        // If nobody listens anymore, we don't know the notification
+        self.sender.unbounded_send(notification).ok().map(Ok)
+    }
+}
+
+/// The notifications passed by a [`NotificationForwarder`](struct.NotificationForwarder.html).
+///
+/// The stream ends once the forwarder is dropped (which happens when the endpoint using it
+/// terminates).
+pub struct NotificationStream(UnboundedReceiver<Notification>);
+
+impl Stream for NotificationStream {
+    type Item = Notification;
+    type Error = ();
+    fn poll(&mut self) -> Poll<Option<Notification>, ()> {
+        self.0.poll()
+    }
+}
+
 /// An RPC server wrapper with dynamic dispatch.
 ///
 /// This server wraps another server and converts it into a common ground, so multiple different
-/// servers can be used as trait objects. Basically, it boxes the futures it returns and converts
-/// the result into `serde_json::Value`. It can then be used together with
-/// [`ServerChain`](struct.ServerChain.html) easilly. Note that this conversion incurs
-/// runtime costs.
+/// servers can be used as trait objects. Basically, it boxes the futures it returns and serializes
+/// the result into [`RawJson`](../message/struct.RawJson.html). It can then be used together with
+/// [`ServerChain`](struct.ServerChain.html) easilly. Note that the boxing incurs runtime costs,
+/// but the result is serialized only once ‒ the endpoint sends the JSON text as it is.
 pub struct AbstractServer<S: Server>(S);
 
 impl<S: Server> AbstractServer<S> {
@@ -114,39 +253,61 @@ impl<S: Server> AbstractServer<S> {
 }
 
 /// A RPC call result wrapping trait objects.
-pub type BoxRpcCallResult = Box<Future<Item = Value, Error = RpcError>>;
+///
+/// The success is already serialized, so it is turned into JSON only once, no matter how many
+/// layers of servers it passes through.
+pub type BoxRpcCallResult = Box<Future<Item = RawJson, Error = RpcError>>;
 /// A notification call result wrapping trait objects.
 pub type BoxNotificationResult = Box<Future<Item = (), Error = ()>>;
 
+/// Boxes the result of an RPC call and serializes its success.
+fn box_rpc<S: Server>(result: S::RpcCallResult) -> BoxRpcCallResult {
+    let future = result.into_future().map(|result| {
+        RawJson::serialize(&result)
+            .expect("Your result type is not convertible to JSON, which is a bug")
+    });
+    Box::new(future)
+}
+
+/// Boxes the result of a notification.
+fn box_notification<S: Server>(result: S::NotificationResult) -> BoxNotificationResult {
+    Box::new(result.into_future())
+}
+
 impl<S: Server> Server for AbstractServer<S> {
-    type Success = Value;
+    type Success = RawJson;
     type RpcCallResult = BoxRpcCallResult;
     type NotificationResult = BoxNotificationResult;
     fn rpc(
         &self, ctl: &ServerCtl, method: &str, params: &Option<Value>
     ) -> Option<Self::RpcCallResult> {
-        self.0
-            .rpc(ctl, method, params)
-            .map(|f| -> Box<Future<Item = Value, Error = RpcError>> {
-                let future = f.into_future().map(|result| {
-                    to_value(result)
-                        .expect("Your result type is not convertible to JSON, which is a bug")
-                });
-                Box::new(future)
-            })
+        self.0.rpc(ctl, method, params).map(box_rpc::<S>)
     }
     fn notification(
         &self, ctl: &ServerCtl, method: &str, params: &Option<Value>
     ) -> Option<Self::NotificationResult> {
-        // It seems the type signature is computed from inside the closure and it doesn't fit on
-        // the outside, so we need to declare it manually :-(
         self.0
             .notification(ctl, method, params)
-            .map(|f| -> Box<Future<Item = (), Error = ()>> { Box::new(f.into_future()) })
+            .map(box_notification::<S>)
+    }
+    fn rpc_raw(
+        &self, ctl: &ServerCtl, method: &str, params: &Option<RawParams>
+    ) -> Option<Self::RpcCallResult> {
+        self.0.rpc_raw(ctl, method, params).map(box_rpc::<S>)
+    }
+    fn notification_raw(
+        &self, ctl: &ServerCtl, method: &str, params: &Option<RawParams>
+    ) -> Option<Self::NotificationResult> {
+        self.0
+            .notification_raw(ctl, method, params)
+            .map(box_notification::<S>)
     }
     fn initialized(&self, ctl: &ServerCtl) {
         self.0.initialized(ctl)
     }
+    fn methods(&self) -> Vec<MethodInfo> {
+        self.0.methods()
+    }
 }
 
 /// A type to store servers as trait objects.
@@ -155,12 +316,23 @@ impl<S: Server> Server for AbstractServer<S> {
 /// [`ServerChain`](struct.ServerChain.html).
 pub type BoxServer = Box<
     Server<
-        Success = Value,
-        RpcCallResult = Box<Future<Item = Value, Error = RpcError>>,
+        Success = RawJson,
+        RpcCallResult = Box<Future<Item = RawJson, Error = RpcError>>,
         NotificationResult = Box<Future<Item = (), Error = ()>>,
     >,
 >;
 
+impl Debug
+    for Server<
+        Success = RawJson,
+        RpcCallResult = BoxRpcCallResult,
+        NotificationResult = BoxNotificationResult,
+    > {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "Server")
+    }
+}
+
 /// A server that chains several other servers.
 ///
 /// This composes multiple servers into one. When a notification or an rpc comes, it tries one by
@@ -196,7 +368,7 @@ impl ServerChain {
 }
 
 impl Server for ServerChain {
-    type Success = Value;
+    type Success = RawJson;
     type RpcCallResult = BoxRpcCallResult;
     type NotificationResult = BoxNotificationResult;
     fn rpc(
@@ -209,11 +381,160 @@ impl Server for ServerChain {
     ) -> Option<Self::NotificationResult> {
         self.iter_chain(|sub| sub.notification(ctl, method, params))
     }
+    fn rpc_raw(
+        &self, ctl: &ServerCtl, method: &str, params: &Option<RawParams>
+    ) -> Option<Self::RpcCallResult> {
+        self.iter_chain(|sub| sub.rpc_raw(ctl, method, params))
+    }
+    fn notification_raw(
+        &self, ctl: &ServerCtl, method: &str, params: &Option<RawParams>
+    ) -> Option<Self::NotificationResult> {
+        self.iter_chain(|sub| sub.notification_raw(ctl, method, params))
+    }
     fn initialized(&self, ctl: &ServerCtl) {
         for sub in &self.0 {
             sub.initialized(ctl);
         }
     }
+    /// Lists the methods of all the subservers.
+    ///
+    /// If more subservers describe the same method, only the first one is listed, as the other
+    /// ones are unreachable.
+    fn methods(&self) -> Vec<MethodInfo> {
+        let mut seen = HashSet::new();
+        self.0
+            .iter()
+            .flat_map(|sub| sub.methods())
+            .filter(|method| seen.insert((method.name.clone(), method.notification)))
+            .collect()
+    }
+}
+
+/// A server that dispatches to other servers by a prefix of the method name.
+///
+/// Each subserver is mounted under a namespace. When a call comes, the method name is split at
+/// the first separator (`.` by default) and the part before it selects the subserver. The rest of
+/// the method name is passed to the subserver, so a server mounted as `fs` sees a call to
+/// `fs.read` as `read`. Calls without a separator or with an unknown namespace are refused.
+///
+/// Unlike [`ServerChain`](struct.ServerChain.html), the dispatch goes directly to the single
+/// subserver, so it doesn't matter if several subservers implement the same method name.
+///
+/// As the namespace ends at the first separator, the namespace names themselves can't contain it
+/// (mounting such a namespace panics). However, a `Namespaced` server can be mounted inside
+/// another one to create deeper hierarchies.
+///
+/// Initialization is called on all the servers.
+pub struct Namespaced {
+    servers: BTreeMap<String, BoxServer>,
+    separator: String,
+}
+
+impl Namespaced {
+    /// Creates a server without any namespaces, using `.` as the separator.
+    pub fn new() -> Self {
+        Self::with_separator(".".to_owned())
+    }
+    /// Creates a server without any namespaces, using a custom separator.
+    ///
+    /// # Panics
+    ///
+    /// If the separator is empty.
+    pub fn with_separator(separator: String) -> Self {
+        assert!(!separator.is_empty(), "The namespace separator must not be empty");
+        Namespaced {
+            servers: BTreeMap::new(),
+            separator,
+        }
+    }
+    /// Mounts a subserver under the given namespace.
+    ///
+    /// If there was a subserver mounted there already, it is replaced and returned.
+    ///
+    /// # Panics
+    ///
+    /// If the namespace contains the separator, as no method could reach it.
+    pub fn mount<N: Into<String>>(&mut self, namespace: N, server: BoxServer) -> Option<BoxServer> {
+        let namespace = namespace.into();
+        assert!(
+            !namespace.contains(&self.separator as &str),
+            "The namespace {} contains the separator {}",
+            namespace,
+            self.separator
+        );
+        self.servers.insert(namespace, server)
+    }
+    /// Removes the subserver mounted under the given namespace.
+    pub fn unmount(&mut self, namespace: &str) -> Option<BoxServer> {
+        self.servers.remove(namespace)
+    }
+    /// Lists the mounted namespaces, in alphabetical order.
+    pub fn namespaces(&self) -> Keys<'_, String, BoxServer> {
+        self.servers.keys()
+    }
+    /// Consume the server and return the subservers inside, together with their namespaces.
+    pub fn into_inner(self) -> BTreeMap<String, BoxServer> {
+        self.servers
+    }
+    /// Finds the subserver and the method name with the namespace stripped.
+    fn route<'m>(&self, method: &'m str) -> Option<(&BoxServer, &'m str)> {
+        let pos = method.find(&self.separator as &str)?;
+        let server = self.servers.get(&method[..pos])?;
+        Some((server, &method[pos + self.separator.len()..]))
+    }
+}
+
+impl Default for Namespaced {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Server for Namespaced {
+    type Success = RawJson;
+    type RpcCallResult = BoxRpcCallResult;
+    type NotificationResult = BoxNotificationResult;
+    fn rpc(
+        &self, ctl: &ServerCtl, method: &str, params: &Option<Value>
+    ) -> Option<Self::RpcCallResult> {
+        self.route(method)
+            .and_then(|(sub, method)| sub.rpc(ctl, method, params))
+    }
+    fn notification(
+        &self, ctl: &ServerCtl, method: &str, params: &Option<Value>
+    ) -> Option<Self::NotificationResult> {
+        self.route(method)
+            .and_then(|(sub, method)| sub.notification(ctl, method, params))
+    }
+    fn rpc_raw(
+        &self, ctl: &ServerCtl, method: &str, params: &Option<RawParams>
+    ) -> Option<Self::RpcCallResult> {
+        self.route(method)
+            .and_then(|(sub, method)| sub.rpc_raw(ctl, method, params))
+    }
+    fn notification_raw(
+        &self, ctl: &ServerCtl, method: &str, params: &Option<RawParams>
+    ) -> Option<Self::NotificationResult> {
+        self.route(method)
+            .and_then(|(sub, method)| sub.notification_raw(ctl, method, params))
+    }
+    fn initialized(&self, ctl: &ServerCtl) {
+        for sub in self.servers.values() {
+            sub.initialized(ctl);
+        }
+    }
+    /// Lists the methods of all the subservers, prefixed by their namespaces.
+    fn methods(&self) -> Vec<MethodInfo> {
+        self.servers
+            .iter()
+            .flat_map(|(namespace, sub)| {
+                let prefix = format!("{}{}", namespace, self.separator);
+                sub.methods()
+                    .into_iter()
+                    .map(move |method| method.prefixed(&prefix))
+            })
+            .collect()
+    }
 }
 
 /// Parses the parameters of an RPC or a notification.
@@ -364,6 +685,25 @@ impl Server for ServerChain {
 /// jsonrpc_params!(&json!([{"num": 42, "b": true}]), single Params).unwrap_err();
 /// # }
 /// ```
+///
+/// The [raw params](message/type.RawParams.html) (as passed to
+/// [`Server::rpc_raw`](server/trait.Server.html#method.rpc_raw)) are deserialized directly into
+/// the given type with the `raw` token. Positional parameters can be decoded into a tuple, named
+/// ones into a structure. Missing parameters are decoded as `null`.
+///
+/// ```rust
+/// # #[macro_use] extern crate tokio_jsonrpc;
+/// # use tokio_jsonrpc::message::{RawParams, RpcError};
+/// fn parse(params: &Option<RawParams>) -> Option<Result<(i32, bool), RpcError>> {
+///     Some(Ok(jsonrpc_params!(params, raw (i32, bool))))
+/// }
+///
+/// # fn main() {
+/// let params = RawParams::from_string("[42, true]".to_owned()).unwrap();
+/// assert_eq!((42, true), parse(&Some(params)).unwrap().unwrap());
+/// parse(&None).unwrap().unwrap_err();
+/// # }
+/// ```
 #[macro_export]
 macro_rules! jsonrpc_params {
     // When the user asks for no params to be present. In that case we allow no params or null or
@@ -394,6 +734,23 @@ macro_rules! jsonrpc_params {
             $crate::message::RpcError::invalid_params(Some(format!("Incompatible type: {}", e)))
         })
     }};
+    // Decode the raw params directly into the given type, with no params being null.
+    ( $value:expr, raw $vartype:ty ) => {{
+        // Fix the type
+        let val: &$crate::macro_exports::Option<$crate::message::RawParams> = $value;
+        let parsed = match *val {
+            Some(ref raw) => raw.parse::<$vartype>(),
+            None => $crate::macro_exports::from_value::<$vartype>(
+                $crate::macro_exports::Value::Null),
+        };
+        match parsed {
+            Ok(result) => result,
+            Err(e) => {
+                let err = format!("Incompatible type: {}", e);
+                return Some(Err($crate::message::RpcError::invalid_params(Some(err))).into());
+            },
+        }
+    }};
     // A helper to count number of arguments
     ( arity $head:ty ) => { 1 };
     ( arity $head:ty, $( $tail:ty ),* ) => { 1 + jsonrpc_params!(arity $( $tail ),*) };
@@ -705,7 +1062,7 @@ mod tests {
             .unwrap()
             .wait()
             .unwrap();
-        assert_eq!(Value::Bool(true), rpc_result);
+        assert_eq!(Value::Bool(true), rpc_result.to_value());
         abstract_server
             .notification(&ctl, "notification", &None)
             .unwrap()
@@ -764,7 +1121,7 @@ mod tests {
         dropped.wait().unwrap();
         assert_eq!(
             Value::Bool(true),
-            chain.rpc(&ctl, "test", &None).unwrap().wait().unwrap()
+            chain.rpc(&ctl, "test", &None).unwrap().wait().unwrap().to_value()
         );
         assert_eq!(
             json!(42),
@@ -773,6 +1130,7 @@ mod tests {
                 .unwrap()
                 .wait()
                 .unwrap()
+                .to_value()
         );
         assert!(chain.rpc(&ctl, "wrong", &Some(Value::Null)).is_none());
         chain
@@ -785,6 +1143,118 @@ mod tests {
         // object seems to be a big pain and probably isn't worth it here.
     }
 
+    /// Test the namespaced server.
+    ///
+    /// Both servers would answer the `another` method if they were chained, but here only the one
+    /// in the namespace is asked.
+    #[test]
+    fn namespaced() {
+        let (ctl, dropped, _killed) = ServerCtl::new_test();
+        let mut server = Namespaced::new();
+        assert!(
+            server
+                .mount("empty", Box::new(AbstractServer::new(Empty)))
+                .is_none()
+        );
+        server.mount("log", Box::new(AbstractServer::new(LogServer::default())));
+        server.mount("another", Box::new(AbstractServer::new(AnotherServer)));
+        assert_eq!(
+            vec!["another", "empty", "log"],
+            server.namespaces().collect::<Vec<_>>()
+        );
+        server.initialized(&ctl);
+        dropped.wait().unwrap();
+        assert_eq!(
+            Value::Bool(true),
+            server.rpc(&ctl, "log.test", &None).unwrap().wait().unwrap().to_value()
+        );
+        assert_eq!(
+            json!(42),
+            server
+                .rpc(&ctl, "another.another", &Some(Value::Null))
+                .unwrap()
+                .wait()
+                .unwrap()
+                .to_value()
+        );
+        assert!(server.rpc(&ctl, "test", &None).is_none());
+        assert!(server.rpc(&ctl, "log.another", &None).is_none());
+        assert!(server.rpc(&ctl, "nothing.test", &None).is_none());
+        server
+            .notification(&ctl, "log.notification", &None)
+            .unwrap()
+            .wait()
+            .unwrap();
+        assert!(server.notification(&ctl, "empty.notification", &None).is_none());
+        assert!(server.unmount("log").is_some());
+        assert!(server.rpc(&ctl, "log.test", &None).is_none());
+    }
+
+    /// A custom separator and nesting of the namespaced servers.
+    #[test]
+    fn namespaced_nested() {
+        let (ctl, _dropped, _killed) = ServerCtl::new_test();
+        let mut inner = Namespaced::with_separator("::".to_owned());
+        inner.mount("another", Box::new(AbstractServer::new(AnotherServer)));
+        let mut outer = Namespaced::with_separator("::".to_owned());
+        outer.mount("inner", Box::new(inner));
+        assert_eq!(
+            json!(42),
+            outer
+                .rpc(&ctl, "inner::another::another", &Some(Value::Null))
+                .unwrap()
+                .wait()
+                .unwrap()
+                .to_value()
+        );
+        assert!(
+            outer
+                .rpc(&ctl, "inner.another.another", &Some(Value::Null))
+                .is_none()
+        );
+    }
+
+    /// A namespace containing the separator is refused, as it is unreachable.
+    #[test]
+    #[should_panic]
+    fn namespaced_separator() {
+        Namespaced::new().mount("a.b", Box::new(AbstractServer::new(Empty)));
+    }
+
+    /// The forwarder passes the accepted notifications into the stream.
+    #[test]
+    fn forwarder() {
+        let (ctl, _dropped, _killed) = ServerCtl::new_test();
+        let (forwarder, notifications) = NotificationForwarder::methods(vec!["time", "date"]);
+        forwarder
+            .notification(&ctl, "time", &Some(json!([1])))
+            .unwrap()
+            .unwrap();
+        forwarder
+            .notification(&ctl, "date", &None)
+            .unwrap()
+            .unwrap();
+        assert!(forwarder.notification(&ctl, "other", &None).is_none());
+        assert!(forwarder.rpc(&ctl, "time", &None).is_none());
+        drop(forwarder);
+        let received = notifications.collect().wait().unwrap();
+        assert_eq!(
+            vec![
+                Notification::new("time".to_owned(), Some(json!([1]))),
+                Notification::new("date".to_owned(), None),
+            ],
+            received
+        );
+        // Without filter, everything is accepted. But once the stream is gone, it is refused.
+        let (forwarder, notifications) = NotificationForwarder::new();
+        forwarder
+            .notification(&ctl, "other", &None)
+            .unwrap()
+            .unwrap();
+        drop(notifications);
+        assert!(forwarder.notification(&ctl, "other", &None).is_none());
+    }
+
     /// A guard object that panics when dropped unless it has been disarmed first.
     ///
     /// We use it to check the macro we test didn't short-circuit the test by returning early. Note
@@ -0,0 +1,285 @@
+// Copyright 2017 tokio-jsonrpc Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Adapters between servers and [tower](https://docs.rs/tower) services.
+//!
+//! This is available with the `tower` feature. It allows reusing the tower middleware (timeouts,
+//! rate limiting, load shedding, …) for the JSON RPC dispatch.
+//!
+//! * [`ServiceServer`](struct.ServiceServer.html) turns a service into a
+//!   [`Server`](../server/trait.Server.html), so it can be passed to an endpoint.
+//! * [`ServerService`](struct.ServerService.html) goes the other way, exposing a server as a
+//!   service, so the tower middleware can be put around it.
+//!
+//! Both directions use the [`Request`](struct.Request.html) type as the service request.
+//!
+//! Note that tower is built on top of the standard futures, while this library uses the futures
+//! 0.1. The adapters convert between them, which incurs some runtime costs.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::task::{Context, Poll as Poll03};
+
+use futures::{Future, IntoFuture};
+use futures_util::compat::{Compat, Compat01As03};
+use futures_util::future::{poll_fn, TryFutureExt};
+use serde_json::Value;
+use tower_service::Service;
+
+use endpoint::ServerCtl;
+use message::{RawJson, RpcError};
+use server::{AbstractServer, BoxNotificationResult, BoxRpcCallResult, Server};
+
+/// A call from the other side, as passed to a service.
+#[derive(Clone)]
+pub struct Request {
+    /// The control of the endpoint the call came through.
+    pub ctl: ServerCtl,
+    /// The called method.
+    pub method: String,
+    /// The parameters of the call.
+    pub params: Option<Value>,
+    /// If this is a notification instead of RPC.
+    ///
+    /// The result of a notification is thrown away, only the fact if it succeeded or not is
+    /// taken into account.
+    pub notification: bool,
+}
+
+/// A server dispatching everything to a tower service.
+///
+/// The service is asked for every method. If it doesn't know the method, it should return the
+/// [`RpcError::method_not_found`](../message/struct.RpcError.html#method.method_not_found) error.
+///
+/// Each call waits for the service to become ready before it is passed to it.
+pub struct ServiceServer<S>(Rc<RefCell<S>>);
+
+impl<S> ServiceServer<S>
+where
+    S: Service<Request, Response = Value, Error = RpcError> + 'static,
+    S::Future: 'static,
+{
+    /// Wraps the service.
+    pub fn new(service: S) -> Self {
+        ServiceServer(Rc::new(RefCell::new(service)))
+    }
+    fn call(&self, ctl: &ServerCtl, method: &str, params: &Option<Value>, notification: bool)
+        -> Box<Future<Item = Value, Error = RpcError>>
+    {
+        let request = Request {
+            ctl: ctl.clone(),
+            method: method.to_owned(),
+            params: params.clone(),
+            notification,
+        };
+        let ready = self.0.clone();
+        let call = self.0.clone();
+        let future = poll_fn(move |cx| ready.borrow_mut().poll_ready(cx))
+            .and_then(move |()| call.borrow_mut().call(request));
+        Box::new(Compat::new(Box::pin(future)))
+    }
+}
+
+impl<S> Server for ServiceServer<S>
+where
+    S: Service<Request, Response = Value, Error = RpcError> + 'static,
+    S::Future: 'static,
+{
+    type Success = Value;
+    type RpcCallResult = Box<Future<Item = Value, Error = RpcError>>;
+    type NotificationResult = BoxNotificationResult;
+    fn rpc(
+        &self, ctl: &ServerCtl, method: &str, params: &Option<Value>
+    ) -> Option<Self::RpcCallResult> {
+        Some(self.call(ctl, method, params, false))
+    }
+    fn notification(
+        &self, ctl: &ServerCtl, method: &str, params: &Option<Value>
+    ) -> Option<Self::NotificationResult> {
+        Some(Box::new(self.call(ctl, method, params, true).then(|result| {
+            result.map(|_| ()).map_err(|_| ())
+        })))
+    }
+}
+
+/// A tower service dispatching everything to a server.
+///
+/// The service is always ready. A method unknown to the server results in the
+/// [`RpcError::method_not_found`](../message/struct.RpcError.html#method.method_not_found) error,
+/// both for RPCs and notifications. A successful notification results in `null` and a failed one
+/// in a [`RpcError::server_error`](../message/struct.RpcError.html#method.server_error).
+///
+/// The results come already serialized, the same way as from the
+/// [`AbstractServer`](../server/struct.AbstractServer.html).
+pub struct ServerService<S: Server>(AbstractServer<S>);
+
+impl<S: Server> ServerService<S> {
+    /// Wraps the server.
+    pub fn new(server: S) -> Self {
+        ServerService(AbstractServer::new(server))
+    }
+    /// Unwraps the server back.
+    pub fn into_inner(self) -> S {
+        self.0.into_inner()
+    }
+}
+
+impl<S: Server> Service<Request> for ServerService<S> {
+    type Response = RawJson;
+    type Error = RpcError;
+    type Future = Compat01As03<BoxRpcCallResult>;
+    fn poll_ready(&mut self, _cx: &mut Context) -> Poll03<Result<(), RpcError>> {
+        Poll03::Ready(Ok(()))
+    }
+    fn call(&mut self, request: Request) -> Self::Future {
+        let Request {
+            ctl,
+            method,
+            params,
+            notification,
+        } = request;
+        let result = if notification {
+            self.0
+                .notification(&ctl, &method, &params)
+                .map(|future| -> BoxRpcCallResult {
+                    Box::new(future.then(|result| {
+                        result
+                            .map(|()| RawJson::serialize(&()).expect("Null is always JSON"))
+                            .map_err(|()| RpcError::server_error::<()>(None))
+                    }))
+                })
+        } else {
+            self.0.rpc(&ctl, &method, &params)
+        };
+        let result = result.unwrap_or_else(|| {
+            Box::new(Err(RpcError::method_not_found(method)).into_future())
+        });
+        Compat01As03::new(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    /// A service that is ready only every other time it is asked.
+    struct Blinking {
+        ready: bool,
+        calls: Rc<Cell<usize>>,
+    }
+
+    impl Service<Request> for Blinking {
+        type Response = Value;
+        type Error = RpcError;
+        type Future = ::std::future::Ready<Result<Value, RpcError>>;
+        fn poll_ready(&mut self, cx: &mut Context) -> Poll03<Result<(), RpcError>> {
+            self.ready = !self.ready;
+            if self.ready {
+                Poll03::Ready(Ok(()))
+            } else {
+                cx.waker().wake_by_ref();
+                Poll03::Pending
+            }
+        }
+        fn call(&mut self, request: Request) -> Self::Future {
+            assert!(self.ready);
+            self.calls.set(self.calls.get() + 1);
+            let result = match request.method.as_ref() {
+                "hello" => Ok(json!([request.params, request.notification])),
+                _ => Err(RpcError::method_not_found(request.method)),
+            };
+            ::std::future::ready(result)
+        }
+    }
+
+    /// A plain server to wrap into a service.
+    struct Hello;
+
+    impl Server for Hello {
+        type Success = String;
+        type RpcCallResult = Result<String, RpcError>;
+        type NotificationResult = Result<(), ()>;
+        fn rpc(
+            &self, _ctl: &ServerCtl, method: &str, _params: &Option<Value>
+        ) -> Option<Self::RpcCallResult> {
+            match method {
+                "hello" => Some(Ok("world".to_owned())),
+                _ => None,
+            }
+        }
+        fn notification(
+            &self, _ctl: &ServerCtl, method: &str, _params: &Option<Value>
+        ) -> Option<Self::NotificationResult> {
+            match method {
+                "hello" => Some(Ok(())),
+                "fail" => Some(Err(())),
+                _ => None,
+            }
+        }
+    }
+
+    /// A service is used as a server, waiting for it to get ready.
+    #[test]
+    fn service_server() {
+        let calls = Rc::new(Cell::new(0));
+        let server = ServiceServer::new(Blinking {
+            ready: false,
+            calls: calls.clone(),
+        });
+        let (ctl, _, _) = ServerCtl::new_test();
+        assert_eq!(
+            json!([[1], false]),
+            server
+                .rpc(&ctl, "hello", &Some(json!([1])))
+                .unwrap()
+                .wait()
+                .unwrap()
+        );
+        assert_eq!(
+            -32_601,
+            server
+                .rpc(&ctl, "other", &None)
+                .unwrap()
+                .wait()
+                .unwrap_err()
+                .code
+        );
+        server
+            .notification(&ctl, "hello", &None)
+            .unwrap()
+            .wait()
+            .unwrap();
+        server
+            .notification(&ctl, "other", &None)
+            .unwrap()
+            .wait()
+            .unwrap_err();
+        assert_eq!(4, calls.get());
+    }
+
+    /// A server is used as a service. We convert the future back to 0.1 to run it.
+    #[test]
+    fn server_service() {
+        let mut service = ServerService::new(Hello);
+        let (ctl, _, _) = ServerCtl::new_test();
+        let mut call = |method: &str, notification| {
+            Compat::new(service.call(Request {
+                ctl: ctl.clone(),
+                method: method.to_owned(),
+                params: None,
+                notification,
+            })).wait()
+        };
+        assert_eq!(json!("world"), call("hello", false).unwrap().to_value());
+        assert_eq!(-32_601, call("other", false).unwrap_err().code);
+        assert_eq!(Value::Null, call("hello", true).unwrap().to_value());
+        assert_eq!(-32_000, call("fail", true).unwrap_err().code);
+        assert_eq!(-32_601, call("other", true).unwrap_err().code);
+    }
+}
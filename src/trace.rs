@@ -0,0 +1,209 @@
+// Copyright 2017 tokio-jsonrpc Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Integration with the `tracing` crate.
+//!
+//! Everything in here turns into no-ops unless the `tracing` feature is enabled. That way the rest
+//! of the code can use it unconditionally, without sprinkling `cfg` attributes all around.
+
+#[cfg(feature = "tracing")]
+pub(crate) use self::enabled::*;
+#[cfg(not(feature = "tracing"))]
+pub(crate) use self::disabled::*;
+
+#[cfg(feature = "tracing")]
+mod enabled {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use futures::{Future, Poll};
+    use serde_json::Value;
+    use tracing::{info_span, Span};
+
+    /// A source of the connection numbers, so they can be told apart in the traces.
+    static CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+
+    /// Creates the spans of one connection.
+    #[derive(Clone, Debug)]
+    pub struct Connection {
+        parent: Span,
+        id: usize,
+    }
+
+    impl Connection {
+        /// Creates a new connection.
+        ///
+        /// The spans of the connection are children of whatever span is current right now.
+        pub fn new() -> Self {
+            Connection {
+                parent: Span::current(),
+                id: CONNECTIONS.fetch_add(1, Ordering::Relaxed),
+            }
+        }
+        /// A span of an incoming RPC request.
+        pub fn request(&self, method: &str, id: &Value) -> Span {
+            info_span!(parent: &self.parent, "jsonrpc_request", method = method, id = %id,
+                       connection = self.id)
+        }
+        /// A span of an incoming notification.
+        pub fn notification(&self, method: &str) -> Span {
+            info_span!(parent: &self.parent, "jsonrpc_notification", method = method,
+                       connection = self.id)
+        }
+        /// A span of an outgoing RPC call.
+        pub fn call(&self, method: &str, id: &Value) -> Span {
+            info_span!(parent: &self.parent, "jsonrpc_call", method = method, id = %id,
+                       connection = self.id)
+        }
+    }
+
+    /// Runs a closure with the span being the current one.
+    pub fn in_span<R, F: FnOnce() -> R>(span: &Span, f: F) -> R {
+        span.in_scope(f)
+    }
+
+    /// A future that makes its span current whenever it is polled.
+    pub struct Instrumented<F> {
+        inner: F,
+        span: Span,
+    }
+
+    impl<F: Future> Future for Instrumented<F> {
+        type Item = F::Item;
+        type Error = F::Error;
+        fn poll(&mut self) -> Poll<F::Item, F::Error> {
+            let _entered = self.span.enter();
+            self.inner.poll()
+        }
+    }
+
+    /// Wraps the future so it runs inside the span.
+    pub fn instrument<F: Future>(span: Span, future: F) -> Instrumented<F> {
+        Instrumented {
+            inner: future,
+            span,
+        }
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+mod disabled {
+    use serde_json::Value;
+
+    /// A placeholder for a span.
+    pub struct Span;
+
+    /// A placeholder for the spans of one connection.
+    #[derive(Clone, Debug)]
+    pub struct Connection;
+
+    impl Connection {
+        pub fn new() -> Self {
+            Connection
+        }
+        pub fn request(&self, _method: &str, _id: &Value) -> Span {
+            Span
+        }
+        pub fn notification(&self, _method: &str) -> Span {
+            Span
+        }
+        pub fn call(&self, _method: &str, _id: &Value) -> Span {
+            Span
+        }
+    }
+
+    pub fn in_span<R, F: FnOnce() -> R>(_span: &Span, f: F) -> R {
+        f()
+    }
+
+    pub fn instrument<F>(_span: Span, future: F) -> F {
+        future
+    }
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    use futures::future::{lazy, Future};
+    use tracing::{Event, Id, Metadata, Subscriber};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Record};
+    use tracing::subscriber::with_default;
+
+    use super::*;
+
+    /// Collects the fields into a string.
+    struct Fields(String);
+
+    impl Visit for Fields {
+        fn record_debug(&mut self, field: &Field, value: &::std::fmt::Debug) {
+            self.0 += &format!(" {}={:?}", field.name(), value);
+        }
+    }
+
+    /// A subscriber that remembers the spans and which span was current during events.
+    ///
+    /// It is good enough for a single-threaded test only.
+    #[derive(Default)]
+    struct LogSubscriber {
+        next: AtomicUsize,
+        current: Mutex<Vec<u64>>,
+        spans: Mutex<Vec<String>>,
+        events: Mutex<Vec<Option<u64>>>,
+    }
+
+    impl Subscriber for LogSubscriber {
+        fn enabled(&self, _metadata: &Metadata) -> bool {
+            true
+        }
+        fn new_span(&self, attrs: &Attributes) -> Id {
+            let mut fields = Fields(attrs.metadata().name().to_owned());
+            attrs.record(&mut fields);
+            self.spans.lock().unwrap().push(fields.0);
+            Id::from_u64(self.next.fetch_add(1, Ordering::Relaxed) as u64 + 1)
+        }
+        fn record(&self, _span: &Id, _values: &Record) {}
+        fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+        fn event(&self, _event: &Event) {
+            let current = self.current.lock().unwrap().last().cloned();
+            self.events.lock().unwrap().push(current);
+        }
+        fn enter(&self, span: &Id) {
+            self.current.lock().unwrap().push(span.into_u64());
+        }
+        fn exit(&self, _span: &Id) {
+            self.current.lock().unwrap().pop();
+        }
+    }
+
+    /// Check the spans get the right fields and are current while the future runs.
+    #[test]
+    fn spans() {
+        let subscriber = ::std::sync::Arc::new(LogSubscriber::default());
+        with_default(subscriber.clone(), || {
+            let connection = Connection::new();
+            let span = connection.request("hello", &json!(42));
+            in_span(&span, || ::tracing::info!("Called"));
+            instrument(span, lazy(|| -> Result<(), ()> {
+                ::tracing::info!("Polled");
+                Ok(())
+            })).wait()
+                .unwrap();
+            connection.notification("notif");
+            ::tracing::info!("Outside");
+        });
+        let spans = subscriber.spans.lock().unwrap();
+        assert_eq!(2, spans.len());
+        assert!(spans[0].starts_with("jsonrpc_request method=\"hello\" id=42 connection="));
+        assert!(spans[1].starts_with("jsonrpc_notification method=\"notif\" connection="));
+        assert_eq!(
+            vec![Some(1), Some(1), None],
+            *subscriber.events.lock().unwrap()
+        );
+    }
+}
@@ -0,0 +1,350 @@
+// Copyright 2017 tokio-jsonrpc Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Conformance to the JSON-RPC 2.0 specification.
+//!
+//! The examples from the specification are sent over a real connection to an endpoint in the
+//! strict mode and the answers are compared to the ones in the specification. The error messages
+//! and data are left to the implementation by the specification, so only the codes are compared.
+
+extern crate bytes;
+extern crate futures;
+#[macro_use]
+extern crate serde_json;
+extern crate tokio_core;
+extern crate tokio_io;
+extern crate tokio_jsonrpc;
+
+use std::io::Error as IoError;
+use std::time::Duration;
+
+use bytes::{BufMut, BytesMut};
+use futures::{Future, Sink, Stream};
+use futures::future::{ok, Either};
+use futures::stream::iter_ok;
+use serde_json::{from_str, Value};
+use tokio_core::net::{TcpListener, TcpStream};
+use tokio_core::reactor::{Core, Timeout};
+use tokio_io::AsyncRead;
+use tokio_io::codec::{Decoder, Encoder};
+
+use tokio_jsonrpc::{Endpoint, RpcError, Server, ServerCtl};
+use tokio_jsonrpc::codec::Line;
+
+/// The server with the methods used in the examples of the specification.
+struct SpecServer;
+
+impl Server for SpecServer {
+    type Success = Value;
+    type RpcCallResult = Result<Value, RpcError>;
+    type NotificationResult = Result<(), ()>;
+    fn rpc(
+        &self, _ctl: &ServerCtl, method: &str, params: &Option<Value>
+    ) -> Option<Self::RpcCallResult> {
+        let invalid = || RpcError::invalid_params(None);
+        let number = |value: &Value| value.as_i64().ok_or_else(invalid);
+        let result = match (method, params) {
+            ("subtract", &Some(Value::Array(ref params))) if params.len() == 2 => {
+                number(&params[0]).and_then(|minuend| Ok(minuend - number(&params[1])?))
+            },
+            ("subtract", &Some(Value::Object(ref params))) => {
+                let get = |name| params.get(name).map_or_else(|| Err(invalid()), &number);
+                get("minuend").and_then(|minuend| Ok(minuend - get("subtrahend")?))
+            },
+            ("subtract", _) => Err(invalid()),
+            ("sum", &Some(Value::Array(ref params))) => params.iter().map(&number).sum(),
+            ("get_data", &None) => return Some(Ok(json!(["hello", 5]))),
+            _ => return None,
+        };
+        Some(result.map(|result| json!(result)))
+    }
+    fn notification(
+        &self, _ctl: &ServerCtl, method: &str, _params: &Option<Value>
+    ) -> Option<Self::NotificationResult> {
+        match method {
+            "update" | "foobar" | "notify_hello" | "notify_sum" => Some(Ok(())),
+            _ => None,
+        }
+    }
+}
+
+/// A codec of plain lines, so the examples can be sent exactly as they are.
+struct Text;
+
+impl Decoder for Text {
+    type Item = String;
+    type Error = IoError;
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<String>, IoError> {
+        let end = match src.iter().position(|&b| b == b'\n') {
+            Some(end) => end,
+            None => return Ok(None),
+        };
+        let line = src.split_to(end + 1);
+        Ok(Some(String::from_utf8_lossy(&line[..end]).into_owned()))
+    }
+}
+
+impl Encoder for Text {
+    type Item = String;
+    type Error = IoError;
+    fn encode(&mut self, line: String, buf: &mut BytesMut) -> Result<(), IoError> {
+        buf.reserve(line.len() + 1);
+        buf.put_slice(line.as_bytes());
+        buf.put(b'\n');
+        Ok(())
+    }
+}
+
+/// Drops what the specification leaves to the implementation and sorts the batches.
+fn normalize(value: Value) -> Value {
+    match value {
+        Value::Array(items) => {
+            let mut items: Vec<_> = items.into_iter().map(normalize).collect();
+            items.sort_by_key(|item| item.to_string());
+            Value::Array(items)
+        },
+        Value::Object(mut object) => {
+            if let Some(&mut Value::Object(ref mut error)) = object.get_mut("error") {
+                error.remove("message");
+                error.remove("data");
+            }
+            Value::Object(object)
+        },
+        other => other,
+    }
+}
+
+/// Sends the messages to a strict endpoint one by one and checks the answers.
+///
+/// A message without an expected answer must not be answered at all. That is checked by the next
+/// one, so the last message must expect an answer.
+fn exchange(examples: Vec<(&'static str, Option<&'static str>)>) {
+    let mut reactor = Core::new().unwrap();
+    let handle = reactor.handle();
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &handle).unwrap();
+    let address = listener.local_addr().unwrap();
+    let accepted = listener
+        .incoming()
+        .into_future()
+        .map(|(connection, _incoming)| connection.unwrap().0)
+        .map_err(|(err, _incoming)| err);
+    let connected = TcpStream::connect(&address, &handle);
+    let (server_side, client_side) = reactor.run(accepted.join(connected)).unwrap();
+    let connection = server_side.framed(Line::new().strict(true));
+    let (_client, finished) = Endpoint::new(connection, SpecServer)
+        .strict(true)
+        .start(&handle);
+    handle.spawn(finished.map_err(|err| panic!("Error: {}", err)));
+    let checked = iter_ok::<_, IoError>(examples).fold(
+        client_side.framed(Text),
+        |connection, (input, expected)| {
+            let sent = connection.send(input.to_owned());
+            match expected {
+                None => Either::A(sent),
+                Some(expected) => Either::B(sent.and_then(move |connection| {
+                    connection
+                        .into_future()
+                        .map_err(|(err, _connection)| err)
+                        .and_then(move |(answer, connection)| {
+                            let answer = answer.expect("Connection closed");
+                            assert_eq!(
+                                normalize(from_str(expected).unwrap()),
+                                normalize(from_str(&answer).unwrap()),
+                                "Wrong answer to {}",
+                                input
+                            );
+                            ok(connection)
+                        })
+                })),
+            }
+        },
+    );
+    let timeout = Timeout::new(Duration::new(15, 0), &handle)
+        .unwrap()
+        .then(|_| -> Result<(), IoError> { panic!("Timeout happened") });
+    let checked = checked.map(drop).select(timeout).map(drop).map_err(|(err, _)| err);
+    reactor.run(checked).unwrap();
+}
+
+/// The answer to the final message, making sure nothing else was sent before.
+const FINAL: (&str, Option<&str>) = (
+    r#"{"jsonrpc": "2.0", "method": "get_data", "id": "end"}"#,
+    Some(r#"{"jsonrpc": "2.0", "result": ["hello", 5], "id": "end"}"#),
+);
+
+#[test]
+fn positional_parameters() {
+    exchange(vec![
+        (
+            r#"{"jsonrpc": "2.0", "method": "subtract", "params": [42, 23], "id": 1}"#,
+            Some(r#"{"jsonrpc": "2.0", "result": 19, "id": 1}"#),
+        ),
+        (
+            r#"{"jsonrpc": "2.0", "method": "subtract", "params": [23, 42], "id": 2}"#,
+            Some(r#"{"jsonrpc": "2.0", "result": -19, "id": 2}"#),
+        ),
+    ]);
+}
+
+#[test]
+fn named_parameters() {
+    exchange(vec![
+        (
+            r#"{"jsonrpc": "2.0", "method": "subtract", "params": {"subtrahend": 23, "minuend": 42}, "id": 3}"#,
+            Some(r#"{"jsonrpc": "2.0", "result": 19, "id": 3}"#),
+        ),
+        (
+            r#"{"jsonrpc": "2.0", "method": "subtract", "params": {"minuend": 42, "subtrahend": 23}, "id": 4}"#,
+            Some(r#"{"jsonrpc": "2.0", "result": 19, "id": 4}"#),
+        ),
+    ]);
+}
+
+#[test]
+fn notifications() {
+    exchange(vec![
+        (r#"{"jsonrpc": "2.0", "method": "update", "params": [1,2,3,4,5]}"#, None),
+        (r#"{"jsonrpc": "2.0", "method": "foobar"}"#, None),
+        FINAL,
+    ]);
+}
+
+#[test]
+fn method_not_found() {
+    exchange(vec![
+        (
+            r#"{"jsonrpc": "2.0", "method": "foobar", "id": "1"}"#,
+            Some(r#"{"jsonrpc": "2.0", "error": {"code": -32601, "message": "Method not found"}, "id": "1"}"#),
+        ),
+    ]);
+}
+
+#[test]
+fn invalid_json() {
+    exchange(vec![
+        (
+            r#"{"jsonrpc": "2.0", "method": "foobar, "params": "bar", "baz]"#,
+            Some(r#"{"jsonrpc": "2.0", "error": {"code": -32700, "message": "Parse error"}, "id": null}"#),
+        ),
+        (
+            r#"[{"jsonrpc": "2.0", "method": "sum", "params": [1,2,4], "id": "1"}, {"jsonrpc": "2.0", "method"]"#,
+            Some(r#"{"jsonrpc": "2.0", "error": {"code": -32700, "message": "Parse error"}, "id": null}"#),
+        ),
+    ]);
+}
+
+#[test]
+fn invalid_request() {
+    exchange(vec![
+        (
+            r#"{"jsonrpc": "2.0", "method": 1, "params": "bar"}"#,
+            Some(r#"{"jsonrpc": "2.0", "error": {"code": -32600, "message": "Invalid Request"}, "id": null}"#),
+        ),
+    ]);
+}
+
+#[test]
+fn invalid_batches() {
+    exchange(vec![
+        (
+            "[]",
+            Some(r#"{"jsonrpc": "2.0", "error": {"code": -32600, "message": "Invalid Request"}, "id": null}"#),
+        ),
+        (
+            "[1]",
+            Some(r#"[{"jsonrpc": "2.0", "error": {"code": -32600, "message": "Invalid Request"}, "id": null}]"#),
+        ),
+        (
+            "[1,2,3]",
+            Some(concat!(
+                r#"[{"jsonrpc": "2.0", "error": {"code": -32600, "message": "Invalid Request"}, "id": null},"#,
+                r#" {"jsonrpc": "2.0", "error": {"code": -32600, "message": "Invalid Request"}, "id": null},"#,
+                r#" {"jsonrpc": "2.0", "error": {"code": -32600, "message": "Invalid Request"}, "id": null}]"#
+            )),
+        ),
+    ]);
+}
+
+#[test]
+fn batch() {
+    exchange(vec![
+        (
+            concat!(
+                r#"[{"jsonrpc": "2.0", "method": "sum", "params": [1,2,4], "id": "1"},"#,
+                r#" {"jsonrpc": "2.0", "method": "notify_hello", "params": [7]},"#,
+                r#" {"jsonrpc": "2.0", "method": "subtract", "params": [42,23], "id": "2"},"#,
+                r#" {"foo": "boo"},"#,
+                r#" {"jsonrpc": "2.0", "method": "foo.get", "params": {"name": "myself"}, "id": "5"},"#,
+                r#" {"jsonrpc": "2.0", "method": "get_data", "id": "9"}]"#
+            ),
+            Some(concat!(
+                r#"[{"jsonrpc": "2.0", "result": 7, "id": "1"},"#,
+                r#" {"jsonrpc": "2.0", "result": 19, "id": "2"},"#,
+                r#" {"jsonrpc": "2.0", "error": {"code": -32600, "message": "Invalid Request"}, "id": null},"#,
+                r#" {"jsonrpc": "2.0", "error": {"code": -32601, "message": "Method not found"}, "id": "5"},"#,
+                r#" {"jsonrpc": "2.0", "result": ["hello", 5], "id": "9"}]"#
+            )),
+        ),
+        (
+            concat!(
+                r#"[{"jsonrpc": "2.0", "method": "notify_sum", "params": [1,2,4]},"#,
+                r#" {"jsonrpc": "2.0", "method": "notify_hello", "params": [7]}]"#
+            ),
+            None,
+        ),
+        FINAL,
+    ]);
+}
+
+/// Things the specification forbids, but the lenient mode accepts.
+#[test]
+fn strict_violations() {
+    exchange(vec![
+        (
+            r#"{"jsonrpc": "2.0", "method": "subtract", "params": 42, "id": 1}"#,
+            Some(r#"{"jsonrpc": "2.0", "error": {"code": -32600}, "id": 1}"#),
+        ),
+        (
+            r#"{"jsonrpc": "2.0", "method": "subtract", "params": null, "id": 2}"#,
+            Some(r#"{"jsonrpc": "2.0", "error": {"code": -32600}, "id": 2}"#),
+        ),
+        (
+            r#"{"jsonrpc": "2.0", "method": "get_data", "id": 1.5}"#,
+            Some(r#"{"jsonrpc": "2.0", "error": {"code": -32600}, "id": null}"#),
+        ),
+        (
+            r#"{"jsonrpc": "2.0", "method": "get_data", "id": {"x": 1}}"#,
+            Some(r#"{"jsonrpc": "2.0", "error": {"code": -32600}, "id": null}"#),
+        ),
+        (
+            r#"[[], {"jsonrpc": "2.0", "method": "get_data", "id": 3}]"#,
+            Some(concat!(
+                r#"[{"jsonrpc": "2.0", "error": {"code": -32600}, "id": null},"#,
+                r#" {"jsonrpc": "2.0", "result": ["hello", 5], "id": 3}]"#
+            )),
+        ),
+        // A nested batch with invalid entries inside
+        (
+            r#"[[1], [{"foo": 1}], {"jsonrpc": "2.0", "method": "get_data", "id": 4}]"#,
+            Some(concat!(
+                r#"[{"jsonrpc": "2.0", "error": {"code": -32600}, "id": null},"#,
+                r#" {"jsonrpc": "2.0", "error": {"code": -32600}, "id": null},"#,
+                r#" {"jsonrpc": "2.0", "result": ["hello", 5], "id": 4}]"#
+            )),
+        ),
+        (
+            "[[1]]",
+            Some(r#"[{"jsonrpc": "2.0", "error": {"code": -32600}, "id": null}]"#),
+        ),
+        // An invalid notification is not a notification, it is answered
+        (
+            r#"{"jsonrpc": "2.0", "method": "update", "params": 7}"#,
+            Some(r#"{"jsonrpc": "2.0", "error": {"code": -32600}, "id": null}"#),
+        ),
+        FINAL,
+    ]);
+}
@@ -10,21 +10,37 @@ extern crate futures;
 extern crate serde_json;
 extern crate tokio_core;
 extern crate tokio_io;
+#[macro_use]
 extern crate tokio_jsonrpc;
 
 use std::time::Duration;
-use std::io::Error as IoError;
-use std::cell::Cell;
+use std::io::{Error as IoError, ErrorKind};
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
 
-use futures::{Future, IntoFuture, Stream};
-use tokio_core::reactor::{Core, Handle, Timeout};
+use futures::{Future, IntoFuture, Sink, Stream};
+use futures::stream::iter_ok;
+use tokio_core::reactor::{Core, Handle, Interval, Timeout};
 use tokio_core::net::{TcpListener, TcpStream};
 use tokio_io::codec::Framed;
 use tokio_io::AsyncRead;
+use tokio_io::io::write_all;
 use serde_json::{from_value, Value};
 
-use tokio_jsonrpc::{Client, Endpoint, LineCodec, RpcError, Server, ServerCtl};
+use tokio_jsonrpc::{Client, Endpoint, LineCodec, Message, RpcError, Server, ServerCtl};
+use tokio_jsonrpc::auth::{AuthGate, UNAUTHORIZED};
+use tokio_jsonrpc::batch::BatchPolicy;
+use tokio_jsonrpc::codec::RawLine;
+use tokio_jsonrpc::message::{from_str, RawParams, Version};
+use tokio_jsonrpc::pubsub::{Publisher, Subscriber};
+use tokio_jsonrpc::reconnect::{Backoff, PendingPolicy, Reconnect, State};
+use tokio_jsonrpc::server::{AbstractServer, ServerChain};
+use tokio_jsonrpc::introspection::{Introspection, MethodInfo};
+use tokio_jsonrpc::keepalive::{Keepalive, PingTimeout};
+use tokio_jsonrpc::limit::Limits;
+use tokio_jsonrpc::rate::{Rate, RateLimits, RATE_LIMITED};
+use tokio_jsonrpc::memory::{self, Memory, Pair};
+use tokio_jsonrpc::metrics::Metrics;
 
 /// A test server
 ///
@@ -57,21 +73,25 @@ impl Server for AnswerServer {
 /// Set up a client and a server
 ///
 /// Create a reactor, set a safety timeout (if the test doesn't finish in 15 seconds, panic) and
-/// provide two connected TCP streams. We could use unix socket pair, but that wouldn't work on
-/// windows, so we just connect on 127.0.0.1.
-fn prepare() -> (
-    Core,
-    Framed<TcpStream, LineCodec>,
-    Framed<TcpStream, LineCodec>,
-) {
-    let mut reactor = Core::new().unwrap();
-    let handle = reactor.handle();
+/// provide two connected in-memory halves.
+fn prepare() -> (Core, Memory, Memory) {
+    let reactor = Core::new().unwrap();
     // Kill the test if it gets stuck
-    let timeout = Timeout::new(Duration::new(15, 0), &handle)
+    let timeout = Timeout::new(Duration::new(15, 0), &reactor.handle())
         .unwrap()
         .then(|_| -> Result<(), ()> { panic!("Timeout happened") });
-    handle.spawn(timeout);
-    // Build two connected TCP streams
+    reactor.handle().spawn(timeout);
+    let (s1, s2) = memory::pair();
+    (reactor, s1, s2)
+}
+
+/// Two connected TCP streams
+///
+/// For the tests that need to put raw bytes on the wire, which the in-memory connection doesn't
+/// allow. We could use unix socket pair, but that wouldn't work on windows, so we just connect on
+/// 127.0.0.1.
+fn tcp_pair(reactor: &mut Core) -> (TcpStream, TcpStream) {
+    let handle = reactor.handle();
     let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &handle).unwrap();
     let address = listener.local_addr().unwrap();
     let server_finished = listener
@@ -83,13 +103,7 @@ fn prepare() -> (
         });
     let client_finished = TcpStream::connect(&address, &handle);
     // Wait for both of them to be connected
-    let (s1, s2) = reactor.run(server_finished.join(client_finished)).unwrap();
-    // And return everything
-    (
-        reactor,
-        s1.framed(LineCodec::new()),
-        s2.framed(LineCodec::new()),
-    )
+    reactor.run(server_finished.join(client_finished)).unwrap()
 }
 
 /// Preprocess the tripple returned by .start
@@ -222,9 +236,10 @@ fn timeout() {
     let all = {
         // Run in a sub-block, so we drop all the clients, etc.
         let handle = reactor.handle();
-        let (_client, server_finished) = process_start(
-            Endpoint::new(s1, AnotherServer(handle.clone(), Cell::new(1))).start(&handle),
-        );
+        let (_client, server_finished) =
+            Endpoint::new(s1, AnotherServer(handle.clone(), Cell::new(1))).start(&handle);
+        // The client is gone by the time the late answer is sent, so the server may fail to send it
+        let server_finished = server_finished.then(|_| Ok(()));
         let (client, client_endpoint_finished) =
             process_start(Endpoint::client_only(s2).start(&handle));
         client
@@ -420,11 +435,12 @@ fn kill_client() {
     let all = {
         // Run in a sub-block, so we drop all the clients, etc.
         let handle = reactor.handle();
-        let (_client, server_finished) = process_start(
+        let (_client, server_finished) =
             Endpoint::new(s1, AnotherServer(handle.clone(), Cell::new(2)))
                 .parallel(2)
-                .start(&handle),
-        );
+                .start(&handle);
+        // The killed client doesn't wait for the late answer, so the server may fail to send it
+        let server_finished = server_finished.then(|_| Ok(()));
         let (client, client_endpoint_finished) =
             process_start(Endpoint::client_only(s2).start(&handle));
         let ctl = client.server_ctl().clone();
@@ -523,4 +539,1098 @@ fn conn_terminate() {
     reactor.run(all).unwrap();
 }
 
+/// Metrics that remember what happened, in a textual form.
+#[derive(Default)]
+struct LogMetrics(RefCell<Vec<String>>);
+
+impl LogMetrics {
+    fn log(&self, what: String) {
+        self.0.borrow_mut().push(what);
+    }
+}
+
+impl Metrics for LogMetrics {
+    fn request_received(&self, method: &str) {
+        self.log(format!("request {}", method));
+    }
+    fn request_answered(&self, method: &str, result: Result<(), &RpcError>, _: Duration) {
+        self.log(format!("answered {} {:?}", method, result.map_err(|e| e.code)));
+    }
+    fn response_received(&self, expected: bool) {
+        self.log(format!("response {}", expected));
+    }
+    fn call_sent(&self, method: &str) {
+        self.log(format!("call {}", method));
+    }
+    fn call_answered(&self, method: &str, _: Duration) {
+        self.log(format!("call answered {}", method));
+    }
+    fn outstanding_calls(&self, count: usize) {
+        self.log(format!("outstanding {}", count));
+    }
+}
+
+/// Check the metrics hooks are called on both sides of the connection.
+#[test]
+fn metrics() {
+    let (mut reactor, s1, s2) = prepare();
+    let server_metrics = Rc::new(LogMetrics::default());
+    let client_metrics = Rc::new(LogMetrics::default());
+    let all = {
+        // Run in a sub-block, so we drop all the clients, etc.
+        let handle = reactor.handle();
+        let (_client, server_finished) = process_start(
+            Endpoint::new(s1, AnotherServer(handle.clone(), Cell::new(2)))
+                .metrics(server_metrics.clone())
+                .start(&handle),
+        );
+        let (client, client_endpoint_finished) = process_start(
+            Endpoint::client_only(s2)
+                .metrics(client_metrics.clone())
+                .start(&handle),
+        );
+        client
+            .call("wrong".to_owned(), None, None)
+            .and_then(|(client, answered)| answered.map(|_| client))
+            .and_then(|client| client.call("timeout".to_owned(), Some(json!([0, 0])), None))
+            .and_then(|(_client, answered)| answered)
+            .join3(server_finished, client_endpoint_finished)
+    };
+    reactor.run(all).unwrap();
+    assert_eq!(
+        vec![
+            "request wrong",
+            "answered wrong Err(-32601)",
+            "request timeout",
+            "answered timeout Ok(())",
+        ],
+        *server_metrics.0.borrow()
+    );
+    assert_eq!(
+        vec![
+            "call wrong",
+            "outstanding 1",
+            "response true",
+            "outstanding 0",
+            "call answered wrong",
+            "call timeout",
+            "outstanding 1",
+            "response true",
+            "outstanding 0",
+            "call answered timeout",
+        ],
+        *client_metrics.0.borrow()
+    );
+}
+
+/// A server describing its only method.
+///
+/// It answers `"test"` by 42 and terminates.
+struct DescribedServer;
+
+impl Server for DescribedServer {
+    type Success = u32;
+    type RpcCallResult = Result<u32, RpcError>;
+    type NotificationResult = Result<(), ()>;
+    fn rpc(
+        &self, ctl: &ServerCtl, method: &str, _params: &Option<Value>
+    ) -> Option<Self::RpcCallResult> {
+        match method {
+            "test" => {
+                ctl.terminate();
+                Some(Ok(42))
+            },
+            _ => None,
+        }
+    }
+    fn methods(&self) -> Vec<MethodInfo> {
+        vec![jsonrpc_method_info!(rpc "test", "The answer")]
+    }
+}
+
+/// The endpoint answers `rpc.discover` on behalf of the server.
+#[test]
+fn introspection() {
+    let (mut reactor, s1, s2) = prepare();
+    let all = {
+        // Run in a sub-block, so we drop all the clients, etc.
+        let handle = reactor.handle();
+        let introspection = Introspection::new("Test".to_owned(), "0.1".to_owned());
+        let (_client, server_finished) = process_start(
+            Endpoint::new(s1, DescribedServer)
+                .introspection(introspection)
+                .start(&handle),
+        );
+        let (client, client_endpoint_finished) =
+            process_start(Endpoint::client_only(s2).start(&handle));
+        client
+            .allow_reserved(true)
+            .call("rpc.discover".to_owned(), None, None)
+            .and_then(|(client, answered)| answered.map(|response| (client, response)))
+            .and_then(|(client, response)| {
+                let document = response.unwrap().result.unwrap();
+                assert_eq!(json!("Test"), document["info"]["title"]);
+                assert_eq!(json!("test"), document["methods"][0]["name"]);
+                assert_eq!(json!("The answer"), document["methods"][0]["description"]);
+                assert_eq!(1, document["methods"].as_array().unwrap().len());
+                client.call("test".to_owned(), None, None)
+            })
+            .and_then(|(_client, answered)| answered)
+            .join3(server_finished, client_endpoint_finished)
+    };
+    reactor.run(all).unwrap();
+}
+
+/// A server answering each method by its name.
+///
+/// It terminates after the `"bye"` method.
+struct NameServer;
+
+impl Server for NameServer {
+    type Success = String;
+    type RpcCallResult = Result<String, RpcError>;
+    type NotificationResult = Result<(), ()>;
+    fn rpc(
+        &self, ctl: &ServerCtl, method: &str, _params: &Option<Value>
+    ) -> Option<Self::RpcCallResult> {
+        if method == "bye" {
+            ctl.terminate();
+        }
+        Some(Ok(method.to_owned()))
+    }
+}
+
+/// An extension answering `rpc.ping`.
+struct PingExtension;
+
+impl Server for PingExtension {
+    type Success = String;
+    type RpcCallResult = Result<String, RpcError>;
+    type NotificationResult = Result<(), ()>;
+    fn rpc(
+        &self, _ctl: &ServerCtl, method: &str, _params: &Option<Value>
+    ) -> Option<Self::RpcCallResult> {
+        match method {
+            "rpc.ping" => Some(Ok("pong".to_owned())),
+            _ => None,
+        }
+    }
+}
+
+/// Call the given methods in sequence and return the results.
+fn call_all(
+    client: Client, methods: Vec<&'static str>
+) -> Box<Future<Item = Vec<Result<Value, RpcError>>, Error = IoError>> {
+    let calls = futures::stream::iter_ok(methods).fold(
+        (client, Vec::new()),
+        |(client, mut results), method| {
+            client
+                .call(method.to_owned(), None, None)
+                .and_then(|(client, answered)| answered.map(|response| (client, response)))
+                .map(move |(client, response)| {
+                    results.push(response.unwrap().result);
+                    (client, results)
+                })
+        },
+    );
+    Box::new(calls.map(|(_client, results)| results))
+}
+
+/// The reserved methods go only to the extensions when enforced.
+#[test]
+fn reserved_names() {
+    let (mut reactor, s1, s2) = prepare();
+    let all = {
+        // Run in a sub-block, so we drop all the clients, etc.
+        let handle = reactor.handle();
+        let (_client, server_finished) = process_start(
+            Endpoint::new(s1, NameServer)
+                .extension(Box::new(AbstractServer::new(PingExtension)))
+                .reserved_names(true)
+                .start(&handle),
+        );
+        let (client, client_endpoint_finished) =
+            process_start(Endpoint::client_only(s2).start(&handle));
+        // Not allowed to call the reserved names by default
+        let refused = client
+            .clone()
+            .call("rpc.ping".to_owned(), None, None)
+            .then(|result| {
+                assert_eq!(ErrorKind::InvalidInput, result.err().unwrap().kind());
+                Ok(())
+            });
+        // But the notifications are sent
+        let notified = client
+            .clone()
+            .notify("rpc.ping".to_owned(), None)
+            .map(drop);
+        let calls = call_all(
+            client.allow_reserved(true),
+            vec!["rpc.ping", "rpc.other", "other", "bye"],
+        ).map(|results| {
+            assert_eq!(
+                vec![
+                    Ok(json!("pong")),
+                    Err(RpcError::method_not_found("rpc.other".to_owned())),
+                    Ok(json!("other")),
+                    Ok(json!("bye")),
+                ],
+                results
+            );
+        });
+        refused
+            .and_then(|()| notified)
+            .and_then(|()| calls)
+            .join3(server_finished, client_endpoint_finished)
+    };
+    reactor.run(all).unwrap();
+}
+
+/// Without the enforcement, unknown reserved methods still reach the server.
+#[test]
+fn reserved_names_compat() {
+    let (mut reactor, s1, s2) = prepare();
+    let all = {
+        // Run in a sub-block, so we drop all the clients, etc.
+        let handle = reactor.handle();
+        let (_client, server_finished) = process_start(
+            Endpoint::new(s1, NameServer)
+                .extension(Box::new(AbstractServer::new(PingExtension)))
+                .start(&handle),
+        );
+        let (client, client_endpoint_finished) =
+            process_start(Endpoint::client_only(s2).start(&handle));
+        call_all(
+            client.allow_reserved(true),
+            vec!["rpc.ping", "rpc.other", "bye"],
+        ).map(|results| {
+            assert_eq!(
+                vec![Ok(json!("pong")), Ok(json!("rpc.other")), Ok(json!("bye"))],
+                results
+            );
+        })
+            .join3(server_finished, client_endpoint_finished)
+    };
+    reactor.run(all).unwrap();
+}
+
+/// Subscribe to two topics and unsubscribe.
+#[test]
+fn pubsub() {
+    let (mut reactor, s1, s2) = prepare();
+    let all = {
+        // Run in a sub-block, so we drop all the clients, etc.
+        let handle = reactor.handle();
+        let publisher = Publisher::new(handle.clone())
+            .topic("count", |_ctl, params| -> Result<_, RpcError> {
+                let (limit,) = jsonrpc_params!(params, wrap "limit" => u32)?;
+                Ok(futures::stream::iter_ok::<_, ()>(0..limit))
+            })
+            .topic("forever", |_ctl, _params| -> Result<_, RpcError> {
+                Ok(futures::stream::repeat::<_, ()>("again"))
+            });
+        let server = ServerChain::new(vec![
+            Box::new(AbstractServer::new(publisher)),
+            Box::new(AbstractServer::new(NameServer)),
+        ]);
+        let (_client, server_finished) = process_start(Endpoint::new(s1, server).start(&handle));
+        let subscriber = Subscriber::new();
+        let (client, client_endpoint_finished) =
+            process_start(Endpoint::new(s2, subscriber.clone()).start(&handle));
+        let subscriber_cloned = subscriber.clone();
+        subscriber
+            .subscribe(client, "count".to_owned(), Some(json!([3])))
+            .and_then(|(client, subscription)| {
+                subscription
+                    .unwrap()
+                    .take(3)
+                    .collect()
+                    .map_err(|()| panic!("Subscription failed"))
+                    .map(|values| {
+                        assert_eq!(vec![json!(0), json!(1), json!(2)], values);
+                        client
+                    })
+            })
+            .and_then(move |client| {
+                subscriber_cloned.subscribe(client, "forever".to_owned(), None)
+            })
+            .and_then(|(client, subscription)| {
+                subscription
+                    .unwrap()
+                    .into_future()
+                    .map_err(|_| panic!("Subscription failed"))
+                    .and_then(|(value, subscription)| {
+                        assert_eq!(Some(json!("again")), value);
+                        subscription.unsubscribe(client)
+                    })
+            })
+            .and_then(|(client, answered)| answered.map(|response| (client, response)))
+            .and_then(|(client, response)| {
+                assert_eq!(json!(true), response.unwrap().result.unwrap());
+                client.call("bye".to_owned(), None, None)
+            })
+            .and_then(|(_client, answered)| answered)
+            .join3(server_finished, client_endpoint_finished)
+    };
+    reactor.run(all).unwrap();
+}
+
+/// Receive the notifications as a stream.
+#[test]
+fn notification_stream() {
+    let (mut reactor, s1, s2) = prepare();
+    let all = {
+        // Run in a sub-block, so we drop all the clients, etc.
+        let handle = reactor.handle();
+        let (client, sender_finished) = process_start(Endpoint::client_only(s1).start(&handle));
+        let (endpoint, notifications) = Endpoint::notifications(s2);
+        let (_client, receiver_finished) = process_start(endpoint.start(&handle));
+        let sent = client
+            .notify("time".to_owned(), Some(json!([1])))
+            .and_then(|client| client.notify("other".to_owned(), None))
+            .and_then(|client| client.notify("time".to_owned(), Some(json!([2]))))
+            // Drop the client, so the connection terminates
+            .map(|_client| ());
+        let received = notifications
+            .filter(|notification| notification.method == "time")
+            .map(|notification| notification.params.unwrap())
+            .collect()
+            .map_err(|()| panic!("The notification stream failed"))
+            .map(|times| assert_eq!(vec![json!([1]), json!([2])], times));
+        sent.join4(received, sender_finished, receiver_finished)
+    };
+    reactor.run(all).unwrap();
+}
+
+/// A server that drops the first connection that calls "kill".
+///
+/// It answers "hello" with the serial number of the connection.
+struct FlakyServer {
+    connection: usize,
+    killed: Rc<Cell<bool>>,
+}
+
+impl Server for FlakyServer {
+    type Success = Value;
+    type RpcCallResult = Box<Future<Item = Value, Error = RpcError>>;
+    type NotificationResult = Result<(), ()>;
+    fn rpc(
+        &self, ctl: &ServerCtl, method: &str, _params: &Option<Value>
+    ) -> Option<Self::RpcCallResult> {
+        match method {
+            "hello" => Some(Box::new(Ok(json!(self.connection)).into_future())),
+            "kill" if self.killed.get() => Some(Box::new(Ok(json!(true)).into_future())),
+            "kill" => {
+                self.killed.set(true);
+                ctl.kill();
+                // Never answered, the connection is gone
+                Some(Box::new(futures::future::empty()))
+            },
+            _ => None,
+        }
+    }
+}
+
+/// The client reconnects after the connection is lost and sends the lost call again.
+#[test]
+fn reconnect() {
+    let (mut reactor, _, _) = prepare();
+    let handle = reactor.handle();
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &handle).unwrap();
+    let address = listener.local_addr().unwrap();
+    let killed = Rc::new(Cell::new(false));
+    let connections = Rc::new(Cell::new(0));
+    let connections_cloned = connections.clone();
+    let handle_cloned = handle.clone();
+    let accepted = listener
+        .incoming()
+        .for_each(move |(stream, _)| {
+            connections_cloned.set(connections_cloned.get() + 1);
+            let server = FlakyServer {
+                connection: connections_cloned.get(),
+                killed: killed.clone(),
+            };
+            let (_client, finished) =
+                Endpoint::new(stream.framed(LineCodec::new()), server).start(&handle_cloned);
+            handle_cloned.spawn(finished.map_err(|e| panic!("Error: {}", e)));
+            Ok(())
+        })
+        .map_err(|e| panic!("Error: {}", e));
+    handle.spawn(accepted);
+    let handshakes = Rc::new(Cell::new(0));
+    let handshakes_cloned = handshakes.clone();
+    let client = Reconnect::new(move |handle| {
+        TcpStream::connect(&address, handle).map(|stream| stream.framed(LineCodec::new()))
+    }).backoff(Backoff::new(Duration::from_millis(10), Duration::from_millis(100)))
+        .pending(PendingPolicy::Reissue)
+        .handshake(move |client| {
+            handshakes_cloned.set(handshakes_cloned.get() + 1);
+            Ok(client)
+        })
+        .start(&handle);
+    let states = client.states().collect();
+    let client_cloned = client.clone();
+    let client_closed = client.clone();
+    let all = client
+        .call("hello".to_owned(), None, None)
+        .and_then(move |response| {
+            assert_eq!(json!(1), response.unwrap().result.unwrap());
+            client_cloned.call("kill".to_owned(), None, None)
+        })
+        .and_then(move |response| {
+            // Answered by the second connection
+            assert_eq!(json!(true), response.unwrap().result.unwrap());
+            client.call("hello".to_owned(), None, None)
+        })
+        .map(move |response| {
+            assert_eq!(json!(2), response.unwrap().result.unwrap());
+            client_closed.close();
+        })
+        .and_then(|()| states.map_err(|()| panic!("The state stream failed")));
+    let states = reactor.run(all).unwrap();
+    assert_eq!(
+        vec![
+            State::Connecting,
+            State::Connected,
+            State::Disconnected,
+            State::Connecting,
+            State::Connected,
+            State::Closed,
+        ],
+        states
+    );
+    assert_eq!(2, connections.get());
+    assert_eq!(2, handshakes.get());
+}
+
+/// The client gives up after the configured number of attempts and fails the waiting calls.
+#[test]
+fn reconnect_give_up() {
+    let (mut reactor, _, _) = prepare();
+    let handle = reactor.handle();
+    let attempts = Rc::new(Cell::new(0));
+    let attempts_cloned = attempts.clone();
+    let client = Reconnect::new(move |_handle: &Handle| -> Result<Framed<TcpStream, LineCodec>, _> {
+        attempts_cloned.set(attempts_cloned.get() + 1);
+        Err(IoError::new(ErrorKind::ConnectionRefused, "Nobody listens"))
+    }).backoff(Backoff::new(Duration::from_millis(1), Duration::from_millis(10)).attempts(3))
+        .start(&handle);
+    let states = client.states().collect();
+    let all = client
+        .call("hello".to_owned(), None, None)
+        .then(|result| {
+            assert_eq!(ErrorKind::NotConnected, result.unwrap_err().kind());
+            states.map_err(|()| panic!("The state stream failed"))
+        });
+    let states = reactor.run(all).unwrap();
+    assert_eq!(Some(&State::GaveUp), states.last());
+    assert_eq!(State::GaveUp, client.state());
+    assert_eq!(3, attempts.get());
+}
+
+/// The client stops reconnecting once the last of its clones is dropped.
+#[test]
+fn reconnect_dropped() {
+    let (mut reactor, _, _) = prepare();
+    let handle = reactor.handle();
+    let attempts = Rc::new(Cell::new(0));
+    let attempts_cloned = attempts.clone();
+    let client = Reconnect::new(move |_handle: &Handle| -> Result<Framed<TcpStream, LineCodec>, _> {
+        attempts_cloned.set(attempts_cloned.get() + 1);
+        Err(IoError::new(ErrorKind::ConnectionRefused, "Nobody listens"))
+    }).backoff(Backoff::new(Duration::from_millis(1), Duration::from_millis(1)))
+        .start(&handle);
+    let states = client.states().collect();
+    let client_cloned = client.clone();
+    drop(client);
+    // The clone keeps it going
+    reactor
+        .run(Timeout::new(Duration::from_millis(30), &handle).unwrap())
+        .unwrap();
+    assert_ne!(State::Closed, client_cloned.state());
+    assert!(attempts.get() > 1);
+    drop(client_cloned);
+    let states = reactor.run(states).unwrap();
+    assert_eq!(Some(&State::Closed), states.last());
+    // No more attempts once closed
+    let attempted = attempts.get();
+    reactor
+        .run(Timeout::new(Duration::from_millis(30), &handle).unwrap())
+        .unwrap();
+    assert_eq!(attempted, attempts.get());
+}
+
+/// The pings are answered by the other side (with an error, as it doesn't have the keepalive), so
+/// the connection lives on.
+#[test]
+fn keepalive() {
+    let (mut reactor, s1, s2) = prepare();
+    let all = {
+        // Run in a sub-block, so we drop all the clients, etc.
+        let handle = reactor.handle();
+        let (_client, server_finished) =
+            process_start(Endpoint::new(s1, NameServer).start(&handle));
+        let (client, client_endpoint_finished) = process_start(
+            Endpoint::client_only(s2)
+                .keepalive(Keepalive::new(
+                    Duration::from_millis(10),
+                    Duration::from_millis(50),
+                ))
+                .start(&handle),
+        );
+        // Give it time for several pings
+        let calls = Timeout::new(Duration::from_millis(200), &handle)
+            .unwrap()
+            .and_then(move |_| call_all(client, vec!["hello", "bye"]))
+            .map(|results| assert_eq!(vec![Ok(json!("hello")), Ok(json!("bye"))], results));
+        calls.join3(server_finished, client_endpoint_finished)
+    };
+    reactor.run(all).unwrap();
+}
+
+/// If the other side doesn't answer, the connection is killed with a distinct error.
+#[test]
+fn keepalive_unanswered() {
+    let (mut reactor, s1, s2) = prepare();
+    let handle = reactor.handle();
+    let (client, finished) = Endpoint::client_only(s2)
+        .keepalive(Keepalive::new(
+            Duration::from_millis(10),
+            Duration::from_millis(30),
+        ))
+        .start(&handle);
+    let call = client
+        .call("hello".to_owned(), None, None)
+        .and_then(|(_client, answered)| answered)
+        .then(|result| -> Result<(), IoError> {
+            // Lost together with the connection
+            result.unwrap_err();
+            Ok(())
+        });
+    let finished = finished.then(|result| {
+        let err = result.unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+        assert!(PingTimeout::is(&err));
+        Ok(())
+    });
+    reactor.run(call.join(finished)).unwrap();
+    // The other side is alive all the time, it just doesn't read anything
+    drop(s1);
+}
+
+/// Calls `rpc.ping` on an endpoint with the given server, with or without the keepalive.
+fn ping_answer<S: Server + 'static>(server: S, keepalive: bool) -> Result<Value, RpcError> {
+    // Only for the safety timeout, the connections are not used
+    let (mut reactor, _, _) = prepare();
+    let handle = reactor.handle();
+    let (left, right) = Pair::new().build();
+    let mut endpoint = Endpoint::new(left, server);
+    if keepalive {
+        // Not pinging during the test
+        let long = Duration::from_secs(60);
+        endpoint = endpoint.keepalive(Keepalive::new(long, long));
+    }
+    let (_server_client, _server_finished) = endpoint.start(&handle);
+    let (client, _client_finished) = Endpoint::client_only(right).start(&handle);
+    let client = client.allow_reserved(true);
+    let mut results = reactor.run(call_all(client, vec!["rpc.ping"])).unwrap();
+    results.remove(0)
+}
+
+/// The pings are answered only with the keepalive on and only if the server doesn't do so.
+#[test]
+fn keepalive_ping_answer() {
+    assert_eq!(Ok(json!(true)), ping_answer(ServerChain::new(Vec::new()), true));
+    assert_eq!(-32_601, ping_answer(ServerChain::new(Vec::new()), false).unwrap_err().code);
+    assert_eq!(Ok(json!("pong")), ping_answer(PingExtension, true));
+}
+
+/// The pings the other side ignores are not kept as outstanding calls forever.
+#[test]
+fn keepalive_ignored() {
+    // Only for the safety timeout, the connections are not used
+    let (mut reactor, _, _) = prepare();
+    let handle = reactor.handle();
+    let metrics = Rc::new(LogMetrics::default());
+    let (left, right) = Pair::new().build();
+    let (_client, _finished) = Endpoint::client_only(left)
+        .keepalive(Keepalive::new(
+            Duration::from_millis(10),
+            Duration::from_millis(30),
+        ))
+        .metrics(metrics.clone())
+        .start(&handle);
+    // Keep the connection alive with other traffic, never answering the pings
+    let traffic = Interval::new(Duration::from_millis(5), &handle)
+        .unwrap()
+        .take(40)
+        .map(|()| Message::notification("tick".to_owned(), None))
+        .forward(right)
+        .map(drop);
+    reactor.run(traffic).unwrap();
+    let outstanding: Vec<usize> = metrics
+        .0
+        .borrow()
+        .iter()
+        .filter(|entry| entry.starts_with("outstanding "))
+        .map(|entry| entry["outstanding ".len()..].parse().unwrap())
+        .collect();
+    // The pings were forgotten after their timeout, only the recent ones wait
+    assert!(!outstanding.is_empty());
+    assert!(outstanding.iter().all(|&count| count <= 4), "{:?}", outstanding);
+    // Each ping is counted when sent and when forgotten, so the count never jumps
+    assert_eq!(1, outstanding[0]);
+    assert!(
+        outstanding
+            .windows(2)
+            .all(|pair| pair[0] + 1 == pair[1] || pair[0] == pair[1] + 1),
+        "{:?}",
+        outstanding
+    );
+}
+
+/// Two endpoints over an in-memory connection that loses every other message.
+#[test]
+fn memory_lossy() {
+    // Only for the safety timeout, the connections are not used
+    let (mut reactor, _, _) = prepare();
+    let all = {
+        // Run in a sub-block, so we drop all the clients, etc.
+        let handle = reactor.handle();
+        let (left, right) = Pair::new()
+            .drop_every(2)
+            .latency(Duration::from_millis(10), &handle)
+            .build();
+        let (_client, server_finished) =
+            process_start(Endpoint::new(left, NameServer).start(&handle));
+        let (client, client_endpoint_finished) =
+            process_start(Endpoint::client_only(right).start(&handle));
+        let timeout = Some(Duration::from_millis(100));
+        let answers = futures::stream::iter_ok(vec!["first", "second", "third"])
+            .fold((client, Vec::new()), move |(client, mut answers), method| {
+                client
+                    .call(method.to_owned(), None, timeout)
+                    .and_then(|(client, answered)| answered.map(|answer| (client, answer)))
+                    .map(|(client, answer)| {
+                        answers.push(answer.map(|response| response.result.unwrap()));
+                        (client, answers)
+                    })
+            })
+            .map(|(_client, answers)| {
+                // The second request is lost, then the answer to the third one
+                assert_eq!(vec![Some(json!("first")), None, None], answers);
+            });
+        answers.join3(server_finished, client_endpoint_finished)
+    };
+    reactor.run(all).unwrap();
+}
+
 // TODO: Test the batches (we can't call batches now, can we?)
+
+/// A server that accepts only the raw params.
+///
+/// It sums two numbers. A notification terminates it.
+struct RawServer;
+
+impl Server for RawServer {
+    type Success = i64;
+    type RpcCallResult = Result<i64, RpcError>;
+    type NotificationResult = Result<(), ()>;
+    fn rpc(&self, _: &ServerCtl, _: &str, _: &Option<Value>) -> Option<Self::RpcCallResult> {
+        panic!("The params should have been left raw");
+    }
+    fn rpc_raw(
+        &self, _ctl: &ServerCtl, method: &str, params: &Option<RawParams>
+    ) -> Option<Self::RpcCallResult> {
+        match method {
+            "sum" => {
+                let (a, b) = jsonrpc_params!(params, raw (i64, i64));
+                Some(Ok(a + b))
+            },
+            _ => None,
+        }
+    }
+    fn notification_raw(
+        &self, ctl: &ServerCtl, method: &str, params: &Option<RawParams>
+    ) -> Option<Self::NotificationResult> {
+        assert_eq!("done", method);
+        assert_eq!("{\"ok\":true}", params.as_ref().unwrap().get());
+        ctl.terminate();
+        Some(Ok(()))
+    }
+}
+
+/// The raw params get to the server through the `RawLine` codec.
+#[test]
+fn raw_params() {
+    let (mut reactor, _, _) = prepare();
+    let (s1, s2) = tcp_pair(&mut reactor);
+    let all = {
+        // Run in a sub-block, so we drop all the clients, etc.
+        let handle = reactor.handle();
+        let s1 = s1.framed(RawLine::new());
+        let s2 = s2.framed(LineCodec::new());
+        let (_client, server_finished) =
+            process_start(Endpoint::new(s1, RawServer).start(&handle));
+        let (client, client_endpoint_finished) =
+            process_start(Endpoint::client_only(s2).start(&handle));
+        let sum = client
+            .call("sum".to_owned(), Some(json!([1, 2])), None)
+            .and_then(|(client, answered)| answered.map(|response| (client, response)));
+        let wrong = sum.and_then(|(client, response)| {
+            assert_eq!(json!(3), response.unwrap().result.unwrap());
+            client
+                .call("sum".to_owned(), Some(json!(["1", 2])), None)
+                .and_then(|(client, answered)| answered.map(|response| (client, response)))
+        });
+        let done = wrong.and_then(|(client, response)| {
+            assert_eq!(-32_602, response.unwrap().result.unwrap_err().code);
+            client
+                .notify("done".to_owned(), Some(json!({"ok": true})))
+                .map(drop)
+        });
+        done.join3(server_finished, client_endpoint_finished)
+    };
+    reactor.run(all).unwrap();
+}
+
+/// A broken request with a valid ID gets its error answered with that ID.
+#[test]
+fn broken_id() {
+    let (mut reactor, _, _) = prepare();
+    let (s1, s2) = tcp_pair(&mut reactor);
+    let handle = reactor.handle();
+    let s1 = s1.framed(LineCodec::new());
+    let (_client, _finished) = process_start(Endpoint::new(s1, AnswerServer).start(&handle));
+    let request = b"{\"jsonrpc\": \"2.0\", \"method\": 42, \"id\": 7}\n";
+    let answer = write_all(s2, &request[..])
+        .and_then(|(s2, _)| s2.framed(LineCodec::new()).into_future().map_err(|(e, _s2)| e))
+        .map(|(answer, _s2)| answer.unwrap().unwrap());
+    match reactor.run(answer).unwrap() {
+        Message::Response(response) => {
+            assert_eq!(-32_600, response.result.unwrap_err().code);
+            assert_eq!(json!(7), response.id);
+        },
+        other => panic!("Not a response: {:?}", other),
+    }
+}
+
+/// Two endpoints talking JSON-RPC 1.0 to each other.
+#[test]
+fn version_v1() {
+    // Only for the safety timeout, the connections are not used
+    let (mut reactor, _, _) = prepare();
+    let all = {
+        // Run in a sub-block, so we drop all the clients, etc.
+        let handle = reactor.handle();
+        let (left, right) = Pair::new().build();
+        let (_client, server_finished) = process_start(
+            Endpoint::new(left, NameServer)
+                .version(Version::V1)
+                .start(&handle),
+        );
+        let (client, client_endpoint_finished) = process_start(
+            Endpoint::client_only(right)
+                .version(Version::V1)
+                .start(&handle),
+        );
+        call_all(client, vec!["hello", "bye"])
+            .map(|results| assert_eq!(vec![Ok(json!("hello")), Ok(json!("bye"))], results))
+            .join3(server_finished, client_endpoint_finished)
+    };
+    reactor.run(all).unwrap();
+}
+
+/// The responses nobody waits for are provided by the client. A parse error reported by the other
+/// side fails the outstanding calls.
+#[test]
+fn unsolicited() {
+    // Only for the safety timeout, the connections are not used
+    let (mut reactor, _, _) = prepare();
+    let all = {
+        // Run in a sub-block, so we drop all the clients, etc.
+        let handle = reactor.handle();
+        let (left, right) = Pair::new().build();
+        let (client, finished) = process_start(
+            Endpoint::client_only(left)
+                .fail_on_parse_error(true)
+                .start(&handle),
+        );
+        let unsolicited = client
+            .unsolicited()
+            .collect()
+            .map_err(|()| panic!("The stream failed"))
+            .map(|responses| {
+                assert_eq!(2, responses.len());
+                assert_eq!(-32_700, responses[0].result.as_ref().unwrap_err().code);
+                assert!(responses[0].id.is_null());
+                assert_eq!(json!("unknown"), responses[1].id);
+            });
+        let calls = client
+            .call("first".to_owned(), None, None)
+            .and_then(|(client, first)| {
+                client
+                    .call("second".to_owned(), None, None)
+                    .and_then(|(_client, second)| first.join(second))
+            })
+            .map(|(first, second)| {
+                for response in &[first, second] {
+                    let response = response.as_ref().unwrap();
+                    assert_eq!(-32_700, response.result.as_ref().unwrap_err().code);
+                    assert!(response.id.is_string());
+                }
+            });
+        let (sink, stream) = right.split();
+        let peer = stream
+            .take(2)
+            .collect()
+            .and_then(move |requests| {
+                assert_eq!(2, requests.len());
+                let answers = vec![
+                    Message::error(RpcError::parse_error("Broken".to_owned())),
+                    from_str(r#"{"jsonrpc": "2.0", "result": 42, "id": "unknown"}"#).unwrap(),
+                ];
+                sink.send_all(iter_ok::<_, IoError>(answers))
+            })
+            .map(drop);
+        calls.join4(peer, unsolicited, finished)
+    };
+    reactor.run(all).unwrap();
+}
+
+/// A server counting how many calls run at once
+///
+/// It waits for as many milliseconds as provided in the first parameter and returns that number.
+struct DelayServer {
+    handle: Handle,
+    running: Rc<Cell<usize>>,
+    max_running: Rc<Cell<usize>>,
+}
+
+impl Server for DelayServer {
+    type Success = u64;
+    type RpcCallResult = Box<Future<Item = u64, Error = RpcError>>;
+    type NotificationResult = Result<(), ()>;
+    fn rpc(
+        &self, _ctl: &ServerCtl, _method: &str, params: &Option<Value>
+    ) -> Option<Self::RpcCallResult> {
+        let params: Vec<u64> = from_value(params.as_ref().unwrap().clone()).unwrap();
+        self.running.set(self.running.get() + 1);
+        self.max_running
+            .set(self.max_running.get().max(self.running.get()));
+        let running = self.running.clone();
+        let delay = Timeout::new(Duration::from_millis(params[0]), &self.handle)
+            .unwrap()
+            .map(move |_| {
+                running.set(running.get() - 1);
+                params[0]
+            })
+            .map_err(|e| RpcError::server_error(Some(format!("{}", e))));
+        Some(Box::new(delay))
+    }
+}
+
+/// Sends a batch of calls with the given delays and returns the answer and the maximum number of
+/// calls that run at once.
+fn run_batch(policy: BatchPolicy, delays: &[u64]) -> (Message, usize) {
+    let calls: Vec<_> = delays.iter().map(|&delay| ("delay", delay)).collect();
+    run_calls(|endpoint| endpoint.batch_policy(policy), &calls)
+}
+
+/// Sends a batch of calls of the given methods with the given delays to an endpoint configured by
+/// the closure.
+fn run_calls<F>(configure: F, calls: &[(&str, u64)]) -> (Message, usize)
+where
+    F: FnOnce(Endpoint<Memory, DelayServer>) -> Endpoint<Memory, DelayServer>,
+{
+    // Only for the safety timeout, the connections are not used
+    let (mut reactor, _, _) = prepare();
+    let handle = reactor.handle();
+    let max_running = Rc::new(Cell::new(0));
+    let server = DelayServer {
+        handle: handle.clone(),
+        running: Rc::new(Cell::new(0)),
+        max_running: max_running.clone(),
+    };
+    let (left, right) = Pair::new().build();
+    // Plenty of parallelism, so only the tested limits apply
+    let endpoint = Endpoint::new(left, server).parallel(16);
+    let (_client, _finished) = configure(endpoint).start(&handle);
+    let batch = calls
+        .iter()
+        .enumerate()
+        .map(|(idx, &(method, delay))| {
+            let request = json!({"jsonrpc": "2.0", "method": method, "params": [delay], "id": idx});
+            from_str(&request.to_string()).unwrap()
+        })
+        .collect();
+    let answer = right
+        .send(Message::Batch(batch))
+        .and_then(|right| right.into_future().map_err(|(e, _right)| e))
+        .map(|(answer, _right)| answer.unwrap().unwrap());
+    (reactor.run(answer).unwrap(), max_running.get())
+}
+
+/// The error codes of the responses in a batch answer.
+fn batch_codes(answer: Message) -> Vec<Option<i64>> {
+    match answer {
+        Message::Batch(responses) => responses
+            .into_iter()
+            .map(|response| match response {
+                Message::Response(response) => response.result.err().map(|e| e.code),
+                other => panic!("Not a response: {:?}", other),
+            })
+            .collect(),
+        other => panic!("Not a batch: {:?}", other),
+    }
+}
+
+/// The IDs of the responses in a batch answer.
+fn batch_ids(answer: Message) -> Vec<Value> {
+    match answer {
+        Message::Batch(responses) => responses
+            .into_iter()
+            .map(|response| match response {
+                Message::Response(response) => response.id,
+                other => panic!("Not a response: {:?}", other),
+            })
+            .collect(),
+        other => panic!("Not a batch: {:?}", other),
+    }
+}
+
+/// The batches are processed according to the policy.
+#[test]
+fn batch_policy() {
+    // By default, everything the endpoint allows runs at once and is answered in the order of
+    // completion
+    let (answer, max_running) = run_batch(BatchPolicy::new(), &[60, 0, 30]);
+    assert_eq!(3, max_running);
+    assert_eq!(vec![json!(1), json!(2), json!(0)], batch_ids(answer));
+    // Limited concurrency, but still answered in the order of completion
+    let (answer, max_running) = run_batch(BatchPolicy::new().concurrency(2), &[60, 30, 0]);
+    assert_eq!(2, max_running);
+    assert_eq!(vec![json!(1), json!(2), json!(0)], batch_ids(answer));
+    // Ordered answers
+    let policy = BatchPolicy::new().concurrency(2).ordered(true);
+    let (answer, max_running) = run_batch(policy, &[60, 30, 0]);
+    assert_eq!(2, max_running);
+    assert_eq!(vec![json!(0), json!(1), json!(2)], batch_ids(answer));
+    // One by one
+    let (answer, max_running) = run_batch(BatchPolicy::new().sequential(), &[30, 0, 10]);
+    assert_eq!(1, max_running);
+    assert_eq!(vec![json!(0), json!(1), json!(2)], batch_ids(answer));
+    // Too long batches are rejected as a whole
+    let (answer, max_running) = run_batch(BatchPolicy::new().max_len(2), &[0, 0, 0]);
+    assert_eq!(0, max_running);
+    match answer {
+        Message::Response(response) => {
+            assert_eq!(-32_600, response.result.unwrap_err().code);
+            assert!(response.id.is_null());
+        },
+        other => panic!("Not a response: {:?}", other),
+    }
+    let (answer, _) = run_batch(BatchPolicy::new().max_len(2), &[0, 0]);
+    assert_eq!(2, batch_ids(answer).len());
+    // Without a policy, the parallelism of the endpoint applies to the entries
+    let calls = [("delay", 30), ("delay", 0), ("delay", 10)];
+    let (answer, max_running) = run_calls(|endpoint| endpoint.parallel(2), &calls);
+    assert_eq!(2, max_running);
+    assert_eq!(3, batch_ids(answer).len());
+}
+
+/// The calls wait for their limits, or are rejected if configured so.
+#[test]
+fn limits() {
+    // Only one slow call at a time, the fast ones are not blocked by the waiting one
+    let limits = Limits::new().method("slow", 1);
+    let calls = [("slow", 30), ("slow", 30), ("fast", 0), ("fast", 0)];
+    let (answer, max_running) = run_calls(|endpoint| endpoint.limits(limits), &calls);
+    assert_eq!(3, max_running);
+    assert_eq!(vec![json!(2), json!(3), json!(0), json!(1)], batch_ids(answer));
+    // The global limit caps everything
+    let limits = Limits::new().global(2);
+    let calls = [("slow", 30), ("slow", 30), ("fast", 0), ("fast", 0)];
+    let (answer, max_running) = run_calls(|endpoint| endpoint.limits(limits), &calls);
+    assert_eq!(2, max_running);
+    assert_eq!(4, batch_ids(answer).len());
+    // The call over the limit is answered with the error right away
+    let busy = RpcError::new(-32_000, "Server busy".to_owned(), None);
+    let limits = Limits::new().method("slow", 1).reject(busy);
+    let calls = [("slow", 30), ("slow", 0), ("fast", 0)];
+    let policy = BatchPolicy::new().ordered(true);
+    let (answer, max_running) =
+        run_calls(|endpoint| endpoint.batch_policy(policy).limits(limits), &calls);
+    assert_eq!(2, max_running);
+    assert_eq!(vec![None, Some(-32_000), None], batch_codes(answer));
+}
+
+/// The calls coming too often are answered with the error without reaching the server.
+#[test]
+fn rate_limits() {
+    let minute = Duration::from_secs(60);
+    let policy = BatchPolicy::new().ordered(true);
+    // The connection allows only two calls
+    let limits = RateLimits::new().connection(Rate::new(2, minute));
+    let calls = [("fast", 0), ("fast", 0), ("fast", 0)];
+    let (answer, max_running) =
+        run_calls(|endpoint| endpoint.batch_policy(policy).rate_limits(limits), &calls);
+    assert_eq!(2, max_running);
+    assert_eq!(vec![None, None, Some(RATE_LIMITED)], batch_codes(answer));
+    // Only one slow call, with a custom error
+    let slow_down = RpcError::new(-32_000, "Slow down".to_owned(), None);
+    let limits = RateLimits::new()
+        .method("slow", Rate::new(1, minute))
+        .reject(slow_down);
+    let calls = [("slow", 0), ("slow", 0), ("fast", 0)];
+    let (answer, _) =
+        run_calls(|endpoint| endpoint.batch_policy(policy).rate_limits(limits), &calls);
+    assert_eq!(vec![None, Some(-32_000), None], batch_codes(answer));
+}
+
+/// A server logging in anyone and telling who they are.
+struct LoginServer;
+
+impl Server for LoginServer {
+    type Success = Value;
+    type RpcCallResult = Result<Value, RpcError>;
+    type NotificationResult = Result<(), ()>;
+    fn rpc(
+        &self, ctl: &ServerCtl, method: &str, params: &Option<Value>
+    ) -> Option<Self::RpcCallResult> {
+        match method {
+            "login" => {
+                ctl.authenticate(params.as_ref().unwrap()[0].clone());
+                Some(Ok(Value::Bool(true)))
+            },
+            "whoami" => Some(Ok(ctl.identity().unwrap_or(Value::Null))),
+            _ => None,
+        }
+    }
+}
+
+/// Nothing but the login is handled before authentication, the identity is kept afterwards.
+#[test]
+fn auth_gate() {
+    // Only for the safety timeout, the connections are not used
+    let (mut reactor, _, _) = prepare();
+    let handle = reactor.handle();
+    let (left, right) = Pair::new().build();
+    let (_server_client, _server_finished) = Endpoint::new(left, LoginServer)
+        .extension(Box::new(AbstractServer::new(PingExtension)))
+        .auth_gate(AuthGate::new("login"))
+        .start(&handle);
+    let (client, _client_finished) = Endpoint::client_only(right).start(&handle);
+    let client = client.allow_reserved(true);
+    let before = call_all(client.clone(), vec!["whoami", "rpc.ping"]);
+    let before = reactor.run(before).unwrap();
+    let codes: Vec<_> = before.into_iter().map(|r| r.unwrap_err().code).collect();
+    assert_eq!(vec![UNAUTHORIZED, UNAUTHORIZED], codes);
+    let login = client
+        .call("login".to_owned(), Some(json!(["alice"])), None)
+        .and_then(|(client, answered)| answered.map(|response| (client, response)));
+    let (client, response) = reactor.run(login).unwrap();
+    assert_eq!(Ok(json!(true)), response.unwrap().result);
+    let after = reactor.run(call_all(client, vec!["whoami", "rpc.ping"])).unwrap();
+    assert_eq!(vec![Ok(json!("alice")), Ok(json!("pong"))], after);
+}